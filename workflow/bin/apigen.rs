@@ -0,0 +1,57 @@
+use clap::Parser;
+use log::info;
+use std::path::PathBuf;
+use workflow::apispec::write_api_specs;
+use workflow::conf::{load_library, root_library_path, FileFormat};
+
+use simplelog::*;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about = "Write the AsyncAPI and OpenAPI documents of the modules", long_about = None)]
+struct Args {
+    /// Path to the library folder
+    #[arg(short, long)]
+    library_path: Option<PathBuf>,
+
+    /// Directory to write the documents to, `api` in the library by default
+    #[arg(short, long)]
+    output_dir: Option<PathBuf>,
+
+    /// Only write the documents of these modules, all of them by default
+    #[arg(short, long)]
+    module: Vec<String>,
+
+    /// Format of the documents, e.g. `yaml` or `json`
+    #[arg(short, long, default_value = "yaml")]
+    format: FileFormat,
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Initialise the logger
+    CombinedLogger::init(vec![TermLogger::new(
+        LevelFilter::Info,
+        Config::default(),
+        TerminalMode::Mixed,
+        ColorChoice::Auto,
+    )])
+    .unwrap();
+
+    let args = Args::parse();
+    let library_path = match args.library_path {
+        Some(path) => path,
+        None => root_library_path()?,
+    };
+    let output_dir = args.output_dir.unwrap_or(library_path.join("api"));
+
+    info!("Starting apigen");
+    let library = load_library(&library_path)?;
+    let written = write_api_specs(&library.modules, &args.module, &output_dir, args.format)?;
+    info!(
+        "{} documents written to {}",
+        written.len(),
+        output_dir.display()
+    );
+    info!("Finished apigen");
+
+    Ok(())
+}
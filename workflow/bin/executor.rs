@@ -1,83 +1,335 @@
-use clap::Parser;
-use log::{debug, info, trace};
+use clap::{ArgGroup, Args, Parser, Subcommand};
+use log::{debug, error, info, trace};
 use simplelog::*;
-use std::error::Error;
+use std::cmp::Reverse;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 use workflow::conf::{
-    get_tree_by_name, load_library, root_library_path, BehaviorTreeFile, Library, ModuleFile, Node,
+    get_tree_by_name, get_workflow_by_title, load_library, root_library_path, Library,
 };
-use workflow::Sequence;
+use workflow::estimate::{Estimator, NodeEstimate};
+use workflow::events::run_log_path;
+use workflow::executor::{Control, Executor, RunReport, StepReport, StepStatus};
+use workflow::journal::Journal;
+use workflow::profile::Profile;
+use workflow::reply::ModuleRequest;
+use workflow::scheduler::{RunRequest, Scheduler};
+use workflow::Value;
 
-use async_recursion::async_recursion;
-use async_std::channel::{bounded, Receiver, Sender};
-use async_std::prelude::*;
+use async_std::channel::{bounded, Receiver};
 use async_std::task;
+use futures::future::BoxFuture;
 use std::io::{stdin, BufRead};
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 use std::thread;
-use std::time::{Duration, Instant};
+use std::time::Duration;
+
+const DEFAULT_JOURNAL: &str = "/tmp/tcr/executor_journal.json";
+const DEFAULT_RUN_LOGS: &str = "/tmp/tcr/runs";
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
-struct Args {
+struct Cli {
+    /// Path to the library folder
+    #[arg(short, long, global = true)]
+    library_path: Option<String>,
+
+    /// Directory to write the JSON lines log of every run to
+    #[arg(long, global = true, default_value = DEFAULT_RUN_LOGS)]
+    log_dir: PathBuf,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Run a workflow or a single tree
+    Run(RunArgs),
+    /// Continue a run that was interrupted, from its journal
+    Resume {
+        /// Path to the journal of the run
+        #[arg(short, long, default_value = DEFAULT_JOURNAL)]
+        journal: PathBuf,
+    },
+    /// Run several workflows at once, sharing the modules between them
+    Schedule {
+        /// Workflow to run, as `name` or `name:priority`. Repeat for every sample
+        #[arg(short, long = "run", required = true)]
+        runs: Vec<String>,
+
+        /// Only print the predicted start and finish times
+        #[arg(long)]
+        plan: bool,
+    },
+    /// Show where the time goes, from the logs of past runs
+    Profile {
+        /// Only profile the runs of this workflow and print its critical path
+        #[arg(short, long)]
+        workflow: Option<String>,
+
+        /// Number of slowest nodes to print
+        #[arg(short = 'n', long, default_value_t = 10)]
+        top: usize,
+    },
+    /// Predict how long a workflow takes and what it uses up, without running anything
+    Estimate {
+        /// The name of the workflow to estimate
+        #[arg(short, long)]
+        workflow: String,
+
+        /// Only use the durations declared in the nodes file, not the logs of past runs
+        #[arg(long)]
+        no_history: bool,
+    },
+}
+
+#[derive(Args, Debug)]
+#[clap(group = ArgGroup::new("input").required(true).multiple(false))]
+struct RunArgs {
     /// The name of the workflow to run
-    #[arg(short, long, default_value = "tb_pcr")]
-    // workflow_name: Option<String>,
-    workflow_name: String,
+    #[arg(short, long, group = "input")]
+    workflow: Option<String>,
 
     /// The name of the tree to run
-    #[arg(short, long, default_value = "get_tip")]
-    // tree_name: Option<String>,
-    tree_name: String,
+    #[arg(short, long, group = "input")]
+    tree: Option<String>,
+
+    /// Tree to run if the run is aborted, overrides the cleanup of the workflow
+    #[arg(short, long)]
+    cleanup: Option<String>,
+
+    /// Where to save the journal of the run
+    #[arg(short, long, default_value = DEFAULT_JOURNAL)]
+    journal: PathBuf,
 }
 
-async fn module_task(receiver: Receiver<Node>, modules: &ModuleFile) {
-    while let Ok(node) = receiver.recv().await {
-        execute_leaf_node(&node, modules).await;
+/// Stand-in for the zenoh session until the modules are reachable from here
+fn simulated_transport(request: ModuleRequest) -> BoxFuture<'static, Result<Vec<Value>, String>> {
+    Box::pin(async move {
+        trace!("{} <- {}", request.module, request.service);
+        async_std::task::sleep(Duration::from_millis(200)).await;
+        Ok(Vec::new())
+    })
+}
+
+/// Read operator commands from stdin: pause (p), resume (r), step (s) and abort (a)
+fn read_commands() -> Receiver<Control> {
+    let (sender, receiver) = bounded(8);
+    thread::spawn(move || {
+        for line in stdin().lock().lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => break,
+            };
+            match line.parse::<Control>() {
+                Ok(command) => {
+                    if task::block_on(sender.send(command)).is_err() {
+                        break;
+                    }
+                }
+                Err(e) => error!("{} (use pause, resume, step or abort)", e),
+            }
+        }
+    });
+    receiver
+}
+
+fn print_step(label: &str, step: &StepReport) {
+    let status = match &step.status {
+        StepStatus::Success => "✅ ok",
+        StepStatus::Failure(_) => "❌ failed",
+        StepStatus::Aborted => "⛔ aborted",
+        StepStatus::Skipped => "⏭ skipped",
+    };
+    println!(
+        "{:>4}  {:<32} {:<10} {:>9.1}s",
+        label,
+        step.name,
+        status,
+        step.elapsed.as_secs_f64()
+    );
+    if let StepStatus::Failure(reason) = &step.status {
+        println!("      └─ {}", reason);
     }
 }
 
-async fn execute_leaf_node(node: &Node, modules: &ModuleFile) -> Result<(), Box<dyn Error>> {
-    info!("Executing leaf node: {}", node.name);
-    async_std::task::sleep(std::time::Duration::from_millis(200)).await;
+/// Parse `name` or `name:priority` into the request for the `index`th run
+fn parse_run(index: usize, run: &str) -> Result<RunRequest, Box<dyn std::error::Error>> {
+    let (workflow, priority) = match run.rsplit_once(':') {
+        Some((workflow, priority)) => (
+            workflow,
+            priority
+                .parse()
+                .map_err(|_| format!("Invalid priority in {}", run))?,
+        ),
+        None => (run, 0),
+    };
+    Ok(RunRequest {
+        id: format!("{}#{}", workflow, index + 1),
+        workflow: workflow.to_string(),
+        priority,
+    })
+}
+
+fn print_plan(scheduler: &Scheduler) -> Result<(), Box<dyn std::error::Error>> {
+    println!(
+        "\n{:<16} {:>4}  {:<32} {:>9} {:>9}  modules",
+        "run", "#", "tree", "start", "finish"
+    );
+    for step in scheduler.plan()? {
+        println!(
+            "{:<16} {:>4}  {:<32} {:>8.1}s {:>8.1}s  {}",
+            step.run,
+            step.step_index + 1,
+            step.tree,
+            step.start.as_secs_f64(),
+            step.finish.as_secs_f64(),
+            step.modules.join(", ")
+        );
+    }
     Ok(())
 }
 
-#[async_recursion]
-pub async fn execute_node(node: &Node, library: &Library) -> Result<(), Box<dyn Error>> {
-    trace!("Executing node: '{}'", node.name);
-    // TODO: Implement the actual node execution logic here
-    // Recursively execute child nodes
-    if let Some(nodes) = &node.sequence {
-        match nodes {
-            Sequence::Fallback(fallback_nodes) => {
-                for node in fallback_nodes {
-                    execute_node(node, library).await?;
-                }
-            }
-            Sequence::Children(children_nodes) => {
-                for node in children_nodes {
-                    execute_node(node, library).await?;
-                }
-            }
+/// Bar chart of the histogram counts, one character per bucket
+fn sparkline(counts: &[usize]) -> String {
+    const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+    let highest = counts.iter().copied().max().unwrap_or(0).max(1);
+    counts
+        .iter()
+        .map(|c| match c {
+            0 => ' ',
+            c => BARS[(c * (BARS.len() - 1)) / highest],
+        })
+        .collect()
+}
+
+fn print_profile(
+    library: &Library,
+    log_dir: &Path,
+    workflow: Option<String>,
+    top: usize,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let workflow = match workflow {
+        Some(title) => Some(get_workflow_by_title(&title, library)?),
+        None => None,
+    };
+    let profile = Profile::load_dir(log_dir, workflow.map(|w| w.title.as_str()))?;
+    if profile.runs == 0 {
+        return Err(format!("No run logs found in {}", log_dir.display()).into());
+    }
+    println!("\n{} run(s) from {}", profile.runs, log_dir.display());
+
+    println!(
+        "\n{:<48} {:>5} {:>5} {:>8} {:>8} {:>8} {:>9}  histogram",
+        "slowest nodes", "execs", "fail", "mean", "p90", "max", "total"
+    );
+    for (path, stats) in profile.slowest(library).into_iter().take(top) {
+        println!(
+            "{:<48} {:>5} {:>5} {:>7.1}s {:>7.1}s {:>7.1}s {:>8.1}s  {}",
+            path,
+            stats.count(),
+            stats.failures,
+            stats.mean().as_secs_f64(),
+            stats.percentile(90.0).as_secs_f64(),
+            stats.max().as_secs_f64(),
+            stats.total().as_secs_f64(),
+            sparkline(&stats.histogram(10))
+        );
+    }
+
+    let mut modules = profile.module_time(library).into_iter().collect::<Vec<_>>();
+    modules.sort_by_key(|(_, total)| Reverse(*total));
+    println!("\n{:<32} {:>9} {:>9}", "module", "total", "per run");
+    for (module, total) in modules {
+        println!(
+            "{:<32} {:>8.1}s {:>8.1}s",
+            module,
+            total.as_secs_f64(),
+            total.as_secs_f64() / profile.runs as f64
+        );
+    }
+
+    if let Some(workflow) = workflow {
+        let critical = profile.critical_path(workflow, library)?;
+        println!("\ncritical path of {}", workflow.title);
+        for (path, mean) in &critical {
+            println!("  {:<46} {:>8.1}s", path, mean.as_secs_f64());
         }
-    } else {
-        trace!("Executing leaf node: {}", node.name);
-        // async_std::task::block_on(execute_leaf_node(node, &library.modules))?;
-        execute_leaf_node(node, &library.modules).await?;
+        let total: Duration = critical.iter().map(|(_, mean)| *mean).sum();
+        println!("  {:<46} {:>8.1}s", "total", total.as_secs_f64());
     }
     Ok(())
 }
 
-async fn execute_tree(tree: &BehaviorTreeFile, library: &Library) -> Result<(), Box<dyn Error>> {
-    info!(
-        "Starting execution of a tree: '{}' ({})",
-        tree.title, tree.description
+fn print_estimate(
+    library: &Library,
+    log_dir: &Path,
+    workflow: &str,
+    no_history: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let workflow = get_workflow_by_title(workflow, library)?;
+    let history = if no_history || !log_dir.exists() {
+        Profile::default()
+    } else {
+        Profile::load_dir(log_dir, Some(&workflow.title))?
+    };
+    info!("Estimating from {} past run(s)", history.runs);
+    let steps = Estimator::new(library, history).workflow(workflow)?;
+
+    println!("\n{}", workflow.title);
+    println!(
+        "{:>4}  {:<32} {:>9} {:>9} {:>9}",
+        "#", "tree", "min", "expected", "max"
+    );
+    let mut total = NodeEstimate::default();
+    for (index, (name, estimate)) in steps.iter().enumerate() {
+        println!(
+            "{:>4}  {:<32} {:>8.1}s {:>8.1}s {:>8.1}s",
+            index + 1,
+            name,
+            estimate.seconds.min,
+            estimate.seconds.expected,
+            estimate.seconds.max
+        );
+        total.then(estimate);
+    }
+    println!(
+        "{:>4}  {:<32} {:>8.1}s {:>8.1}s {:>8.1}s",
+        "", "total", total.seconds.min, total.seconds.expected, total.seconds.max
     );
-    execute_node(&tree.tree, library).await
+    if !total.consumables.is_empty() {
+        println!(
+            "\n{:<38} {:>9} {:>9} {:>9}",
+            "consumable", "min", "expected", "max"
+        );
+        for (name, amount) in &total.consumables {
+            println!(
+                "{:<38} {:>9.1} {:>9.1} {:>9.1}",
+                name, amount.min, amount.expected, amount.max
+            );
+        }
+    }
+    Ok(())
+}
+
+fn print_summary(report: &RunReport) {
+    println!("\n{}", report.title);
+    println!("{:>4}  {:<32} {:<10} {:>10}", "#", "tree", "status", "time");
+    for step in &report.steps {
+        print_step(&(step.index + 1).to_string(), step);
+    }
+    if let Some(cleanup) = &report.cleanup {
+        print_step("↺", cleanup);
+    }
+    for (key, value) in &report.blackboard {
+        println!("      {} = {:?}", key, value);
+    }
+    println!("Total: {:.1}s", report.elapsed.as_secs_f64());
 }
 
 #[async_std::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
+async fn main() -> Result<ExitCode, Box<dyn std::error::Error>> {
     // Initialise the logger
     CombinedLogger::init(vec![TermLogger::new(
         LevelFilter::Info,
@@ -87,18 +339,84 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     )])
     .unwrap();
 
-    let args = Args::parse();
+    let args = Cli::parse();
     debug!("{:?}", &args);
 
     // Load the library
-    let library_path = root_library_path().expect("Unable to find library path");
+    let library_path = match args.library_path {
+        Some(path) => PathBuf::new().join(path),
+        None => root_library_path().expect("Unable to find library path"),
+    };
     let library = load_library(&library_path).expect("Failed to load library");
 
-    // let out = get_workflow_by_title(&args.workflow_name, &library).expect("Failed to get workflow");
-    // trace!("{:?}", out);
-
-    let tree = get_tree_by_name(&args.tree_name, &library).expect("Failed to get tree");
-    // execute_tree(&tree, &library).await
+    let reports = match args.command {
+        Command::Profile { workflow, top } => {
+            print_profile(&library, &args.log_dir, workflow, top)?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        Command::Estimate {
+            workflow,
+            no_history,
+        } => {
+            print_estimate(&library, &args.log_dir, &workflow, no_history)?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        Command::Run(run) => {
+            let mut executor = Executor::new(&library, Arc::new(simulated_transport))
+                .with_control(read_commands())
+                .with_journal(run.journal);
+            if let Some(workflow_name) = run.workflow {
+                let workflow = get_workflow_by_title(&workflow_name, &library)?;
+                executor = executor.with_run_log(&run_log_path(&args.log_dir, &workflow.title))?;
+                executor.parameters = workflow.parameters.clone();
+                let report = match run.cleanup {
+                    Some(cleanup) => {
+                        let mut workflow = workflow.clone();
+                        workflow.cleanup = Some(cleanup);
+                        executor.execute_workflow(&workflow).await
+                    }
+                    None => executor.execute_workflow(workflow).await,
+                };
+                vec![report]
+            } else {
+                let tree_name = run.tree.expect("Either a workflow or a tree is required");
+                let tree = get_tree_by_name(&tree_name, &library)?;
+                executor = executor.with_run_log(&run_log_path(&args.log_dir, &tree.title))?;
+                vec![executor.run_tree(tree, run.cleanup.as_deref()).await]
+            }
+        }
+        Command::Resume { journal } => {
+            let journal_file = Journal::load(&journal)?;
+            let log_path = run_log_path(&args.log_dir, &journal_file.title);
+            let executor = Executor::new(&library, Arc::new(simulated_transport))
+                .with_control(read_commands())
+                .with_run_log(&log_path)?
+                .resume(journal_file, journal)?;
+            vec![executor.execute_journal().await?]
+        }
+        Command::Schedule { runs, plan } => {
+            let mut scheduler = Scheduler::new(&library);
+            scheduler.log_dir = Some(args.log_dir);
+            for (index, run) in runs.iter().enumerate() {
+                scheduler.submit(parse_run(index, run)?)?;
+            }
+            print_plan(&scheduler)?;
+            if plan {
+                return Ok(ExitCode::SUCCESS);
+            }
+            scheduler.execute(Arc::new(simulated_transport)).await?
+        }
+    };
 
-    Ok(())
+    let mut exit_code = ExitCode::SUCCESS;
+    for report in &reports {
+        print_summary(report);
+        if report.succeeded() {
+            info!("{} completed", report.title);
+        } else {
+            error!("{} failed", report.title);
+            exit_code = ExitCode::FAILURE;
+        }
+    }
+    Ok(exit_code)
 }
@@ -1,8 +1,13 @@
 use clap::Parser;
 use std::path::PathBuf;
-use workflow::conf::{get_tree_by_name, get_workflow_by_title, load_library, root_library_path};
+use workflow::analysis::analyse_library;
+use workflow::btcpp::{tree_to_xml, xml_to_tree};
+use workflow::conf::{
+    convert_library, error_catalogue, get_tree_by_name, get_workflow_by_title, load_library,
+    root_library_path, tree_file_path, FileFormat,
+};
 
-use log::{debug, trace};
+use log::{debug, info, trace};
 use simplelog::*;
 
 #[derive(Parser, Debug)]
@@ -21,6 +26,34 @@ struct Args {
     #[arg(short, long, default_value = "get_tip")]
     // tree_name: Option<String>,
     tree_name: String,
+
+    /// Fail if the analyser finds dead branches or contradictions in any tree
+    #[arg(long)]
+    strict: bool,
+
+    /// Print the error catalogue as a markdown table
+    #[arg(long)]
+    errors: bool,
+
+    /// Write the library in another format, `yaml`, `json` or `toml`, to `--output-dir`
+    #[arg(long, requires = "output_dir")]
+    convert: Option<FileFormat>,
+
+    /// Write the tree as BehaviorTree.CPP v4 XML for Groot2, to `--output-dir` or stdout
+    #[arg(long)]
+    export_xml: Option<String>,
+
+    /// Add a BehaviorTree.CPP v4 XML file to the trees of the library, or to `--output-dir`
+    #[arg(long, conflicts_with = "export_xml")]
+    import_xml: Option<PathBuf>,
+
+    /// Replace the file of a tree with the same name when importing
+    #[arg(long, requires = "import_xml")]
+    overwrite: bool,
+
+    /// Directory to write the converted library, the XML or the imported tree to
+    #[arg(long)]
+    output_dir: Option<PathBuf>,
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -43,10 +76,89 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     };
     let library = load_library(&library_path).expect("Failed to load library");
 
+    if let (Some(format), Some(output_dir)) = (args.convert, &args.output_dir) {
+        let converted = convert_library(&library_path, output_dir, format)?;
+        info!("{} files written to {:?}", converted.len(), output_dir);
+        return Ok(());
+    }
+
+    if let Some(tree_name) = &args.export_xml {
+        let tree = get_tree_by_name(tree_name, &library)?;
+        let xml = tree_to_xml(tree, &library)?;
+        match &args.output_dir {
+            Some(output_dir) => {
+                let path = output_dir.join(format!("{}.xml", tree_name));
+                std::fs::write(&path, xml)?;
+                info!("Tree {} written to {:?}", tree_name, path);
+            }
+            None => print!("{}", xml),
+        }
+        return Ok(());
+    }
+
+    if let Some(xml_path) = &args.import_xml {
+        let tree = xml_to_tree(&std::fs::read_to_string(xml_path)?, &library)
+            .map_err(|e| format!("{:?}: {}", xml_path, e))?;
+        // A tree of the library is replaced in its own file, whatever its name and format
+        let existing = match &args.output_dir {
+            Some(output_dir) => Some(output_dir.join(format!("{}.yaml", tree.tree.name)))
+                .filter(|path| path.exists()),
+            None if library.trees.iter().any(|t| t.tree.name == tree.tree.name) => Some(
+                tree_file_path(&library_path, &tree.tree.name)?
+                    .ok_or(format!("No file of tree {} in the library", tree.tree.name))?,
+            ),
+            None => None,
+        };
+        let path = match existing {
+            Some(path) if args.overwrite => path,
+            Some(path) => {
+                return Err(format!(
+                    "Tree {} is already in {:?}, use --overwrite to replace it",
+                    tree.tree.name, path
+                )
+                .into())
+            }
+            None => args
+                .output_dir
+                .clone()
+                .unwrap_or(library_path.join("trees"))
+                .join(format!("{}.yaml", tree.tree.name)),
+        };
+        let format = FileFormat::from_path(&path).unwrap_or(FileFormat::Yaml);
+        std::fs::write(&path, format.write(&tree)?)?;
+        info!("Tree {} written to {:?}", tree.tree.name, path);
+        return Ok(());
+    }
+
     let out = get_workflow_by_title(&args.workflow_name, &library).expect("Failed to get workflow");
     trace!("{:?}", out);
     let out = get_tree_by_name(&args.tree_name, &library).expect("Failed to get tree");
     trace!("{:#?}", out);
 
+    if args.errors {
+        println!("| Error | Class | Message | Recovery | Operator | Raised by |");
+        println!("|---|---|---|---|---|---|");
+        for (name, info, raised_by) in error_catalogue(&library) {
+            println!(
+                "| {} | {:?} | {} | {} | {} | {} |",
+                name,
+                info.class,
+                info.message,
+                info.recovery.as_deref().unwrap_or("-"),
+                if info.requires_human { "yes" } else { "no" },
+                raised_by
+                    .iter()
+                    .map(|t| t.as_str())
+                    .collect::<Vec<&str>>()
+                    .join(", ")
+            );
+        }
+    }
+
+    let findings = analyse_library(&library);
+    if args.strict && !findings.is_empty() {
+        return Err(format!("{} finding(s) in the behavior trees", findings.len()).into());
+    }
+
     Ok(())
 }
@@ -1,20 +1,22 @@
-use clap::{ArgGroup, Args, Parser};
+use clap::{ArgGroup, Args, Parser, ValueEnum};
 // use std::error::Error;
-use log::{debug, trace};
+use log::{debug, info};
 use simplelog::*;
-use std::fs::File;
 use std::io::Write;
 use std::path::PathBuf;
 use workflow::conf::{
     get_tree_by_name,
     get_workflow_by_title,
     load_library,
+    load_tree_file,
+    load_workflow_file,
     root_library_path,
     // BehaviorTreeFile,
     Library,
     // Node,
     // WorkflowFile,
 };
+use workflow::events::{read_run_log, Event};
 
 /// Different ways to input a workflow or tree file
 #[derive(Args, Debug, Clone)]
@@ -36,6 +38,10 @@ struct InputFile {
     /// The name of the tree to run
     #[clap(short = 't', long, group = "input", conflicts_with_all = &["tree_path", "workflow_path", "workflow_name"])]
     tree_name: Option<String>,
+
+    /// What depends on what in the whole library
+    #[clap(long, group = "input", conflicts_with = "run_log")]
+    library: bool,
 }
 
 #[derive(Parser, Debug)]
@@ -50,25 +56,168 @@ struct Cli {
     #[command(flatten)]
     input_file: InputFile,
 
-    /// Output file path
+    /// File or directory to write to, the current directory by default. Text goes to the
+    /// terminal unless a path is given
     #[arg(short, long)]
     output_path: Option<PathBuf>,
+
+    /// Format to write
+    #[arg(short, long, value_enum, default_value_t = Format::Svg)]
+    format: Format,
+
+    /// Only what ends up calling this module or tool, with `--library`
+    #[arg(long)]
+    module: Vec<String>,
+
+    /// Only what this workflow uses, with `--library`
+    #[arg(long)]
+    workflow: Vec<String>,
+
+    /// Run log to colour the nodes with, see `executor --log-dir`
+    #[arg(short, long)]
+    run_log: Option<PathBuf>,
 }
 
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    /// Drawn without Graphviz
+    Svg,
+    /// Rendered with the Graphviz `dot` binary
+    Png,
+    /// Graphviz source
+    Dot,
+    /// Mermaid flowchart, for Markdown documents
+    Mermaid,
+    /// Nodes and edges, for the UI
+    Json,
+    /// Indented tree, for terminals
+    Text,
+}
+
+impl Format {
+    fn extension(&self) -> &'static str {
+        match self {
+            Format::Svg => "svg",
+            Format::Png => "png",
+            Format::Dot => "dot",
+            Format::Mermaid => "mmd",
+            Format::Json => "json",
+            Format::Text => "txt",
+        }
+    }
+}
+
+#[path = "plot/formats.rs"]
+mod formats;
+#[path = "plot/library.rs"]
+mod library;
+#[path = "plot/svg.rs"]
+mod svg;
+
 pub mod plotflow {
-    use log::trace;
+    use log::{trace, warn};
+    use petgraph::graph::{DiGraph, NodeIndex};
+    use petgraph::visit::EdgeRef;
+    use petgraph::Direction;
+    use std::collections::{HashMap, HashSet};
     use std::error::Error;
     use std::fs::File;
     use std::io::Write;
+    use std::path::Path;
+    use std::time::Duration;
+    use workflow::events::{Event, EventKind};
 
-    use workflow::{get_tree_by_name, BehaviorTreeFile, Library, Node, Sequence, WorkflowFile};
+    use workflow::{
+        get_tree_by_name, node_path, BehaviorTreeFile, Library, Node, Sequence, WorkflowFile,
+    };
 
     pub enum Input<'a> {
         Tree(&'a BehaviorTreeFile),
         Workflow(&'a WorkflowFile),
     }
 
-    fn starting_block(title: String) -> String {
+    impl Input<'_> {
+        pub fn title(&self) -> &str {
+            match self {
+                Input::Tree(tree) => &tree.title,
+                Input::Workflow(workflow) => &workflow.title,
+            }
+        }
+    }
+
+    /// How a node is drawn, colours are indices of the `rdylbu11` colour scheme
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct PlotNode {
+        /// Unique in the graph, the path of tree nodes, see `node_path`
+        pub id: String,
+        /// Lines separated by `\n`
+        pub label: String,
+        pub shape: &'static str,
+        /// Graphviz style, e.g. `rounded,filled`
+        pub style: &'static str,
+        pub color: &'static str,
+        pub fontcolor: &'static str,
+        pub peripheries: usize,
+        /// Rows of a record below the label
+        pub fields: Vec<String>,
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct PlotEdge {
+        pub color: &'static str,
+        /// Graphviz style, `solid`, `dashed` or `bold`
+        pub style: &'static str,
+        /// Row of the record the edge starts from
+        pub port: Option<usize>,
+        pub label: Option<String>,
+    }
+
+    pub type PlotGraph = DiGraph<PlotNode, PlotEdge>;
+
+    /// cds for conditions and egg for leaves, ellipses for sequences and parallel nodes and
+    /// octagons for fallbacks, decorators above the name
+    pub fn node_style(node: &Node, id: &str) -> PlotNode {
+        let name = &node.name;
+        let (shape, color, fontcolor, mut label) = match &node.sequence {
+            None if name.starts_with("is_") => ("cds", "8", "black", format!("{}?", name)),
+            None => ("egg", "5", "black", name.to_string()),
+            Some(Sequence::Children(_)) => ("ellipse", "6", "11", format!("→ {}", name)),
+            Some(Sequence::Fallback(_)) => ("octagon", "7", "11", format!("? {}", name)),
+            Some(Sequence::Parallel { .. }) => ("ellipse", "6", "11", format!("⇉ {}", name)),
+        };
+
+        // Decorated nodes get a double border and their decorators above the name, outermost first
+        let mut peripheries = 1;
+        for decorator in &node.decorators {
+            label = format!("[{}]\n{}", decorator, label);
+            peripheries = 2;
+        }
+        PlotNode {
+            id: id.to_string(),
+            label,
+            shape,
+            style: "rounded,filled",
+            color,
+            fontcolor,
+            peripheries,
+            fields: Vec::new(),
+        }
+    }
+
+    pub fn error_style(error: &str, id: &str) -> PlotNode {
+        PlotNode {
+            id: id.to_string(),
+            label: error.to_string(),
+            shape: "record",
+            style: "filled",
+            color: "1",
+            fontcolor: "white",
+            peripheries: 1,
+            fields: Vec::new(),
+        }
+    }
+
+    fn starting_block(title: &str) -> String {
         format!(
             "digraph \"{}\" {{\n\
         rankdir=LR;\n\
@@ -77,132 +226,168 @@ pub mod plotflow {
         edge [colorscheme=rdylbu11];\n\
         ranksep=0.6;\n\
         nodesep=0.25;\n\n",
-            title
+            quote(title)
         )
     }
 
-    pub fn tree_to_dot<'a>(
-        tree: &BehaviorTreeFile,
-        starting_block: &'a mut String,
-    ) -> Result<&'a mut String, Box<dyn Error>> {
-        traverse_nodes(&tree.tree, starting_block);
-        Ok(starting_block)
+    /// Escape `text` for a double quoted DOT string, keeping line breaks
+    fn quote(text: &str) -> String {
+        text.replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('\n', "\\n")
     }
 
-    fn traverse_nodes(node: &Node, dot: &mut String) {
-        let name = &node.name;
-        let is_leaf = node.sequence.is_none();
-        let (shape, color, fontcolor, label) = if name.starts_with("is_") {
-            ("cds", "8", "black", format!("{}?", name))
-        } else if is_leaf {
-            ("egg", "5", "black", name.to_string())
-        } else {
-            ("ellipse", "6", "11", name.to_string())
-        };
-
-        dot.push_str(&format!(
-            "{} [label=\"{}\", shape={} color={} fontcolor={} fontsize=12 style=\"rounded,filled\" fillcolor={}];\n",
-            name, label, shape, color, fontcolor, color
-        ));
+    /// Escape the characters that structure a record label
+    fn record_field(text: &str) -> String {
+        let mut escaped = String::new();
+        for c in quote(text).chars() {
+            if "{}|<>".contains(c) {
+                escaped.push('\\');
+            }
+            escaped.push(c);
+        }
+        escaped
+    }
 
-        if let Some(error) = &node.error {
-            trace!("{} has error {}", name, error);
-            let error_shape = "record";
-            let error_color = "1";
-            let error_fontcolor = "white";
-            let error_label = error.to_string();
+    /// Write `graph` as a Graphviz digraph
+    pub fn graph_to_dot(graph: &PlotGraph, title: &str) -> String {
+        let mut dot = starting_block(title);
+        for node in graph.node_weights() {
+            let label = if node.shape != "record" {
+                quote(&node.label)
+            } else {
+                let fields = node
+                    .fields
+                    .iter()
+                    .enumerate()
+                    .map(|(index, field)| format!("<{}> {}", index + 1, record_field(field)));
+                std::iter::once(record_field(&node.label))
+                    .chain(fields)
+                    .collect::<Vec<_>>()
+                    .join(" | ")
+            };
+            let fill = if node.style.contains("filled") {
+                format!(" fillcolor=\"{}\"", node.color)
+            } else {
+                String::new()
+            };
             dot.push_str(&format!(
-            "{}_{} [label=\"{}\" shape={} color={} fontcolor={} fontsize=12 style=filled fillcolor={}];\n",
-                name, error, error_label, error_shape, error_color, error_fontcolor, error_color
-            ));
-            dot.push_str(&format!(
-                "{} -> {}_{} [color={} style=bold];\n",
-                name, name, error, error_color
+                "\"{}\" [label=\"{}\" shape={} color=\"{}\" fontcolor=\"{}\" fontsize=12 style=\"{}\"{} peripheries={}];\n",
+                quote(&node.id),
+                label,
+                node.shape,
+                node.color,
+                node.fontcolor,
+                node.style,
+                fill,
+                node.peripheries
             ));
         }
-
-        let children = match &node.sequence {
-            Some(Sequence::Children(nodes)) | Some(Sequence::Fallback(nodes)) => nodes,
-            None => return,
-        };
-
-        for child in children {
+        for edge in graph.edge_references() {
+            let port = match edge.weight().port {
+                Some(index) => format!(":{}", index + 1),
+                None => String::new(),
+            };
+            let label = match &edge.weight().label {
+                Some(label) => format!(" label=\"{}\" fontsize=10", quote(label)),
+                None => String::new(),
+            };
             dot.push_str(&format!(
-                "{} -> {} [style=solid color=10];\n",
-                name, child.name
+                "\"{}\"{} -> \"{}\" [style={} color=\"{}\"{}];\n",
+                quote(&graph[edge.source()].id),
+                port,
+                quote(&graph[edge.target()].id),
+                edge.weight().style,
+                edge.weight().color,
+                label
             ));
-            traverse_nodes(child, dot);
         }
+        dot.push_str("}\n");
+        dot
     }
 
-    pub fn workflow_to_dot<'a>(
-        workflow: &WorkflowFile,
-        starting_block: &'a mut String,
-        library: &Library,
-    ) -> Result<&'a mut String, Box<dyn Error>> {
-        let step_names: Vec<String> = workflow
-            .workflow
-            .iter()
-            .map(|step| step.name.clone())
-            .collect();
-
-        // draw the workflow
-        let workflow_steps_label = step_names
-            .iter()
-            .enumerate()
-            .map(|(index, action)| format!("{} | <{}> {} ", index + 1, index + 1, action))
-            .collect::<Vec<String>>()
-            .join("| ");
-
-        starting_block.push_str(&format!(
-            "workflow [shape=record, label=\"workflow | {{ {} }}\"];\n",
-            workflow_steps_label
-        ));
-
-        // draw the trees
-        for (index, name) in step_names.iter().enumerate() {
-            let tree = get_tree_by_name(name, &library)?;
-
-            // let mut tree_dot = starting_block.clone();
-            tree_to_dot(&tree, starting_block)?;
-
-            // draw edges between workflow and trees
-            starting_block.push_str(&format!(
-                "workflow:{} -> {} [style=solid color=10];\n",
-                index + 1,
-                name
-            ));
+    /// Add `node` at `path`, its error and its children to `graph`
+    fn add_node(node: &Node, path: &str, graph: &mut PlotGraph) -> NodeIndex {
+        let index = graph.add_node(node_style(node, path));
+        if let Some(error) = &node.error {
+            trace!("{} has error {}", path, error);
+            let error = graph.add_node(error_style(error, &format!("{}!{}", path, error)));
+            let edge = PlotEdge {
+                color: "1",
+                style: "bold",
+                port: None,
+                label: None,
+            };
+            graph.add_edge(index, error, edge);
         }
-
-        Ok(starting_block)
+        // Fallback children are tried one after the other until one succeeds
+        let style = match &node.sequence {
+            Some(Sequence::Fallback(_)) => "dashed",
+            _ => "solid",
+        };
+        for (position, child) in node.sequence.iter().flat_map(|s| s.nodes()).enumerate() {
+            let child_index = add_node(child, &node_path(path, position, child), graph);
+            let edge = PlotEdge {
+                color: "10",
+                style,
+                port: None,
+                label: Some(child.step_number.to_string()),
+            };
+            graph.add_edge(index, child_index, edge);
+        }
+        index
     }
 
-    pub fn input_to_dot(input: &Input, library: &Library) -> Result<String, Box<dyn Error>> {
+    /// The nodes and edges drawn for the input, every node of a tree drawn separately
+    pub fn input_to_graph(input: &Input, library: &Library) -> Result<PlotGraph, Box<dyn Error>> {
+        let mut graph = PlotGraph::new();
         match input {
             Input::Tree(tree) => {
-                let mut dot = starting_block(tree.title.clone());
-                tree_to_dot(&tree, &mut dot)?;
-                dot.push_str("}\n");
-                Ok(dot)
+                add_node(&tree.tree, &tree.tree.name, &mut graph);
             }
             Input::Workflow(workflow) => {
-                let mut dot = starting_block(workflow.title.clone());
-                workflow_to_dot(&workflow, &mut dot, &library)?;
-                dot.push_str("}\n");
-                Ok(dot)
+                let steps = graph.add_node(PlotNode {
+                    id: "workflow".to_string(),
+                    label: "workflow".to_string(),
+                    shape: "record",
+                    style: "solid",
+                    color: "black",
+                    fontcolor: "black",
+                    peripheries: 1,
+                    fields: workflow
+                        .workflow
+                        .iter()
+                        .enumerate()
+                        .map(|(index, step)| format!("{}  {}", index + 1, step.name))
+                        .collect(),
+                });
+                for (index, step) in workflow.workflow.iter().enumerate() {
+                    let tree = get_tree_by_name(&step.name, library)?;
+                    // The same tree can run in several steps
+                    let path = format!("{}:{}", index + 1, tree.tree.name);
+                    let root = add_node(&tree.tree, &path, &mut graph);
+                    let edge = PlotEdge {
+                        color: "10",
+                        style: "solid",
+                        port: Some(index),
+                        label: Some((index + 1).to_string()),
+                    };
+                    graph.add_edge(steps, root, edge);
+                }
             }
         }
+        Ok(graph)
     }
 
-    pub fn input_to_png(input: &Input, library: &Library) -> Result<Vec<u8>, Box<dyn Error>> {
-        let dot = input_to_dot(input, &library)?;
+    /// Render `dot` with the Graphviz `dot` binary
+    pub fn dot_to_png(dot: &str) -> Result<Vec<u8>, Box<dyn Error>> {
         let mut cmd = std::process::Command::new("dot");
         cmd.arg("-Tpng");
         let mut child = cmd
             .stdin(std::process::Stdio::piped())
             .stdout(std::process::Stdio::piped())
             .spawn()
-            .expect("Failed to spawn dot process");
+            .map_err(|e| format!("Failed to run dot, is Graphviz installed? {}", e))?;
         let stdin = child.stdin.as_mut().ok_or("Failed to open stdin")?;
         stdin.write_all(dot.as_bytes())?;
         let output = child.wait_with_output()?;
@@ -217,13 +402,156 @@ pub mod plotflow {
         }
     }
 
-    pub fn save_image_to_file(image_data: Vec<u8>, file_path: &str) -> Result<(), Box<dyn Error>> {
+    /// What happened to a node in a run
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Outcome {
+        Success,
+        Failure,
+        /// Its parent finished without running it, e.g. a fallback child after one that succeeded
+        Skipped,
+        /// The run stopped before it, or was still in it when the log ends
+        NotReached,
+    }
+
+    impl Outcome {
+        /// Fill colour, font colour and style of nodes with this outcome
+        fn style(&self) -> (&'static str, &'static str, &'static str) {
+            match self {
+                Outcome::Success => ("#a6d96a", "black", "rounded,filled"),
+                Outcome::Failure => ("1", "white", "rounded,filled"),
+                Outcome::Skipped => ("#d9d9d9", "#636363", "rounded,filled"),
+                Outcome::NotReached => ("#bdbdbd", "#969696", "rounded,dashed"),
+            }
+        }
+    }
+
+    /// Colour the nodes of `graph` by their outcome in the run of `events` and add how long they took
+    pub fn overlay(graph: &mut PlotGraph, events: &[Event], input: &Input) {
+        let id = |event: &Event| match input {
+            Input::Tree(_) => event.path.clone(),
+            Input::Workflow(_) => format!("{}:{}", event.step_index + 1, event.path),
+        };
+        let mut results = HashMap::new();
+        let mut retries = HashMap::new();
+        let mut raised = HashSet::new();
+        let mut finished = false;
+        for event in events {
+            match &event.kind {
+                EventKind::RunStarted { steps } => {
+                    if let Input::Workflow(workflow) = input {
+                        let planned = workflow.workflow.iter().map(|s| &s.name);
+                        if !planned.eq(steps.iter()) {
+                            warn!("The run log is of other steps than {}", workflow.title);
+                        }
+                    }
+                }
+                EventKind::NodeResult { success, error } => {
+                    results.insert(id(event), (*success, event.duration, error.clone()));
+                }
+                EventKind::Retry { .. } => *retries.entry(id(event)).or_insert(0) += 1,
+                EventKind::ErrorHandler { handler, .. } => {
+                    raised.insert(format!("{}!{}", id(event), handler));
+                }
+                EventKind::RunFinished { .. } => finished = true,
+                EventKind::NodeEntered => {}
+            }
+        }
+
+        // Parents are added to the graph before their children
+        let mut outcomes: HashMap<NodeIndex, Outcome> = HashMap::new();
+        for index in graph.node_indices().collect::<Vec<_>>() {
+            let parent = graph.neighbors_directed(index, Direction::Incoming).next();
+            let node = &graph[index];
+            let outcome = match results.get(&node.id) {
+                Some((true, _, _)) => Outcome::Success,
+                Some((false, _, _)) => Outcome::Failure,
+                None if raised.contains(&node.id) => Outcome::Failure,
+                None => {
+                    match parent.map(|p| (outcomes.get(&p), results.contains_key(&graph[p].id))) {
+                        // Not yet done with the parent when the log ends
+                        Some((Some(_), false)) => Outcome::NotReached,
+                        Some((Some(Outcome::NotReached), _)) => Outcome::NotReached,
+                        Some((Some(_), true)) => Outcome::Skipped,
+                        // Trees of the workflow steps
+                        _ if finished => Outcome::Skipped,
+                        _ => Outcome::NotReached,
+                    }
+                }
+            };
+            if parent.is_none() && !node.fields.is_empty() {
+                // The steps of the workflow
+                continue;
+            }
+            outcomes.insert(index, outcome);
+
+            let node = &mut graph[index];
+            let (color, fontcolor, style) = outcome.style();
+            node.color = color;
+            node.fontcolor = fontcolor;
+            node.style = style;
+            if let Some((_, duration, error)) = results.get(&node.id) {
+                node.label = format!("{}\n{}", node.label, format_duration(*duration));
+                if let Some(retried) = retries.get(&node.id) {
+                    node.label = format!("{} after {} retries", node.label, retried);
+                }
+                if let Some(error) = error {
+                    node.label = format!("{}\n{}", node.label, shorten(error, 40));
+                }
+            }
+        }
+    }
+
+    pub fn format_duration(duration: Duration) -> String {
+        match duration.as_secs() {
+            0 => format!("{}ms", duration.as_millis()),
+            seconds if seconds < 60 => format!("{:.1}s", duration.as_secs_f64()),
+            seconds => format!("{}m{:02}s", seconds / 60, seconds % 60),
+        }
+    }
+
+    fn shorten(text: &str, length: usize) -> String {
+        match text.char_indices().nth(length) {
+            Some((end, _)) => format!("{}…", &text[..end]),
+            None => text.to_string(),
+        }
+    }
+
+    pub fn save_image_to_file(image_data: Vec<u8>, file_path: &Path) -> Result<(), Box<dyn Error>> {
         let mut file = File::create(file_path)?;
         file.write_all(&image_data)?;
         Ok(())
     }
 }
 
+/// Plot `input`, colouring the nodes by the outcome in `events` of a run
+fn plot(
+    input: &plotflow::Input,
+    library: &Library,
+    events: Option<&[Event]>,
+) -> Result<plotflow::PlotGraph, Box<dyn std::error::Error>> {
+    let mut graph = plotflow::input_to_graph(input, library)?;
+    if let Some(events) = events {
+        plotflow::overlay(&mut graph, events, input);
+    }
+    Ok(graph)
+}
+
+fn render(
+    graph: &plotflow::PlotGraph,
+    title: &str,
+    format: Format,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let output = match format {
+        Format::Svg => svg::graph_to_svg(graph, title)?.into_bytes(),
+        Format::Png => plotflow::dot_to_png(&plotflow::graph_to_dot(graph, title))?,
+        Format::Dot => plotflow::graph_to_dot(graph, title).into_bytes(),
+        Format::Mermaid => formats::graph_to_mermaid(graph, title).into_bytes(),
+        Format::Json => formats::graph_to_json(graph, title)?.into_bytes(),
+        Format::Text => formats::graph_to_text(graph).into_bytes(),
+    };
+    Ok(output)
+}
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Initialise the logger
     CombinedLogger::init(vec![TermLogger::new(
@@ -244,35 +572,164 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     };
     let library: Library = load_library(&library_path).expect("Failed to load library");
 
-    if let Some(tree_name) = args.input_file.tree_name {
-        let out = get_tree_by_name(&tree_name, &library).expect("Failed to get tree");
-        trace!("{:#?}", out);
+    if !args.input_file.library && (!args.module.is_empty() || !args.workflow.is_empty()) {
+        return Err("--module and --workflow only narrow down --library".into());
+    }
+    let events = match &args.run_log {
+        Some(path) => Some(read_run_log(path)?),
+        None => None,
+    };
 
-        let dot = plotflow::input_to_dot(&plotflow::Input::Tree(out), &library)?;
-        trace!("{}", dot);
+    let (graph, title, name) = match args.input_file {
+        InputFile {
+            tree_name: Some(tree_name),
+            ..
+        } => {
+            let input = plotflow::Input::Tree(get_tree_by_name(&tree_name, &library)?);
+            let graph = plot(&input, &library, events.as_deref())?;
+            (graph, input.title().to_string(), tree_name)
+        }
+        InputFile {
+            tree_path: Some(path),
+            ..
+        } => {
+            let tree = load_tree_file(&path, &library)?;
+            let graph = plot(&plotflow::Input::Tree(&tree), &library, events.as_deref())?;
+            (graph, tree.title, tree.tree.name)
+        }
+        InputFile {
+            workflow_name: Some(workflow_name),
+            ..
+        } => {
+            let input = plotflow::Input::Workflow(get_workflow_by_title(&workflow_name, &library)?);
+            let graph = plot(&input, &library, events.as_deref())?;
+            (graph, workflow_name.clone(), workflow_name)
+        }
+        InputFile {
+            workflow_path: Some(path),
+            ..
+        } => {
+            let workflow = load_workflow_file(&path, &library)?;
+            let graph = plot(
+                &plotflow::Input::Workflow(&workflow),
+                &library,
+                events.as_deref(),
+            )?;
+            (graph, workflow.title.clone(), workflow.title)
+        }
+        InputFile { library: true, .. } => {
+            let graph = library::library_graph(&library, &args.module, &args.workflow)?;
+            let title = library_path.display().to_string();
+            (graph, title, "library".to_string())
+        }
+        _ => unreachable!("clap requires one input"),
+    };
+    let output = render(&graph, &title, args.format)?;
+
+    // A diagram of a run is named after its log
+    let stem = match &args.run_log {
+        Some(path) => path
+            .file_stem()
+            .map_or(name, |s| s.to_string_lossy().to_string()),
+        None => name,
+    };
+    let file_name = format!("{}.{}", stem, args.format.extension());
+    let path = match args.output_path {
+        Some(path) if path.is_dir() => path.join(file_name),
+        Some(path) => path,
+        None if args.format == Format::Text => {
+            std::io::stdout().write_all(&output)?;
+            return Ok(());
+        }
+        None => PathBuf::from(file_name),
+    };
+    plotflow::save_image_to_file(output, &path)?;
+    info!("Plot written to {}", path.display());
 
-        // Save dot in a text file named output.dot
-        let mut file = File::create("output_tree.dot")?;
-        file.write_all(dot.as_bytes())?;
+    Ok(())
+}
 
-        let image_data = plotflow::input_to_png(&plotflow::Input::Tree(out), &library)?;
-        plotflow::save_image_to_file(image_data, "tree.png")?;
+#[cfg(test)]
+mod tests {
+    use super::plotflow::{format_duration, graph_to_dot, input_to_graph, overlay, Input};
+    use std::time::{Duration, SystemTime};
+    use workflow::events::{Event, EventKind};
+    use workflow::{BehaviorTreeFile, Library};
+
+    fn tree() -> BehaviorTreeFile {
+        serde_yaml::from_str::<BehaviorTreeFile>(
+            "{title: t, version: 0.1.0, description: d, participant_modules: [], \
+             tree: {name: get-tip, step_number: 1, error: null, sequence: !fallback [\
+             {name: is_tip_available, step_number: 1}, \
+             {name: is_tip_available, step_number: 2}]}}",
+        )
+        .unwrap()
     }
 
-    if let Some(worflow_name) = args.input_file.workflow_name {
-        let out = get_workflow_by_title(&worflow_name, &library)?;
-        trace!("{:?}", out);
-
-        let dot = plotflow::input_to_dot(&plotflow::Input::Workflow(out), &library)?;
-        trace!("{}", dot);
+    #[test]
+    fn repeated_leaves_get_their_own_quoted_ids() {
+        let tree = tree();
+        let input = Input::Tree(&tree);
+        let graph = input_to_graph(&input, &Library::default()).unwrap();
+        let dot = graph_to_dot(&graph, input.title());
+        assert!(dot.contains("\"get-tip/0.is_tip_available\" [label=\"is_tip_available?\""));
+        assert!(dot.contains("\"get-tip/1.is_tip_available\" [label=\"is_tip_available?\""));
+        assert!(dot.contains(
+            "\"get-tip\" -> \"get-tip/1.is_tip_available\" [style=dashed color=\"10\" label=\"2\""
+        ));
+    }
 
-        // Save dot in a text file named output.dot
-        let mut file = File::create("output_workflow.dot")?;
-        file.write_all(dot.as_bytes())?;
+    #[test]
+    fn run_log_colours_nodes_by_outcome() {
+        let tree = tree();
+        let input = Input::Tree(&tree);
+        let mut graph = input_to_graph(&input, &Library::default()).unwrap();
+        let event = |path: &str, milliseconds, kind| Event {
+            timestamp: SystemTime::UNIX_EPOCH,
+            step_index: 0,
+            path: path.to_string(),
+            node: String::new(),
+            duration: Duration::from_millis(milliseconds),
+            kind,
+        };
+        let events = vec![
+            event(
+                "get-tip/0.is_tip_available",
+                1500,
+                EventKind::NodeResult {
+                    success: true,
+                    error: None,
+                },
+            ),
+            event(
+                "get-tip",
+                1600,
+                EventKind::NodeResult {
+                    success: true,
+                    error: None,
+                },
+            ),
+        ];
+        overlay(&mut graph, &events, &input);
+        let dot = graph_to_dot(&graph, input.title());
+        assert!(dot.contains(
+            "\"get-tip/0.is_tip_available\" [label=\"is_tip_available?\\n1.5s\" shape=cds color=\"#a6d96a\""
+        ));
+        assert!(dot.contains("\"get-tip/1.is_tip_available\" [label=\"is_tip_available?\" shape=cds color=\"#d9d9d9\""));
 
-        let image_data = plotflow::input_to_png(&plotflow::Input::Workflow(out), &library)?;
-        plotflow::save_image_to_file(image_data, "workflow.png")?;
+        let mut graph = input_to_graph(&input, &Library::default()).unwrap();
+        overlay(&mut graph, &events[..1], &input);
+        let dot = graph_to_dot(&graph, input.title());
+        assert!(dot.contains("\"get-tip/1.is_tip_available\" [label=\"is_tip_available?\" shape=cds color=\"#bdbdbd\""));
     }
 
-    Ok(())
+    #[test]
+    fn durations_round_down_to_whole_seconds() {
+        let format = |milliseconds| format_duration(Duration::from_millis(milliseconds));
+        assert_eq!(format(250), "250ms");
+        assert_eq!(format(1500), "1.5s");
+        assert_eq!(format(60_000), "1m00s");
+        assert_eq!(format(119_900), "1m59s");
+        assert_eq!(format(3_725_000), "62m05s");
+    }
 }
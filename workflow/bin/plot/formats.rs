@@ -0,0 +1,244 @@
+//! Plot graphs as Mermaid flowcharts, JSON for the UI and indented text for terminals
+
+use crate::plotflow::{PlotGraph, PlotNode};
+use crate::svg::{children, colour};
+use petgraph::graph::NodeIndex;
+use petgraph::visit::EdgeRef;
+use petgraph::Direction;
+use serde::Serialize;
+use std::error::Error;
+
+fn mermaid_label(node: &PlotNode) -> String {
+    let lines = std::iter::once(node.label.as_str())
+        .chain(node.fields.iter().map(String::as_str))
+        .flat_map(|l| l.split('\n'))
+        .map(|l| l.replace('"', "#quot;"))
+        .collect::<Vec<_>>();
+    lines.join("<br/>")
+}
+
+/// Mermaid flowchart, for Markdown documents
+pub fn graph_to_mermaid(graph: &PlotGraph, title: &str) -> String {
+    let mut mermaid = format!("---\ntitle: {}\n---\nflowchart LR\n", title);
+    for index in graph.node_indices() {
+        let node = &graph[index];
+        let label = mermaid_label(node);
+        let (open, close) = match node.shape {
+            "ellipse" => ("([", "])"),
+            "egg" => ("(", ")"),
+            "cds" => (">", "]"),
+            "octagon" => ("{{", "}}"),
+            _ => ("[", "]"),
+        };
+        mermaid.push_str(&format!(
+            "    n{}{}\"{}\"{}\n",
+            index.index(),
+            open,
+            label,
+            close
+        ));
+    }
+    for edge in graph.edge_references() {
+        let arrow = match edge.weight().style {
+            "dashed" => "-.->",
+            "bold" => "==>",
+            _ => "-->",
+        };
+        let label = match &edge.weight().label {
+            Some(label) => format!("|\"{}\"|", label),
+            None => String::new(),
+        };
+        mermaid.push_str(&format!(
+            "    n{} {}{} n{}\n",
+            edge.source().index(),
+            arrow,
+            label,
+            edge.target().index()
+        ));
+    }
+    for index in graph.node_indices() {
+        let node = &graph[index];
+        let stroke = colour(node.color);
+        let fill = match node.style.contains("filled") {
+            true => stroke,
+            false => "white",
+        };
+        let mut style = format!(
+            "fill:{},stroke:{},color:{}",
+            fill,
+            stroke,
+            colour(node.fontcolor)
+        );
+        if node.style.contains("dashed") {
+            style.push_str(",stroke-dasharray:5 3");
+        }
+        if node.peripheries > 1 {
+            style.push_str(",stroke-width:3px");
+        }
+        mermaid.push_str(&format!("    style n{} {}\n", index.index(), style));
+    }
+    for edge in graph.edge_references() {
+        mermaid.push_str(&format!(
+            "    linkStyle {} stroke:{}\n",
+            edge.id().index(),
+            colour(edge.weight().color)
+        ));
+    }
+    mermaid
+}
+
+#[derive(Serialize)]
+struct JsonNode<'a> {
+    id: &'a str,
+    label: &'a str,
+    shape: &'a str,
+    style: &'a str,
+    color: &'a str,
+    fontcolor: &'a str,
+    peripheries: usize,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    fields: &'a [String],
+}
+
+#[derive(Serialize)]
+struct JsonEdge<'a> {
+    source: &'a str,
+    target: &'a str,
+    style: &'a str,
+    color: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    port: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    label: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+struct JsonGraph<'a> {
+    title: &'a str,
+    nodes: Vec<JsonNode<'a>>,
+    edges: Vec<JsonEdge<'a>>,
+}
+
+/// Nodes and edges with their ids and how they are drawn, colours as hex
+pub fn graph_to_json(graph: &PlotGraph, title: &str) -> Result<String, Box<dyn Error>> {
+    let nodes = graph
+        .node_weights()
+        .map(|node| JsonNode {
+            id: &node.id,
+            label: &node.label,
+            shape: node.shape,
+            style: node.style,
+            color: colour(node.color),
+            fontcolor: colour(node.fontcolor),
+            peripheries: node.peripheries,
+            fields: &node.fields,
+        })
+        .collect();
+    let edges = graph
+        .edge_references()
+        .map(|edge| JsonEdge {
+            source: &graph[edge.source()].id,
+            target: &graph[edge.target()].id,
+            style: edge.weight().style,
+            color: colour(edge.weight().color),
+            port: edge.weight().port,
+            label: edge.weight().label.as_deref(),
+        })
+        .collect();
+    let json = JsonGraph {
+        title,
+        nodes,
+        edges,
+    };
+    Ok(serde_json::to_string_pretty(&json)?)
+}
+
+fn text_node(graph: &PlotGraph, node: NodeIndex, prefix: &str, text: &mut String) {
+    let children = children(graph, node);
+    for (i, child) in children.iter().enumerate() {
+        let last = i + 1 == children.len();
+        let edge = graph
+            .find_edge(node, *child)
+            .map(|e| &graph[e])
+            .expect("Child without an edge");
+        let mut line = format!("{}{}", prefix, if last { "└── " } else { "├── " });
+        if edge.style == "bold" {
+            line.push_str("raises ");
+        }
+        if let Some(label) = &edge.label {
+            line.push_str(&format!("{}. ", label));
+        }
+        line.push_str(&graph[*child].label.replace('\n', " · "));
+        text.push_str(line.trim_end());
+        text.push('\n');
+        let prefix = format!("{}{}", prefix, if last { "    " } else { "│   " });
+        text_node(graph, *child, &prefix, text);
+    }
+}
+
+/// Indented tree, for terminals
+pub fn graph_to_text(graph: &PlotGraph) -> String {
+    let mut text = String::new();
+    for root in graph.externals(Direction::Incoming) {
+        text.push_str(&graph[root].label.replace('\n', " · "));
+        text.push('\n');
+        text_node(graph, root, "", &mut text);
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plotflow::{input_to_graph, Input};
+    use workflow::{BehaviorTreeFile, Library};
+
+    fn graph() -> PlotGraph {
+        let tree = serde_yaml::from_str::<BehaviorTreeFile>(
+            "{title: t, version: 0.1.0, description: d, participant_modules: [], \
+             tree: {name: get_tip, step_number: 1, error: null, sequence: !children [\
+             {name: is_tip_available, step_number: 1}, \
+             {name: pick, step_number: 2, sequence: !fallback [\
+             {name: grab, step_number: 1}, {name: home, step_number: 2}]}]}}",
+        )
+        .unwrap();
+        input_to_graph(&Input::Tree(&tree), &Library::default()).unwrap()
+    }
+
+    #[test]
+    fn text_is_indented_by_depth() {
+        let text = graph_to_text(&graph());
+        let expected = [
+            "→ get_tip",
+            "├── 1. is_tip_available?",
+            "└── 2. ? pick",
+            "    ├── 1. grab",
+            "    └── 2. home",
+        ];
+        assert_eq!(text, expected.join("\n") + "\n");
+    }
+
+    #[test]
+    fn mermaid_and_json_keep_the_edges() {
+        let graph = graph();
+        let mermaid = graph_to_mermaid(&graph, "t");
+        assert!(mermaid.contains("n0([\"→ get_tip\"])"));
+        assert!(mermaid.contains("n2 -.->|\"1\"| n3"));
+
+        let json: serde_json::Value =
+            serde_json::from_str(&graph_to_json(&graph, "t").unwrap()).unwrap();
+        assert_eq!(json["nodes"].as_array().unwrap().len(), 5);
+        let edges = json["edges"].as_array().unwrap();
+        let pick = edges
+            .iter()
+            .find(|e| e["target"] == "get_tip/1.pick")
+            .unwrap();
+        assert_eq!(pick["source"], "get_tip");
+        assert_eq!(pick["label"], "2");
+        let grab = edges
+            .iter()
+            .find(|e| e["target"] == "get_tip/1.pick/0.grab")
+            .unwrap();
+        assert_eq!(grab["style"], "dashed");
+    }
+}
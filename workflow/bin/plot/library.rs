@@ -0,0 +1,303 @@
+//! What depends on what in a library: workflows → trees → nodes → modules and tools
+
+use crate::plotflow::{PlotEdge, PlotGraph, PlotNode};
+use petgraph::graph::NodeIndex;
+use petgraph::visit::{Dfs, Reversed};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::error::Error;
+use workflow::{Library, Node, NodeType};
+
+fn node(id: String, label: &str, shape: &'static str, color: &'static str) -> PlotNode {
+    PlotNode {
+        id,
+        label: label.to_string(),
+        shape,
+        style: "rounded,filled",
+        color,
+        fontcolor: "black",
+        peripheries: 1,
+        fields: Vec::new(),
+    }
+}
+
+fn edge(style: &'static str, label: Option<String>) -> PlotEdge {
+    PlotEdge {
+        color: "10",
+        style,
+        port: None,
+        label,
+    }
+}
+
+/// Leaves of the tree under `node`
+fn leaves<'a>(node: &'a Node, names: &mut BTreeSet<&'a str>) {
+    match &node.sequence {
+        Some(sequence) => {
+            for child in sequence.nodes() {
+                leaves(child, names);
+            }
+        }
+        None => {
+            names.insert(&node.name);
+        }
+    }
+}
+
+struct Builder<'a> {
+    library: &'a Library,
+    graph: PlotGraph,
+    ids: BTreeMap<String, NodeIndex>,
+}
+
+impl Builder<'_> {
+    fn add(&mut self, plot_node: PlotNode) -> NodeIndex {
+        let id = plot_node.id.clone();
+        *self
+            .ids
+            .entry(id)
+            .or_insert_with(|| self.graph.add_node(plot_node))
+    }
+
+    /// A module of the modules file, or a tool of the tools file
+    fn module(&mut self, abbr: &str) -> NodeIndex {
+        match self.library.tools.content.get(abbr) {
+            Some(tool) => self.add(node(
+                format!("tool:{}", abbr),
+                &format!("{}\n{}", abbr, tool.name),
+                "tab",
+                "3",
+            )),
+            None => {
+                let label = match self.library.modules.content.get(abbr) {
+                    Some(module) => format!("{}\n{}", abbr, module.info.name),
+                    None => abbr.to_string(),
+                };
+                self.add(node(format!("module:{}", abbr), &label, "component", "9"))
+            }
+        }
+    }
+
+    fn known_node(&mut self, name: &str) -> NodeIndex {
+        let known = self.library.nodes.content.get(name);
+        let (shape, color) = match known.map(|k| &k.node_type) {
+            Some(NodeType::Condition) => ("cds", "8"),
+            _ => ("egg", "5"),
+        };
+        let index = self.add(node(format!("node:{}", name), name, shape, color));
+        if let Some(known) = known {
+            for module in known.zenoh.iter().flat_map(|z| &z.modules) {
+                let module = self.module(module);
+                self.graph.update_edge(index, module, edge("solid", None));
+            }
+            if let Some(tool) = &known.needs_tool {
+                let tool = self.module(tool);
+                self.graph.update_edge(index, tool, edge("dashed", None));
+            }
+        }
+        index
+    }
+
+    fn tree(&mut self, name: &str) -> NodeIndex {
+        let index = self.add(node(format!("tree:{}", name), name, "ellipse", "6"));
+        let tree = match self.library.trees.iter().find(|t| t.tree.name == name) {
+            Some(tree) => tree,
+            None => return index,
+        };
+        let mut names = BTreeSet::new();
+        leaves(&tree.tree, &mut names);
+        for name in names {
+            let leaf = self.known_node(name);
+            self.graph.update_edge(index, leaf, edge("solid", None));
+        }
+        for participant in &tree.participants {
+            let module = self.module(participant);
+            self.graph.update_edge(index, module, edge("dashed", None));
+        }
+        index
+    }
+}
+
+/// Graph of the whole library, narrowed to what uses `modules` and what `workflows` use when
+/// they are not empty
+pub fn library_graph(
+    library: &Library,
+    modules: &[String],
+    workflows: &[String],
+) -> Result<PlotGraph, Box<dyn Error>> {
+    let mut builder = Builder {
+        library,
+        graph: PlotGraph::new(),
+        ids: BTreeMap::new(),
+    };
+    let mut workflow_files = library.workflows.iter().collect::<Vec<_>>();
+    workflow_files.sort_by(|a, b| a.title.cmp(&b.title));
+    for workflow in workflow_files {
+        let index = builder.add(node(
+            format!("workflow:{}", workflow.title),
+            &workflow.title,
+            "box",
+            "7",
+        ));
+        let mut steps: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+        for (i, step) in workflow.workflow.iter().enumerate() {
+            steps
+                .entry(&step.name)
+                .or_default()
+                .push((i + 1).to_string());
+        }
+        let cleanup = workflow.cleanup.iter().map(|c| (c, "cleanup".to_string()));
+        for (tree, label) in steps
+            .into_iter()
+            .map(|(tree, steps)| (tree, steps.join(", ")))
+            .chain(cleanup.map(|(tree, label)| (tree.as_str(), label)))
+        {
+            let tree = builder.tree(tree);
+            builder
+                .graph
+                .update_edge(index, tree, edge("solid", Some(label)));
+        }
+    }
+    let mut trees = library
+        .trees
+        .iter()
+        .map(|t| &t.tree.name)
+        .collect::<Vec<_>>();
+    trees.sort();
+    for tree in trees {
+        builder.tree(tree);
+    }
+    let mut nodes = library.nodes.content.keys().collect::<Vec<_>>();
+    nodes.sort();
+    for name in nodes {
+        if library.nodes.content[name].node_type != NodeType::Error {
+            builder.known_node(name);
+        }
+    }
+    let mut abbrs = library
+        .modules
+        .content
+        .keys()
+        .chain(library.tools.content.keys())
+        .collect::<Vec<_>>();
+    abbrs.sort();
+    for abbr in abbrs {
+        builder.module(abbr);
+    }
+
+    let Builder { graph, ids, .. } = builder;
+    let mut keep = graph.node_indices().collect::<HashSet<_>>();
+    if !modules.is_empty() {
+        // Everything that ends up calling the modules
+        let mut users = HashSet::new();
+        for module in modules {
+            let start = ids
+                .get(&format!("module:{}", module))
+                .or(ids.get(&format!("tool:{}", module)))
+                .ok_or(format!("Module {} is not in the library", module))?;
+            let mut dfs = Dfs::new(Reversed(&graph), *start);
+            while let Some(index) = dfs.next(Reversed(&graph)) {
+                users.insert(index);
+            }
+        }
+        keep.retain(|i| users.contains(i));
+    }
+    if !workflows.is_empty() {
+        let mut used = HashSet::new();
+        for workflow in workflows {
+            let start = ids
+                .get(&format!("workflow:{}", workflow))
+                .ok_or(format!("Workflow {} is not in the library", workflow))?;
+            let mut dfs = Dfs::new(&graph, *start);
+            while let Some(index) = dfs.next(&graph) {
+                used.insert(index);
+            }
+        }
+        keep.retain(|i| used.contains(i));
+    }
+    Ok(graph.filter_map(
+        |i, n| keep.contains(&i).then(|| n.clone()),
+        |_, e| Some(e.clone()),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use workflow::{BehaviorTreeFile, WorkflowFile};
+
+    fn library() -> Library {
+        let mut library = Library {
+            modules: serde_yaml::from_str(
+            "{title: m, description: d, version: 0.1.0, endpoint: e, content: {\
+             cfg: {info: {name: Centrifuge, type: t, description: d}, api: {endpoint: e, services: {}}}, \
+             pip: {info: {name: Pipettor, type: t, description: d}, api: {endpoint: e, services: {}}}}}",
+            )
+            .unwrap(),
+            nodes: serde_yaml::from_str(
+                "{title: n, description: d, version: 0.1.0, content: {\
+             spin: {type: action, description: d, zenoh: {modules: [cfg], min_reply: all}}, \
+             aspirate: {type: action, description: d, zenoh: {modules: [pip], min_reply: all}}}}",
+            )
+            .unwrap(),
+            ..Default::default()
+        };
+        for (name, leaf) in [("spin_down", "spin"), ("transfer", "aspirate")] {
+            library.trees.push(
+                serde_yaml::from_str::<BehaviorTreeFile>(&format!(
+                    "{{title: t, version: 0.1.0, description: d, participant_modules: [], \
+                     tree: {{name: {}, step_number: 0, sequence: !children [\
+                     {{name: {}, step_number: 1}}]}}}}",
+                    name, leaf
+                ))
+                .unwrap(),
+            );
+        }
+        for (title, tree) in [("spin_only", "spin_down"), ("pipette_only", "transfer")] {
+            library.workflows.push(
+                serde_yaml::from_str::<WorkflowFile>(&format!(
+                    "{{title: {}, description: d, version: 0.1.0, parameters: [], \
+                     process_tldr: p, workflow: [{{name: {}, why: w}}]}}",
+                    title, tree
+                ))
+                .unwrap(),
+            );
+        }
+        library
+    }
+
+    fn ids(graph: &PlotGraph) -> BTreeSet<&str> {
+        graph.node_weights().map(|n| n.id.as_str()).collect()
+    }
+
+    #[test]
+    fn filters_keep_what_uses_a_module_or_is_used_by_a_workflow() {
+        let library = library();
+        let graph = library_graph(&library, &[], &[]).unwrap();
+        assert_eq!(graph.node_count(), 8);
+
+        let graph = library_graph(&library, &["cfg".to_string()], &[]).unwrap();
+        assert_eq!(
+            ids(&graph),
+            BTreeSet::from([
+                "module:cfg",
+                "node:spin",
+                "tree:spin_down",
+                "workflow:spin_only"
+            ])
+        );
+
+        let graph = library_graph(&library, &[], &["pipette_only".to_string()]).unwrap();
+        assert_eq!(
+            ids(&graph),
+            BTreeSet::from([
+                "module:pip",
+                "node:aspirate",
+                "tree:transfer",
+                "workflow:pipette_only"
+            ])
+        );
+        assert_eq!(graph.edge_count(), 3);
+
+        assert!(library_graph(&library, &["xyz".to_string()], &[]).is_err());
+    }
+}
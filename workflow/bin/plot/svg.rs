@@ -0,0 +1,440 @@
+//! Layered left to right layout of a plot graph, drawn as SVG without Graphviz
+
+use crate::plotflow::{PlotEdge, PlotGraph, PlotNode};
+use petgraph::algo::toposort;
+use petgraph::graph::NodeIndex;
+use petgraph::visit::EdgeRef;
+use petgraph::Direction;
+use std::error::Error;
+
+const FONT_SIZE: f64 = 12.0;
+/// Rough width of a character at `FONT_SIZE`, there is no font metrics to measure it with
+const CHAR_WIDTH: f64 = 6.6;
+const LINE_HEIGHT: f64 = 15.0;
+const PADDING: f64 = 12.0;
+/// Horizontal gap between layers and vertical gap between nodes, as `ranksep` and `nodesep`
+const RANK_SEP: f64 = 45.0;
+const NODE_SEP: f64 = 14.0;
+const MARGIN: f64 = 16.0;
+
+/// Colours of the `rdylbu11` Graphviz colour scheme the DOT output uses
+pub fn colour(name: &str) -> &str {
+    match name {
+        "1" => "#a50026",
+        "2" => "#d73027",
+        "3" => "#f46d43",
+        "4" => "#fdae61",
+        "5" => "#fee090",
+        "6" => "#ffffbf",
+        "7" => "#e0f3f8",
+        "8" => "#abd9e9",
+        "9" => "#74add1",
+        "10" => "#4575b4",
+        "11" => "#313695",
+        other => other,
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Rect {
+    x: f64,
+    y: f64,
+    width: f64,
+    height: f64,
+}
+
+impl Rect {
+    fn centre_y(&self) -> f64 {
+        self.y + self.height / 2.0
+    }
+}
+
+fn text_width(text: &str) -> f64 {
+    text.chars().count() as f64 * CHAR_WIDTH
+}
+
+fn size(node: &PlotNode) -> (f64, f64) {
+    let lines = node
+        .label
+        .lines()
+        .chain(node.fields.iter().map(|f| f.as_str()));
+    let widest = lines.map(text_width).fold(0.0, f64::max);
+    let rows = (node.label.lines().count() + node.fields.len()) as f64;
+    let (extra_width, extra_height) = match node.shape {
+        // Leave room for the curve of ellipses and the point of cds
+        "ellipse" | "egg" => (2.0 * PADDING, PADDING),
+        "cds" | "octagon" => (PADDING, 0.0),
+        _ => (0.0, 0.0),
+    };
+    (
+        widest + 2.0 * PADDING + extra_width,
+        rows * LINE_HEIGHT + PADDING + extra_height,
+    )
+}
+
+/// Children of `node` in the order they were added
+pub fn children(graph: &PlotGraph, node: NodeIndex) -> Vec<NodeIndex> {
+    let mut edges = graph.edges(node).collect::<Vec<_>>();
+    edges.sort_by_key(|e| e.id());
+    edges.into_iter().map(|e| e.target()).collect()
+}
+
+struct Layout {
+    rects: Vec<Rect>,
+    /// Lowest point used in each layer so far
+    layer_bottom: Vec<f64>,
+    /// Where the next node without children goes
+    cursor: f64,
+    placed: Vec<bool>,
+}
+
+impl Layout {
+    /// Put the children of `node` one under the other and `node` level with them
+    fn place(&mut self, graph: &PlotGraph, node: NodeIndex, layers: &[usize]) {
+        if self.placed[node.index()] {
+            return;
+        }
+        self.placed[node.index()] = true;
+        let children = children(graph, node)
+            .into_iter()
+            .filter(|c| !self.placed[c.index()])
+            .collect::<Vec<_>>();
+        for child in &children {
+            self.place(graph, *child, layers);
+        }
+        let layer = layers[node.index()];
+        let height = self.rects[node.index()].height;
+        let wanted = match (children.first(), children.last()) {
+            (Some(first), Some(last)) => {
+                let centre = (self.rects[first.index()].centre_y()
+                    + self.rects[last.index()].centre_y())
+                    / 2.0;
+                centre - height / 2.0
+            }
+            _ => self.cursor,
+        };
+        let y = wanted.max(self.layer_bottom[layer]);
+        self.rects[node.index()].y = y;
+        self.layer_bottom[layer] = y + height + NODE_SEP;
+        self.cursor = self.cursor.max(y + height + NODE_SEP);
+    }
+}
+
+/// Position of every node: layers from left to right by longest path from a root, then each
+/// subtree in its own horizontal band
+fn layout(graph: &PlotGraph) -> Result<Vec<Rect>, Box<dyn Error>> {
+    let order = toposort(graph, None).map_err(|_| "Cannot lay out a graph with a cycle")?;
+    let mut layers = vec![0; graph.node_count()];
+    for node in &order {
+        for child in graph.neighbors_directed(*node, Direction::Outgoing) {
+            layers[child.index()] = layers[child.index()].max(layers[node.index()] + 1);
+        }
+    }
+    let layer_count = layers.iter().max().map_or(0, |l| l + 1);
+
+    let mut rects = graph
+        .node_indices()
+        .map(|n| {
+            let (width, height) = size(&graph[n]);
+            Rect {
+                width,
+                height,
+                ..Default::default()
+            }
+        })
+        .collect::<Vec<_>>();
+    let mut layer_x = vec![MARGIN; layer_count];
+    for layer in 1..layer_count {
+        let widest = graph
+            .node_indices()
+            .filter(|n| layers[n.index()] == layer - 1)
+            .map(|n| rects[n.index()].width)
+            .fold(0.0, f64::max);
+        layer_x[layer] = layer_x[layer - 1] + widest + RANK_SEP;
+    }
+    for node in graph.node_indices() {
+        rects[node.index()].x = layer_x[layers[node.index()]];
+    }
+
+    let mut layout = Layout {
+        rects,
+        layer_bottom: vec![MARGIN; layer_count],
+        cursor: MARGIN,
+        placed: vec![false; graph.node_count()],
+    };
+    let roots = graph
+        .node_indices()
+        .filter(|n| {
+            graph
+                .neighbors_directed(*n, Direction::Incoming)
+                .next()
+                .is_none()
+        })
+        .collect::<Vec<_>>();
+    for root in roots {
+        layout.place(graph, root, &layers);
+    }
+    Ok(layout.rects)
+}
+
+fn shape(node: &PlotNode, rect: &Rect, inset: f64, fill: &str, stroke: &str) -> String {
+    let Rect {
+        x,
+        y,
+        width,
+        height,
+    } = *rect;
+    let (x, y, width, height) = (
+        x - inset,
+        y - inset,
+        width + 2.0 * inset,
+        height + 2.0 * inset,
+    );
+    let mut style = format!("fill=\"{}\" stroke=\"{}\"", fill, stroke);
+    if node.style.contains("dashed") {
+        style.push_str(" stroke-dasharray=\"5,3\"");
+    }
+    match node.shape {
+        "ellipse" | "egg" => format!(
+            "<ellipse cx=\"{:.1}\" cy=\"{:.1}\" rx=\"{:.1}\" ry=\"{:.1}\" {}/>\n",
+            x + width / 2.0,
+            y + height / 2.0,
+            width / 2.0,
+            height / 2.0,
+            style
+        ),
+        "cds" => {
+            let tip = height / 2.0;
+            format!(
+                "<polygon points=\"{:.1},{:.1} {:.1},{:.1} {:.1},{:.1} {:.1},{:.1} {:.1},{:.1}\" {}/>\n",
+                x,
+                y,
+                x + width - tip,
+                y,
+                x + width,
+                y + height / 2.0,
+                x + width - tip,
+                y + height,
+                x,
+                y + height,
+                style
+            )
+        }
+        "octagon" => {
+            let corner = height / 3.0;
+            let points = [
+                (x + corner, y),
+                (x + width - corner, y),
+                (x + width, y + corner),
+                (x + width, y + height - corner),
+                (x + width - corner, y + height),
+                (x + corner, y + height),
+                (x, y + height - corner),
+                (x, y + corner),
+            ];
+            format!(
+                "<polygon points=\"{}\" {}/>\n",
+                points
+                    .iter()
+                    .map(|(x, y)| format!("{:.1},{:.1}", x, y))
+                    .collect::<Vec<_>>()
+                    .join(" "),
+                style
+            )
+        }
+        _ => {
+            let rounded = if node.shape != "record" && node.style.contains("rounded") {
+                " rx=\"6\""
+            } else {
+                ""
+            };
+            format!(
+                "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\"{} {}/>\n",
+                x, y, width, height, rounded, style
+            )
+        }
+    }
+}
+
+fn draw_node(node: &PlotNode, rect: &Rect, svg: &mut String) {
+    let stroke = colour(node.color);
+    let fill = if node.style.contains("filled") {
+        stroke
+    } else {
+        "white"
+    };
+    svg.push_str(&shape(node, rect, 0.0, fill, stroke));
+    for periphery in 1..node.peripheries {
+        svg.push_str(&shape(node, rect, 4.0 * periphery as f64, "none", stroke));
+    }
+
+    let lines = node.label.lines().count() + node.fields.len();
+    let top = rect.centre_y() - lines as f64 * LINE_HEIGHT / 2.0;
+    for (row, field) in node.fields.iter().enumerate() {
+        let y = top + (node.label.lines().count() + row) as f64 * LINE_HEIGHT;
+        svg.push_str(&format!(
+            "<line x1=\"{:.1}\" y1=\"{:.1}\" x2=\"{:.1}\" y2=\"{:.1}\" stroke=\"black\"/>\n",
+            rect.x,
+            y,
+            rect.x + rect.width,
+            y
+        ));
+        svg.push_str(&format!(
+            "<text x=\"{:.1}\" y=\"{:.1}\" font-family=\"Times,serif\" font-size=\"{}\" fill=\"{}\">{}</text>\n",
+            rect.x + PADDING,
+            y + LINE_HEIGHT - 3.0,
+            FONT_SIZE,
+            colour(node.fontcolor),
+            escape(field)
+        ));
+    }
+    for (row, line) in node.label.lines().enumerate() {
+        svg.push_str(&format!(
+            "<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"middle\" font-family=\"Times,serif\" font-size=\"{}\" fill=\"{}\">{}</text>\n",
+            rect.x + rect.width / 2.0,
+            top + (row + 1) as f64 * LINE_HEIGHT - 3.0,
+            FONT_SIZE,
+            colour(node.fontcolor),
+            escape(line)
+        ));
+    }
+}
+
+/// Where an edge leaves `node`: the middle of its right side, or of the row of a record field
+fn port_y(node: &PlotNode, rect: &Rect, port: Option<usize>) -> f64 {
+    match port {
+        Some(field) => {
+            let lines = node.label.lines().count() + node.fields.len();
+            let top = rect.centre_y() - lines as f64 * LINE_HEIGHT / 2.0;
+            top + (node.label.lines().count() + field) as f64 * LINE_HEIGHT + LINE_HEIGHT / 2.0
+        }
+        None => rect.centre_y(),
+    }
+}
+
+fn draw_edge(edge: &PlotEdge, from: (f64, f64), to: (f64, f64), svg: &mut String) {
+    let stroke = colour(edge.color);
+    let (width, dashes) = match edge.style {
+        "bold" => (2.0, ""),
+        "dashed" => (1.0, " stroke-dasharray=\"5,3\""),
+        _ => (1.0, ""),
+    };
+    let arrow = 7.0;
+    let end = (to.0 - arrow, to.1);
+    let bend = (end.0 - from.0) / 2.0;
+    svg.push_str(&format!(
+        "<path d=\"M{:.1},{:.1} C{:.1},{:.1} {:.1},{:.1} {:.1},{:.1}\" fill=\"none\" stroke=\"{}\" stroke-width=\"{}\"{}/>\n",
+        from.0,
+        from.1,
+        from.0 + bend,
+        from.1,
+        end.0 - bend,
+        end.1,
+        end.0,
+        end.1,
+        stroke,
+        width,
+        dashes
+    ));
+    svg.push_str(&format!(
+        "<polygon points=\"{:.1},{:.1} {:.1},{:.1} {:.1},{:.1}\" fill=\"{}\" stroke=\"{}\"/>\n",
+        to.0,
+        to.1,
+        end.0,
+        end.1 - arrow / 2.0,
+        end.0,
+        end.1 + arrow / 2.0,
+        stroke,
+        stroke
+    ));
+    if let Some(label) = &edge.label {
+        // Halfway along the curve, just above it
+        svg.push_str(&format!(
+            "<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"middle\" font-family=\"Times,serif\" font-size=\"10\" fill=\"{}\">{}</text>\n",
+            (from.0 + end.0) / 2.0,
+            (from.1 + end.1) / 2.0 - 3.0,
+            stroke,
+            escape(label)
+        ));
+    }
+}
+
+/// Draw `graph` as a standalone SVG document
+pub fn graph_to_svg(graph: &PlotGraph, title: &str) -> Result<String, Box<dyn Error>> {
+    let rects = layout(graph)?;
+    let width = rects.iter().map(|r| r.x + r.width).fold(0.0, f64::max) + MARGIN;
+    let height = rects.iter().map(|r| r.y + r.height).fold(0.0, f64::max) + MARGIN;
+
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{:.0}\" height=\"{:.0}\" viewBox=\"0 0 {:.0} {:.0}\">\n\
+         <title>{}</title>\n\
+         <rect width=\"100%\" height=\"100%\" fill=\"white\"/>\n",
+        width,
+        height,
+        width,
+        height,
+        escape(title)
+    );
+    for edge in graph.edge_references() {
+        let (source, target) = (edge.source().index(), edge.target().index());
+        let from = (
+            rects[source].x + rects[source].width,
+            port_y(&graph[edge.source()], &rects[source], edge.weight().port),
+        );
+        let to = (rects[target].x, rects[target].centre_y());
+        draw_edge(edge.weight(), from, to, &mut svg);
+    }
+    for node in graph.node_indices() {
+        draw_node(&graph[node], &rects[node.index()], &mut svg);
+    }
+    svg.push_str("</svg>\n");
+    Ok(svg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(label: &str) -> PlotNode {
+        PlotNode {
+            id: label.to_string(),
+            label: label.to_string(),
+            shape: "egg",
+            style: "rounded,filled",
+            color: "5",
+            fontcolor: "black",
+            peripheries: 1,
+            fields: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn children_are_laid_out_right_of_their_parent_without_overlapping() {
+        let mut graph = PlotGraph::new();
+        let root = graph.add_node(node("pick_tip"));
+        for _ in 0..3 {
+            let child = graph.add_node(node("is_tip_available"));
+            let edge = PlotEdge {
+                color: "10",
+                style: "solid",
+                port: None,
+                label: None,
+            };
+            graph.add_edge(root, child, edge);
+        }
+        let rects = layout(&graph).unwrap();
+        for pair in rects[1..].windows(2) {
+            assert_eq!(pair[0].x, pair[1].x);
+            assert!(pair[0].y + pair[0].height <= pair[1].y);
+        }
+        assert!(rects[0].x + rects[0].width < rects[1].x);
+        assert_eq!(rects[0].centre_y(), rects[2].centre_y());
+    }
+}
@@ -1,8 +1,31 @@
+use clap::Parser;
 use log::info;
-use workflow::conf::generate_json_schemas;
+use std::path::PathBuf;
+use workflow::conf::{load_library, root_library_path};
+use workflow::schema::{generate_json_schemas, write_editor_settings};
 
 use simplelog::*;
 
+#[derive(Parser, Debug)]
+#[command(author, version, about = "Write the JSON schemas of the library files", long_about = None)]
+struct Args {
+    /// Path to the library folder
+    #[arg(short, long)]
+    library_path: Option<PathBuf>,
+
+    /// Directory to write the schemas to, `schema` in the library by default
+    #[arg(short, long)]
+    output_dir: Option<PathBuf>,
+
+    /// Only accept the nodes, trees, modules and tools the library defines where files name them
+    #[arg(long)]
+    library_specific: bool,
+
+    /// Do not map the library files to the schemas in `.vscode/settings.json` of the library
+    #[arg(long)]
+    no_editor: bool,
+}
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Initialise the logger
     CombinedLogger::init(vec![TermLogger::new(
@@ -14,8 +37,27 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     )])
     .unwrap();
 
+    let args = Args::parse();
+    let library_path = match args.library_path {
+        Some(path) => path,
+        None => root_library_path()?,
+    };
+    let schema_dir = args.output_dir.unwrap_or(library_path.join("schema"));
+
     info!("Starting schemagen");
-    generate_json_schemas()?;
+    let library = match args.library_specific {
+        true => Some(load_library(&library_path)?),
+        false => None,
+    };
+    generate_json_schemas(&schema_dir, library.as_ref())?;
+    info!("Schemas written to {}", schema_dir.display());
+    if !args.no_editor {
+        let settings = write_editor_settings(&library_path, &schema_dir)?;
+        info!(
+            "Library files mapped to the schemas in {}",
+            settings.display()
+        );
+    }
     info!("Finished schemagen");
 
     Ok(())
@@ -0,0 +1,515 @@
+use crate::blackboard::{Blackboard, Expression};
+use crate::conf::{
+    node_path, BehaviorTreeFile, BlackboardKey, BlackboardValue, Decorator, KeyType, Library, Node,
+    NodeType, Sequence,
+};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+/// Assignments tried before giving up on proving a contradiction
+const MAX_ASSIGNMENTS: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FindingKind {
+    /// A fallback child that is never run because an earlier child cannot fail
+    UnreachableChild,
+    /// A condition that can never hold together with an earlier condition of the sequence
+    Contradiction,
+    /// An error node attached to a node that cannot fail
+    UnreachableError,
+    EmptySequence,
+}
+
+/// Something in a tree that is valid but most likely a mistake
+#[derive(Debug, Clone, PartialEq)]
+pub struct Finding {
+    pub kind: FindingKind,
+    pub tree: String,
+    /// Position of the node in the tree, see `node_path`
+    pub path: String,
+    pub message: String,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}: {}", self.tree, self.path, self.message)
+    }
+}
+
+/// Findings of every tree in the library
+pub fn analyse_library(library: &Library) -> Vec<Finding> {
+    library
+        .trees
+        .iter()
+        .flat_map(|tree| analyse_tree(tree, library))
+        .collect()
+}
+
+pub fn analyse_tree(tree: &BehaviorTreeFile, library: &Library) -> Vec<Finding> {
+    let mut analyser = Analyser {
+        tree,
+        library,
+        findings: Vec::new(),
+    };
+    analyser.node(&tree.tree, &tree.tree.name);
+    analyser.findings
+}
+
+/// True if nothing below `node` can make it fail
+///
+/// Leaves without modules to call or an expression to evaluate always succeed, as do nodes
+/// forced to succeed. A timeout can always fail.
+pub fn never_fails(node: &Node, library: &Library) -> bool {
+    let undecorated = match &node.sequence {
+        Some(Sequence::Children(children)) => children.iter().all(|c| never_fails(c, library)),
+        Some(Sequence::Fallback(children)) => children.iter().any(|c| never_fails(c, library)),
+        Some(Sequence::Parallel {
+            success_threshold,
+            children,
+        }) => {
+            let infallible = children.iter().filter(|c| never_fails(c, library)).count();
+            infallible >= success_threshold.unwrap_or(children.len())
+        }
+        None => library
+            .nodes
+            .content
+            .get(&node.name)
+            .is_some_and(|n| n.zenoh.is_none() && n.expression.is_none()),
+    };
+    node.decorators
+        .iter()
+        .fold(undecorated, |never_fails, decorator| match decorator {
+            Decorator::ForceSuccess => true,
+            Decorator::Invert | Decorator::Timeout { .. } => false,
+            Decorator::Retry { .. } | Decorator::RepeatUntilSuccess { .. } => never_fails,
+        })
+}
+
+struct Analyser<'a> {
+    tree: &'a BehaviorTreeFile,
+    library: &'a Library,
+    findings: Vec<Finding>,
+}
+
+impl<'a> Analyser<'a> {
+    fn report(&mut self, kind: FindingKind, path: &str, message: String) {
+        self.findings.push(Finding {
+            kind,
+            tree: self.tree.tree.name.clone(),
+            path: path.to_string(),
+            message,
+        });
+    }
+
+    fn node(&mut self, node: &Node, path: &str) {
+        if let Some(error) = &node.error {
+            if never_fails(node, self.library) {
+                self.report(
+                    FindingKind::UnreachableError,
+                    path,
+                    format!(
+                        "{} cannot fail, so error {} is never raised",
+                        node.name, error
+                    ),
+                );
+            }
+        }
+
+        let sequence = match &node.sequence {
+            Some(sequence) => sequence,
+            None => return,
+        };
+        if sequence.nodes().is_empty() {
+            self.report(
+                FindingKind::EmptySequence,
+                path,
+                format!("{} has no children", node.name),
+            );
+        }
+        match sequence {
+            Sequence::Fallback(children) => {
+                if let Some(index) = children.iter().position(|c| never_fails(c, self.library)) {
+                    for (skipped, child) in children.iter().enumerate().skip(index + 1) {
+                        self.report(
+                            FindingKind::UnreachableChild,
+                            &node_path(path, skipped, child),
+                            format!(
+                                "{} is never run, {} before it cannot fail",
+                                child.name, children[index].name
+                            ),
+                        );
+                    }
+                }
+            }
+            Sequence::Children(children) => self.contradictions(children, path),
+            Sequence::Parallel { .. } => {}
+        }
+        for (index, child) in sequence.nodes().iter().enumerate() {
+            self.node(child, &node_path(path, index, child));
+        }
+    }
+
+    /// What a condition leaf asserts when the sequence carries on after it
+    ///
+    /// Conditions without an expression stand for a boolean named after the node.
+    fn assertion(&self, node: &Node) -> Option<Expression> {
+        if node.sequence.is_some() || node.decorators.iter().any(|d| *d != Decorator::Invert) {
+            return None;
+        }
+        let known_node = self.library.nodes.content.get(&node.name)?;
+        if known_node.node_type != NodeType::Condition {
+            return None;
+        }
+        let mut expression = match &known_node.expression {
+            Some(expression) => Expression::parse(expression).ok()?,
+            None => Expression::Key(condition_key(&node.name)),
+        };
+        for _ in &node.decorators {
+            expression = Expression::Not(Box::new(expression));
+        }
+        Some(expression)
+    }
+
+    /// Compare the condition a sequence starts with to the conditions after it, up to
+    /// the point where the nodes in between may change what it depends on
+    fn contradictions(&mut self, children: &[Node], path: &str) {
+        let first = match children.first().and_then(|c| self.assertion(c)) {
+            Some(first) => first,
+            None => return,
+        };
+        let depends_on = first
+            .keys()
+            .into_iter()
+            .map(String::from)
+            .collect::<BTreeSet<String>>();
+        for (index, child) in children.iter().enumerate().skip(1) {
+            match self.assertion(child) {
+                Some(condition) => {
+                    if !self.satisfiable(&[&first, &condition]) {
+                        self.report(
+                            FindingKind::Contradiction,
+                            &node_path(path, index, child),
+                            format!(
+                                "{} can never hold after {}, the sequence always fails",
+                                child.name, children[0].name
+                            ),
+                        );
+                    }
+                }
+                None => {
+                    let changed = self.changes(child);
+                    if changed.iter().any(|key| depends_on.contains(key)) {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Blackboard keys written below `node`, and the condition keys of the hardware if it runs actions
+    fn changes(&self, node: &Node) -> BTreeSet<String> {
+        let mut changed = BTreeSet::new();
+        match &node.sequence {
+            Some(sequence) => {
+                for child in sequence.nodes() {
+                    changed.extend(self.changes(child));
+                }
+            }
+            None => {
+                if let Some(known_node) = self.library.nodes.content.get(&node.name) {
+                    changed.extend(known_node.writes.iter().cloned());
+                    if known_node.node_type != NodeType::Condition {
+                        changed.extend(
+                            self.library
+                                .nodes
+                                .content
+                                .iter()
+                                .filter(|(_, n)| {
+                                    n.node_type == NodeType::Condition && n.expression.is_none()
+                                })
+                                .map(|(name, _)| condition_key(name)),
+                        );
+                    }
+                }
+            }
+        }
+        changed
+    }
+
+    /// Search for values of the keys that make every expression true
+    ///
+    /// Numbers are tried around every literal they are compared with. Expressions that
+    /// cannot be evaluated, or need too many assignments, count as satisfiable.
+    fn satisfiable(&self, expressions: &[&Expression]) -> bool {
+        let mut keys = BTreeSet::new();
+        let mut literals = Vec::new();
+        for expression in expressions {
+            collect(expression, &mut keys, &mut literals);
+        }
+
+        let mut declared = BTreeMap::new();
+        let mut candidates = Vec::new();
+        for name in &keys {
+            let key_type = match self.tree.blackboard.get(name) {
+                Some(key) => key.key_type,
+                None if name.starts_with('?') => KeyType::Bool,
+                None => return true,
+            };
+            declared.insert(
+                name.clone(),
+                BlackboardKey {
+                    key_type,
+                    description: String::new(),
+                    unit: None,
+                    default: None,
+                },
+            );
+            candidates.push((name.clone(), candidate_values(key_type, &literals)));
+        }
+        let assignments = candidates
+            .iter()
+            .map(|(_, values)| values.len())
+            .product::<usize>();
+        if assignments > MAX_ASSIGNMENTS {
+            return true;
+        }
+
+        for mut assignment in 0..assignments {
+            let mut blackboard = Blackboard::default();
+            if blackboard.declare(&declared).is_err() {
+                return true;
+            }
+            for (name, values) in &candidates {
+                let value = values[assignment % values.len()].clone();
+                assignment /= values.len();
+                if blackboard.set(name, value).is_err() {
+                    return true;
+                }
+            }
+            let holds = expressions
+                .iter()
+                .map(|e| e.evaluate(&blackboard))
+                .collect::<Result<Vec<bool>, _>>();
+            match holds {
+                Ok(holds) if holds.iter().all(|h| *h) => return true,
+                Ok(_) => {}
+                Err(_) => return true,
+            }
+        }
+        false
+    }
+}
+
+/// Boolean standing for the result of a condition node without an expression
+fn condition_key(node: &str) -> String {
+    format!("?{}", node)
+}
+
+fn collect(
+    expression: &Expression,
+    keys: &mut BTreeSet<String>,
+    literals: &mut Vec<BlackboardValue>,
+) {
+    match expression {
+        Expression::Literal(value) => literals.push(value.clone()),
+        Expression::Key(name) => {
+            keys.insert(name.clone());
+        }
+        Expression::Not(inner) => collect(inner, keys, literals),
+        Expression::And(left, right)
+        | Expression::Or(left, right)
+        | Expression::Compare(left, _, right) => {
+            collect(left, keys, literals);
+            collect(right, keys, literals);
+        }
+    }
+}
+
+fn candidate_values(key_type: KeyType, literals: &[BlackboardValue]) -> Vec<BlackboardValue> {
+    let mut numbers = literals
+        .iter()
+        .filter_map(|l| l.as_number())
+        .collect::<Vec<f64>>();
+    numbers.push(0.0);
+    numbers.sort_by(|a, b| a.total_cmp(b));
+    numbers.dedup();
+    match key_type {
+        KeyType::Bool => vec![BlackboardValue::Bool(false), BlackboardValue::Bool(true)],
+        KeyType::Integer => {
+            let mut integers = numbers
+                .iter()
+                .flat_map(|n| [n.floor() - 1.0, n.floor(), n.ceil(), n.ceil() + 1.0])
+                .map(|n| n as i64)
+                .collect::<Vec<i64>>();
+            integers.sort();
+            integers.dedup();
+            integers.into_iter().map(BlackboardValue::Integer).collect()
+        }
+        KeyType::Number => {
+            let mut values = numbers
+                .iter()
+                .flat_map(|n| [n - 1.0, *n, n + 1.0])
+                .collect::<Vec<f64>>();
+            values.extend(numbers.windows(2).map(|w| (w[0] + w[1]) / 2.0));
+            values.sort_by(|a, b| a.total_cmp(b));
+            values.dedup();
+            values.into_iter().map(BlackboardValue::Number).collect()
+        }
+        KeyType::String => {
+            let mut strings = literals
+                .iter()
+                .filter_map(|l| match l {
+                    BlackboardValue::String(s) => Some(s.clone()),
+                    _ => None,
+                })
+                .collect::<Vec<String>>();
+            strings.push(String::new());
+            strings.sort();
+            strings.dedup();
+            strings.into_iter().map(BlackboardValue::String).collect()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conf::{KnownNode, ReplyMode, Zenoh};
+
+    fn known_node(node_type: NodeType, gantry: bool, expression: Option<&str>) -> KnownNode {
+        KnownNode {
+            node_type,
+            zenoh: gantry.then(|| Zenoh {
+                modules: vec!["gantry".to_string()],
+                min_reply: ReplyMode::Any,
+            }),
+            description: String::new(),
+            reads: Vec::new(),
+            writes: Vec::new(),
+            expression: expression.map(String::from),
+            duration: None,
+            consumes: BTreeMap::new(),
+            error: None,
+            preconditions: Vec::new(),
+            postconditions: Vec::new(),
+            needs_tool: None,
+        }
+    }
+
+    fn node(name: &str, sequence: Option<Sequence>, decorators: Vec<Decorator>) -> Node {
+        Node {
+            name: name.to_string(),
+            step_number: 0,
+            sequence,
+            error: None,
+            decorators,
+        }
+    }
+
+    fn library() -> Library {
+        let mut library = Library::default();
+        let nodes = &mut library.nodes.content;
+        nodes.insert(
+            "is_tip_available".to_string(),
+            known_node(NodeType::Condition, true, None),
+        );
+        nodes.insert(
+            "has_volume".to_string(),
+            known_node(NodeType::Condition, false, Some("volume >= 10")),
+        );
+        nodes.insert(
+            "is_nearly_empty".to_string(),
+            known_node(NodeType::Condition, false, Some("volume < 5")),
+        );
+        nodes.insert(
+            "get_tip".to_string(),
+            known_node(NodeType::Action, true, None),
+        );
+        nodes.insert("log".to_string(), known_node(NodeType::Action, false, None));
+        library
+    }
+
+    fn tree(root: Node) -> BehaviorTreeFile {
+        serde_yaml::from_str::<BehaviorTreeFile>(
+            "{title: t, version: 0.1.0, description: d, participant_modules: [], \
+             blackboard: {volume: {type: number, description: v}}, \
+             tree: {name: root, step_number: 0, sequence: null, error: null}}",
+        )
+        .map(|mut file| {
+            file.tree = root;
+            file
+        })
+        .unwrap()
+    }
+
+    fn kinds(root: Node) -> Vec<(FindingKind, String)> {
+        let library = library();
+        analyse_tree(&tree(root), &library)
+            .into_iter()
+            .map(|f| (f.kind, f.path))
+            .collect()
+    }
+
+    #[test]
+    fn flags_children_after_an_infallible_one() {
+        let mut forced = node("get_tip", None, vec![Decorator::ForceSuccess]);
+        forced.error = Some("no_tip".to_string());
+        let root = node(
+            "root",
+            Some(Sequence::Fallback(vec![
+                node("log", None, Vec::new()),
+                forced,
+                node("empty", Some(Sequence::Children(Vec::new())), Vec::new()),
+            ])),
+            Vec::new(),
+        );
+        assert_eq!(
+            kinds(root),
+            vec![
+                (FindingKind::UnreachableChild, "root/1.get_tip".to_string()),
+                (FindingKind::UnreachableChild, "root/2.empty".to_string()),
+                (FindingKind::UnreachableError, "root/1.get_tip".to_string()),
+                (FindingKind::EmptySequence, "root/2.empty".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn flags_contradicting_conditions() {
+        let root = node(
+            "root",
+            Some(Sequence::Children(vec![
+                node("has_volume", None, Vec::new()),
+                node("log", None, Vec::new()),
+                node("is_nearly_empty", None, Vec::new()),
+            ])),
+            Vec::new(),
+        );
+        assert_eq!(
+            kinds(root),
+            vec![(
+                FindingKind::Contradiction,
+                "root/2.is_nearly_empty".to_string()
+            )]
+        );
+
+        // The action in between may change the state of the tip
+        let root = node(
+            "root",
+            Some(Sequence::Children(vec![
+                node("is_tip_available", None, Vec::new()),
+                node("is_tip_available", None, vec![Decorator::Invert]),
+                node("get_tip", None, Vec::new()),
+                node("is_tip_available", None, vec![Decorator::Invert]),
+            ])),
+            Vec::new(),
+        );
+        assert_eq!(
+            kinds(root),
+            vec![(
+                FindingKind::Contradiction,
+                "root/1.is_tip_available".to_string()
+            )]
+        );
+    }
+}
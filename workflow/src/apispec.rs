@@ -0,0 +1,305 @@
+//! AsyncAPI and OpenAPI documents of the modules, for integration partners
+//!
+//! Every service of a module is a channel at `<modules endpoint>/<api endpoint>/<service>` in
+//! AsyncAPI and a path `/<service>` in OpenAPI. Parameters and responses are JSON objects with a
+//! property per value, units are `x-unit` and timeouts `x-timeout-ms`.
+
+use crate::conf::{FileFormat, Module, ModuleFile, RequestSchema, RequestType, ValueSchema};
+use log::debug;
+use serde_json::{json, Map, Value};
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+fn method(request_type: &RequestType) -> &'static str {
+    match request_type {
+        RequestType::GET => "get",
+        RequestType::POST => "post",
+        RequestType::PUT => "put",
+        RequestType::DELETE => "delete",
+    }
+}
+
+fn value_schema(value: &ValueSchema) -> Result<Value, Box<dyn Error>> {
+    let mut schema = match value.data_type.as_str() {
+        "float" | "double" => json!({"type": "number", "format": value.data_type}),
+        "number" => json!({"type": "number"}),
+        "int" | "integer" => json!({"type": "integer"}),
+        "bool" | "boolean" => json!({"type": "boolean"}),
+        "str" | "string" => json!({"type": "string"}),
+        // Left open, the type is only a hint
+        other => json!({"format": other}),
+    };
+    schema["description"] = json!(value.description);
+    if let Some(unit) = &value.unit {
+        schema["x-unit"] = serde_json::to_value(unit)?;
+    }
+    Ok(schema)
+}
+
+/// An object with a required property per value
+fn values_schema(values: &Option<Vec<ValueSchema>>) -> Result<Value, Box<dyn Error>> {
+    let values = values.as_deref().unwrap_or_default();
+    let mut properties = Map::new();
+    for value in values {
+        properties.insert(value.name.clone(), value_schema(value)?);
+    }
+    let required = values.iter().map(|v| v.name.as_str()).collect::<Vec<_>>();
+    let mut schema = json!({"type": "object", "properties": properties});
+    if !required.is_empty() {
+        schema["required"] = json!(required);
+    }
+    Ok(schema)
+}
+
+fn get_module<'a>(module_file: &'a ModuleFile, abbr: &str) -> Result<&'a Module, Box<dyn Error>> {
+    module_file
+        .content
+        .get(abbr)
+        .ok_or(format!("Module {} is not in the modules file", abbr).into())
+}
+
+fn info(module_file: &ModuleFile, module: &Module) -> Value {
+    json!({
+        "title": module.info.name,
+        "version": module_file.version,
+        "description": module.info.description,
+        "x-module-type": module.info.module_type,
+        "x-access": module.info.access,
+    })
+}
+
+/// Requests of a module sorted by service and method
+fn requests(module: &Module) -> Vec<(&String, &RequestType, &RequestSchema)> {
+    let mut requests = module
+        .api
+        .services
+        .iter()
+        .flat_map(|(name, service)| {
+            service
+                .requests()
+                .iter()
+                .map(move |(request_type, request)| (name, request_type, request))
+        })
+        .collect::<Vec<_>>();
+    requests.sort_by_key(|(name, request_type, _)| (name.as_str(), method(request_type)));
+    requests
+}
+
+/// `name` as a single token of a JSON Pointer, services are keyed by path so they may hold `/`
+fn pointer_token(name: &str) -> String {
+    name.replace('~', "~0").replace('/', "~1")
+}
+
+fn json_content(schema: Value) -> Value {
+    json!({"application/json": {"schema": schema}})
+}
+
+fn timeout_ms(request: &RequestSchema) -> Result<u128, Box<dyn Error>> {
+    Ok(request.timeout_duration()?.as_millis())
+}
+
+/// AsyncAPI 3.0 document of the requests a module answers on the message bus
+pub fn module_asyncapi(module_file: &ModuleFile, abbr: &str) -> Result<Value, Box<dyn Error>> {
+    let module = get_module(module_file, abbr)?;
+    let mut channels = Map::new();
+    let mut operations = Map::new();
+    for (service, request_type, request) in requests(module) {
+        let method = method(request_type);
+        let channel = channels.entry(service.clone()).or_insert_with(|| {
+            json!({
+                "address": format!("{}/{}/{}", module_file.endpoint, module.api.endpoint, service),
+                "messages": {},
+            })
+        });
+        for (suffix, values) in [
+            ("request", &request.parameters),
+            ("reply", &request.response),
+        ] {
+            channel["messages"][format!("{}_{}", method, suffix)] = json!({
+                "name": format!("{}_{}_{}", service, method, suffix),
+                "summary": format!("{} {}", request.summary, suffix),
+                "payload": values_schema(values)?,
+            });
+        }
+        let channel = format!("#/channels/{}", pointer_token(service));
+        let reference = |suffix: &str| {
+            let message = format!("{}/messages/{}_{}", channel, method, suffix);
+            json!({ "$ref": message })
+        };
+        let channel_ref = json!({ "$ref": channel });
+        operations.insert(
+            format!("{}_{}", service, method),
+            json!({
+                "action": "receive",
+                "channel": channel_ref,
+                "summary": request.summary,
+                "messages": [reference("request")],
+                "reply": {"channel": channel_ref, "messages": [reference("reply")]},
+                "x-method": method,
+                "x-timeout-ms": timeout_ms(request)?,
+            }),
+        );
+    }
+    Ok(json!({
+        "asyncapi": "3.0.0",
+        "info": info(module_file, module),
+        "defaultContentType": "application/json",
+        "channels": channels,
+        "operations": operations,
+    }))
+}
+
+/// OpenAPI 3.0 document of a module behind an HTTP gateway
+pub fn module_openapi(module_file: &ModuleFile, abbr: &str) -> Result<Value, Box<dyn Error>> {
+    let module = get_module(module_file, abbr)?;
+    let mut paths = Map::new();
+    for (service, request_type, request) in requests(module) {
+        let method = method(request_type);
+        let mut operation = json!({
+            "operationId": format!("{}_{}", service, method),
+            "summary": request.summary,
+            "x-timeout-ms": timeout_ms(request)?,
+            "responses": {
+                "200": {
+                    "description": format!("{} reply", request.summary),
+                    "content": json_content(values_schema(&request.response)?),
+                },
+                "504": {"description": format!("No reply within {}", request.timeout)},
+            },
+        });
+        match request_type {
+            RequestType::POST | RequestType::PUT => {
+                operation["requestBody"] = json!({
+                    "required": true,
+                    "content": json_content(values_schema(&request.parameters)?),
+                });
+            }
+            RequestType::GET | RequestType::DELETE => {
+                let mut parameters = Vec::new();
+                for value in request.parameters.iter().flatten() {
+                    parameters.push(json!({
+                        "name": value.name,
+                        "in": "query",
+                        "required": true,
+                        "description": value.description,
+                        "schema": value_schema(value)?,
+                    }));
+                }
+                if !parameters.is_empty() {
+                    operation["parameters"] = json!(parameters);
+                }
+            }
+        }
+        paths
+            .entry(format!("/{}", service))
+            .or_insert_with(|| json!({}))[method] = operation;
+    }
+    Ok(json!({
+        "openapi": "3.0.3",
+        "info": info(module_file, module),
+        "servers": [{"url": format!("/{}/{}", module_file.endpoint, module.api.endpoint)}],
+        "paths": paths,
+    }))
+}
+
+/// Write `<module>.asyncapi.<ext>` and `<module>.openapi.<ext>` of the modules, all of them if
+/// `modules` is empty
+pub fn write_api_specs(
+    module_file: &ModuleFile,
+    modules: &[String],
+    output_dir: &Path,
+    format: FileFormat,
+) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut abbrs = match modules.is_empty() {
+        true => module_file.content.keys().cloned().collect(),
+        false => modules.to_vec(),
+    };
+    abbrs.sort();
+    fs::create_dir_all(output_dir)?;
+    let mut written = Vec::new();
+    for abbr in abbrs {
+        for (kind, document) in [
+            ("asyncapi", module_asyncapi(module_file, &abbr)?),
+            ("openapi", module_openapi(module_file, &abbr)?),
+        ] {
+            let path = output_dir.join(format!("{}.{}.{}", abbr, kind, format.extension()));
+            fs::write(&path, format.write(&document)?)?;
+            debug!("{} of {} written to {:?}", kind, abbr, path);
+            written.push(path);
+        }
+    }
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn module_file() -> ModuleFile {
+        serde_yaml::from_str(
+            "{title: m, description: d, version: 0.2.0, endpoint: genotyper, content: {\
+             cfg: {info: {name: Centrifuge, type: spin, description: d}, api: {endpoint: centrifuge, services: {\
+             spin: {post: {summary: Spin, timeout: 1m, \
+              parameters: [{name: speed, type: float, description: rpm}, {name: time, type: int, description: t, unit: s}], \
+              response: [{name: temperature, type: float, description: t, unit: C}]}, \
+             get: {summary: Spin state, timeout: 500ms, parameters: [{name: rotor, type: int, description: r}]}}}}}}}",
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn specs_carry_units_and_timeouts() {
+        let module_file = module_file();
+        let asyncapi = module_asyncapi(&module_file, "cfg").unwrap();
+        assert_eq!(asyncapi["info"]["version"], "0.2.0");
+        assert_eq!(
+            asyncapi["channels"]["spin"]["address"],
+            "genotyper/centrifuge/spin"
+        );
+        let request = &asyncapi["channels"]["spin"]["messages"]["post_request"]["payload"];
+        assert_eq!(request["properties"]["time"]["x-unit"], "s");
+        assert_eq!(request["required"], json!(["speed", "time"]));
+        let operation = &asyncapi["operations"]["spin_post"];
+        assert_eq!(operation["x-timeout-ms"], 60000);
+        assert_eq!(
+            operation["reply"]["messages"][0]["$ref"],
+            "#/channels/spin/messages/post_reply"
+        );
+
+        let openapi = module_openapi(&module_file, "cfg").unwrap();
+        let post = &openapi["paths"]["/spin"]["post"];
+        let body = &post["requestBody"]["content"]["application/json"]["schema"];
+        assert_eq!(body["properties"]["speed"]["type"], "number");
+        let reply = &post["responses"]["200"]["content"]["application/json"]["schema"];
+        assert_eq!(reply["properties"]["temperature"]["x-unit"], "C");
+        let get = &openapi["paths"]["/spin"]["get"];
+        assert_eq!(get["x-timeout-ms"], 500);
+        assert_eq!(get["parameters"][0]["in"], "query");
+        assert!(get.get("requestBody").is_none());
+
+        assert!(module_openapi(&module_file, "xyz").is_err());
+    }
+
+    #[test]
+    fn references_escape_service_paths() {
+        let mut module_file = module_file();
+        let module = module_file.content.get_mut("cfg").unwrap();
+        let spin = module.api.services.remove("spin").unwrap();
+        module
+            .api
+            .services
+            .insert("rotor/speed~max".to_string(), spin);
+        let asyncapi = module_asyncapi(&module_file, "cfg").unwrap();
+        let operation = &asyncapi["operations"]["rotor/speed~max_post"];
+        assert_eq!(operation["channel"]["$ref"], "#/channels/rotor~1speed~0max");
+        assert_eq!(
+            operation["messages"][0]["$ref"],
+            "#/channels/rotor~1speed~0max/messages/post_request"
+        );
+        assert_eq!(
+            asyncapi["channels"]["rotor/speed~max"]["address"],
+            "genotyper/centrifuge/rotor/speed~max"
+        );
+    }
+}
@@ -0,0 +1,394 @@
+use crate::conf::{BlackboardKey, BlackboardValue, KeyType};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::error::Error;
+
+/// Values shared between the nodes of a run, keyed by the names declared in the trees
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Blackboard {
+    keys: BTreeMap<String, KeyType>,
+    values: BTreeMap<String, BlackboardValue>,
+}
+
+impl Blackboard {
+    /// Add the keys of a tree, setting their defaults if they have no value yet
+    pub fn declare(
+        &mut self,
+        keys: &BTreeMap<String, BlackboardKey>,
+    ) -> Result<(), Box<dyn Error>> {
+        for (name, key) in keys {
+            match self.keys.get(name) {
+                Some(key_type) if *key_type != key.key_type => {
+                    return Err(format!(
+                        "Blackboard key {} is declared as {:?} and {:?}",
+                        name, key_type, key.key_type
+                    )
+                    .into())
+                }
+                _ => {
+                    self.keys.insert(name.clone(), key.key_type);
+                }
+            }
+            if let (Some(default), false) = (&key.default, self.values.contains_key(name)) {
+                self.set(name, default.clone())?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&BlackboardValue> {
+        self.values.get(name)
+    }
+
+    pub fn values(&self) -> &BTreeMap<String, BlackboardValue> {
+        &self.values
+    }
+
+    pub fn set(&mut self, name: &str, value: BlackboardValue) -> Result<(), Box<dyn Error>> {
+        let key_type = self
+            .keys
+            .get(name)
+            .ok_or(format!("Blackboard key {} is not declared", name))?;
+        let value = match (key_type, value) {
+            (KeyType::Number, BlackboardValue::Integer(i)) => BlackboardValue::Number(i as f64),
+            (key_type, value) if value.key_type() == *key_type => value,
+            (key_type, value) => {
+                return Err(format!("{} is a {:?}, not {:?}", name, key_type, value).into())
+            }
+        };
+        self.values.insert(name.to_string(), value);
+        Ok(())
+    }
+
+    /// Store a value from a module response, converted to the declared type of the key
+    pub fn set_number(&mut self, name: &str, number: f64) -> Result<(), Box<dyn Error>> {
+        let value = match self.keys.get(name) {
+            Some(KeyType::Bool) => BlackboardValue::Bool(number != 0.0),
+            Some(KeyType::Integer) => BlackboardValue::Integer(number.round() as i64),
+            Some(KeyType::Number) => BlackboardValue::Number(number),
+            Some(KeyType::String) => BlackboardValue::String(number.to_string()),
+            None => return Err(format!("Blackboard key {} is not declared", name).into()),
+        };
+        self.set(name, value)
+    }
+
+    /// The values of `names` that are set
+    pub fn select(&self, names: &[String]) -> BTreeMap<String, BlackboardValue> {
+        names
+            .iter()
+            .filter_map(|n| self.values.get(n).map(|v| (n.clone(), v.clone())))
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+/// A boolean expression over blackboard keys, e.g. `tip_slot >= 0 && !tray_empty`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expression {
+    Literal(BlackboardValue),
+    Key(String),
+    Not(Box<Expression>),
+    And(Box<Expression>, Box<Expression>),
+    Or(Box<Expression>, Box<Expression>),
+    Compare(Box<Expression>, Comparison, Box<Expression>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Literal(BlackboardValue),
+    Key(String),
+    Not,
+    And,
+    Or,
+    Compare(Comparison),
+    Open,
+    Close,
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, Box<dyn Error>> {
+    let chars = text.chars().collect::<Vec<char>>();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        let (token, length) = match (c, next) {
+            (c, _) if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            ('(', _) => (Token::Open, 1),
+            (')', _) => (Token::Close, 1),
+            ('&', Some('&')) => (Token::And, 2),
+            ('|', Some('|')) => (Token::Or, 2),
+            ('=', Some('=')) => (Token::Compare(Comparison::Equal), 2),
+            ('!', Some('=')) => (Token::Compare(Comparison::NotEqual), 2),
+            ('<', Some('=')) => (Token::Compare(Comparison::LessOrEqual), 2),
+            ('>', Some('=')) => (Token::Compare(Comparison::GreaterOrEqual), 2),
+            ('<', _) => (Token::Compare(Comparison::Less), 1),
+            ('>', _) => (Token::Compare(Comparison::Greater), 1),
+            ('!', _) => (Token::Not, 1),
+            ('\'', _) | ('"', _) => {
+                let end = chars[i + 1..]
+                    .iter()
+                    .position(|q| *q == c)
+                    .ok_or(format!("Unterminated string in {}", text))?;
+                let string = chars[i + 1..i + 1 + end].iter().collect::<String>();
+                (Token::Literal(BlackboardValue::String(string)), end + 2)
+            }
+            (c, _) if c.is_ascii_digit() || c == '-' => {
+                let length = 1 + chars[i + 1..]
+                    .iter()
+                    .take_while(|d| d.is_ascii_digit() || **d == '.')
+                    .count();
+                let number = chars[i..i + length].iter().collect::<String>();
+                let value = match number.parse::<i64>() {
+                    Ok(integer) => BlackboardValue::Integer(integer),
+                    Err(_) => BlackboardValue::Number(
+                        number
+                            .parse()
+                            .map_err(|_| format!("Invalid number {} in {}", number, text))?,
+                    ),
+                };
+                (Token::Literal(value), length)
+            }
+            (c, _) if c.is_alphabetic() || c == '_' => {
+                let length = chars[i..]
+                    .iter()
+                    .take_while(|d| d.is_alphanumeric() || **d == '_')
+                    .count();
+                let word = chars[i..i + length].iter().collect::<String>();
+                let token = match word.as_str() {
+                    "true" => Token::Literal(BlackboardValue::Bool(true)),
+                    "false" => Token::Literal(BlackboardValue::Bool(false)),
+                    _ => Token::Key(word),
+                };
+                (token, length)
+            }
+            (c, _) => return Err(format!("Unexpected '{}' in {}", c, text).into()),
+        };
+        tokens.push(token);
+        i += length;
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn or(&mut self) -> Result<Expression, Box<dyn Error>> {
+        let mut left = self.and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.next();
+            left = Expression::Or(Box::new(left), Box::new(self.and()?));
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<Expression, Box<dyn Error>> {
+        let mut left = self.unary()?;
+        while self.peek() == Some(&Token::And) {
+            self.next();
+            left = Expression::And(Box::new(left), Box::new(self.unary()?));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expression, Box<dyn Error>> {
+        if self.peek() == Some(&Token::Not) {
+            self.next();
+            return Ok(Expression::Not(Box::new(self.unary()?)));
+        }
+        let left = self.primary()?;
+        if let Some(Token::Compare(comparison)) = self.peek().cloned() {
+            self.next();
+            let right = self.primary()?;
+            return Ok(Expression::Compare(
+                Box::new(left),
+                comparison,
+                Box::new(right),
+            ));
+        }
+        Ok(left)
+    }
+
+    fn primary(&mut self) -> Result<Expression, Box<dyn Error>> {
+        match self.next() {
+            Some(Token::Open) => {
+                let inner = self.or()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(inner),
+                    _ => Err("Expected ')'".into()),
+                }
+            }
+            Some(Token::Literal(value)) => Ok(Expression::Literal(value)),
+            Some(Token::Key(name)) => Ok(Expression::Key(name)),
+            Some(token) => Err(format!("Unexpected {:?}", token).into()),
+            None => Err("Unexpected end of expression".into()),
+        }
+    }
+}
+
+impl Expression {
+    pub fn parse(text: &str) -> Result<Self, Box<dyn Error>> {
+        let mut parser = Parser {
+            tokens: tokenize(text)?,
+            position: 0,
+        };
+        let expression = parser
+            .or()
+            .map_err(|e| format!("Invalid expression '{}': {}", text, e))?;
+        if parser.position < parser.tokens.len() {
+            return Err(format!("Invalid expression '{}': unexpected trailing input", text).into());
+        }
+        Ok(expression)
+    }
+
+    /// The blackboard keys used by the expression
+    pub fn keys(&self) -> Vec<&str> {
+        match self {
+            Expression::Literal(_) => Vec::new(),
+            Expression::Key(name) => vec![name.as_str()],
+            Expression::Not(inner) => inner.keys(),
+            Expression::And(left, right)
+            | Expression::Or(left, right)
+            | Expression::Compare(left, _, right) => {
+                let mut keys = left.keys();
+                keys.extend(right.keys());
+                keys
+            }
+        }
+    }
+
+    fn value(&self, blackboard: &Blackboard) -> Result<BlackboardValue, Box<dyn Error>> {
+        let truth = |e: &Expression| -> Result<bool, Box<dyn Error>> {
+            match e.value(blackboard)? {
+                BlackboardValue::Bool(b) => Ok(b),
+                other => Err(format!("{:?} is not a boolean", other).into()),
+            }
+        };
+        let value = match self {
+            Expression::Literal(value) => value.clone(),
+            Expression::Key(name) => blackboard
+                .get(name)
+                .cloned()
+                .ok_or(format!("Blackboard key {} is not set", name))?,
+            Expression::Not(inner) => BlackboardValue::Bool(!truth(inner)?),
+            Expression::And(left, right) => BlackboardValue::Bool(truth(left)? && truth(right)?),
+            Expression::Or(left, right) => BlackboardValue::Bool(truth(left)? || truth(right)?),
+            Expression::Compare(left, comparison, right) => {
+                let (left, right) = (left.value(blackboard)?, right.value(blackboard)?);
+                BlackboardValue::Bool(compare(&left, *comparison, &right)?)
+            }
+        };
+        Ok(value)
+    }
+
+    pub fn evaluate(&self, blackboard: &Blackboard) -> Result<bool, Box<dyn Error>> {
+        match self.value(blackboard)? {
+            BlackboardValue::Bool(b) => Ok(b),
+            other => Err(format!("{:?} is not a boolean", other).into()),
+        }
+    }
+}
+
+fn compare(
+    left: &BlackboardValue,
+    comparison: Comparison,
+    right: &BlackboardValue,
+) -> Result<bool, Box<dyn Error>> {
+    let ordering = match (left, right) {
+        (BlackboardValue::String(l), BlackboardValue::String(r)) => l.partial_cmp(r),
+        (BlackboardValue::Bool(l), BlackboardValue::Bool(r)) => l.partial_cmp(r),
+        (l, r) => match (l.as_number(), r.as_number()) {
+            (Some(l), Some(r)) => l.partial_cmp(&r),
+            _ => return Err(format!("Cannot compare {:?} with {:?}", l, r).into()),
+        },
+    }
+    .ok_or(format!("Cannot compare {:?} with {:?}", left, right))?;
+    Ok(match comparison {
+        Comparison::Equal => ordering.is_eq(),
+        Comparison::NotEqual => ordering.is_ne(),
+        Comparison::Less => ordering.is_lt(),
+        Comparison::LessOrEqual => ordering.is_le(),
+        Comparison::Greater => ordering.is_gt(),
+        Comparison::GreaterOrEqual => ordering.is_ge(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blackboard() -> Blackboard {
+        let mut keys = BTreeMap::new();
+        let key = |key_type, default| BlackboardKey {
+            key_type,
+            description: String::new(),
+            unit: None,
+            default,
+        };
+        keys.insert(
+            "tip_slot".to_string(),
+            key(KeyType::Integer, Some(BlackboardValue::Integer(3))),
+        );
+        keys.insert("volume".to_string(), key(KeyType::Number, None));
+        keys.insert(
+            "tray_empty".to_string(),
+            key(KeyType::Bool, Some(BlackboardValue::Bool(false))),
+        );
+        let mut blackboard = Blackboard::default();
+        blackboard.declare(&keys).unwrap();
+        blackboard
+    }
+
+    #[test]
+    fn evaluates_expressions() {
+        let mut blackboard = blackboard();
+        blackboard.set_number("volume", 150.0).unwrap();
+
+        let expression = Expression::parse("tip_slot >= 0 && !tray_empty").unwrap();
+        assert!(expression.evaluate(&blackboard).unwrap());
+        let expression = Expression::parse("(volume < 100 || tip_slot == 4) && true").unwrap();
+        assert!(!expression.evaluate(&blackboard).unwrap());
+        assert_eq!(expression.keys(), vec!["volume", "tip_slot"]);
+    }
+
+    #[test]
+    fn rejects_invalid_expressions_and_types() {
+        assert!(Expression::parse("tip_slot >=").is_err());
+        assert!(Expression::parse("(tip_slot > 1").is_err());
+
+        let mut blackboard = blackboard();
+        assert!(blackboard
+            .set("tip_slot", BlackboardValue::String("A3".to_string()))
+            .is_err());
+        assert!(blackboard
+            .set("unknown", BlackboardValue::Bool(true))
+            .is_err());
+        let expression = Expression::parse("volume > 1").unwrap();
+        assert!(expression.evaluate(&blackboard).is_err());
+    }
+}
@@ -0,0 +1,755 @@
+//! BehaviorTree.CPP v4 XML, the format Groot2 edits, to and from behavior tree files
+//!
+//! Control nodes keep their `name`, leaves are written with the name of the known node as tag
+//! and their blackboard keys as `{key}` ports. The blackboard of the tree is the interface of
+//! its `SubTree` in the `TreeNodesModel`, the error a node raises is an `_onFailure` script and
+//! the title, version, description and participants are attributes of the `BehaviorTree`.
+
+use crate::conf::{
+    node_modules, parse_duration, validate_btree, BehaviorTreeFile, BlackboardKey, BlackboardValue,
+    Decorator, KeyType, Library, Node, NodeType, Sequence, Unit,
+};
+use log::warn;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::error::Error;
+
+const REPEAT_UNTIL_SUCCESS: &str = "repeat_until_success";
+const BACKOFF: &str = "backoff ";
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn line(xml: &mut String, depth: usize, text: &str) {
+    xml.push_str(&"  ".repeat(depth));
+    xml.push_str(text);
+    xml.push('\n');
+}
+
+fn open_tag(tag: &str, attributes: &[(&str, String)]) -> String {
+    let mut text = format!("<{}", tag);
+    for (name, value) in attributes {
+        text.push_str(&format!(" {}=\"{}\"", name, escape(value)));
+    }
+    text
+}
+
+fn cpp_type(key_type: KeyType) -> &'static str {
+    match key_type {
+        KeyType::Bool => "bool",
+        KeyType::Integer => "int",
+        KeyType::Number => "double",
+        KeyType::String => "std::string",
+    }
+}
+
+fn key_type(cpp_type: &str) -> KeyType {
+    match cpp_type {
+        "bool" => KeyType::Bool,
+        "int" | "long" | "unsigned" | "int32_t" | "int64_t" | "uint32_t" | "uint64_t" => {
+            KeyType::Integer
+        }
+        "double" | "float" => KeyType::Number,
+        _ => KeyType::String,
+    }
+}
+
+fn value_text(value: &BlackboardValue) -> String {
+    match value {
+        BlackboardValue::Bool(b) => b.to_string(),
+        BlackboardValue::Integer(i) => i.to_string(),
+        BlackboardValue::Number(n) => n.to_string(),
+        BlackboardValue::String(s) => s.clone(),
+    }
+}
+
+fn parse_value(text: &str, key_type: KeyType) -> Result<BlackboardValue, Box<dyn Error>> {
+    let value = match key_type {
+        KeyType::Bool => text.parse().map(BlackboardValue::Bool).ok(),
+        KeyType::Integer => text.parse().map(BlackboardValue::Integer).ok(),
+        KeyType::Number => text.parse().map(BlackboardValue::Number).ok(),
+        KeyType::String => Some(BlackboardValue::String(text.to_string())),
+    };
+    Ok(value.ok_or(format!("Invalid {:?} default: {}", key_type, text))?)
+}
+
+/// Tag and attributes of an element
+type Element = (&'static str, Vec<(&'static str, String)>);
+
+fn decorator_element(decorator: &Decorator) -> Result<Element, Box<dyn Error>> {
+    Ok(match decorator {
+        Decorator::Retry { attempts, backoff } => {
+            let mut attributes = vec![("num_attempts", attempts.to_string())];
+            if let Some(backoff) = backoff {
+                attributes.insert(0, ("name", format!("{}{}", BACKOFF, backoff)));
+            }
+            ("RetryUntilSuccessful", attributes)
+        }
+        Decorator::RepeatUntilSuccess { max } => (
+            "RetryUntilSuccessful",
+            vec![
+                ("name", REPEAT_UNTIL_SUCCESS.to_string()),
+                ("num_attempts", max.to_string()),
+            ],
+        ),
+        Decorator::Timeout { duration } => (
+            "Timeout",
+            vec![("msec", parse_duration(duration)?.as_millis().to_string())],
+        ),
+        Decorator::Invert => ("Inverter", Vec::new()),
+        Decorator::ForceSuccess => ("ForceSuccess", Vec::new()),
+    })
+}
+
+fn write_node(
+    node: &Node,
+    library: &Library,
+    depth: usize,
+    xml: &mut String,
+) -> Result<(), Box<dyn Error>> {
+    // The error is raised on the result of the outermost decorator
+    let mut on_failure = node
+        .error
+        .as_ref()
+        .map(|error| ("_onFailure", format!("error := '{}'", error)));
+    let mut closing = Vec::new();
+    for decorator in node.decorators.iter().rev() {
+        let (tag, mut attributes) = decorator_element(decorator)?;
+        attributes.extend(on_failure.take());
+        line(
+            xml,
+            depth + closing.len(),
+            &format!("{}>", open_tag(tag, &attributes)),
+        );
+        closing.push(tag);
+    }
+    let inner = depth + closing.len();
+    match &node.sequence {
+        Some(sequence) => {
+            let mut attributes = vec![("name", node.name.clone())];
+            let tag = match sequence {
+                Sequence::Children(_) => "Sequence",
+                Sequence::Fallback(_) => "Fallback",
+                Sequence::Parallel {
+                    success_threshold,
+                    children,
+                } => {
+                    let success = success_threshold.unwrap_or(children.len());
+                    attributes.push(("success_count", success.to_string()));
+                    attributes.push(("failure_count", (children.len() + 1 - success).to_string()));
+                    "Parallel"
+                }
+            };
+            attributes.extend(on_failure.take());
+            line(xml, inner, &format!("{}>", open_tag(tag, &attributes)));
+            for child in sequence.nodes() {
+                write_node(child, library, inner + 1, xml)?;
+            }
+            line(xml, inner, &format!("</{}>", tag));
+        }
+        None => {
+            let mut attributes = Vec::new();
+            if let Some(known) = library.nodes.content.get(&node.name) {
+                for key in known.reads.iter().chain(&known.writes) {
+                    attributes.push((key.as_str(), format!("{{{}}}", key)));
+                }
+            }
+            attributes.extend(on_failure.take());
+            line(
+                xml,
+                inner,
+                &format!("{}/>", open_tag(&node.name, &attributes)),
+            );
+        }
+    }
+    for (i, tag) in closing.iter().enumerate().rev() {
+        line(xml, depth + i, &format!("</{}>", tag));
+    }
+    Ok(())
+}
+
+fn leaves<'a>(node: &'a Node, names: &mut BTreeSet<&'a str>) {
+    match &node.sequence {
+        Some(sequence) => sequence.nodes().iter().for_each(|c| leaves(c, names)),
+        None => {
+            names.insert(&node.name);
+        }
+    }
+}
+
+fn write_port(
+    xml: &mut String,
+    depth: usize,
+    tag: &str,
+    name: &str,
+    key: Option<&BlackboardKey>,
+) -> Result<(), Box<dyn Error>> {
+    let mut attributes = vec![("name", name.to_string())];
+    let key = match key {
+        Some(key) => key,
+        None => {
+            line(xml, depth, &format!("{}/>", open_tag(tag, &attributes)));
+            return Ok(());
+        }
+    };
+    attributes.push(("type", cpp_type(key.key_type).to_string()));
+    if let Some(default) = &key.default {
+        attributes.push(("default", value_text(default)));
+    }
+    if let Some(unit) = &key.unit {
+        let unit = serde_json::to_value(unit)?;
+        attributes.push(("unit", unit.as_str().unwrap_or_default().to_string()));
+    }
+    line(
+        xml,
+        depth,
+        &format!(
+            "{}>{}</{}>",
+            open_tag(tag, &attributes),
+            escape(&key.description),
+            tag
+        ),
+    );
+    Ok(())
+}
+
+/// The tree as a BehaviorTree.CPP v4 document, with the models of its leaves for Groot2
+pub fn tree_to_xml(
+    tree_file: &BehaviorTreeFile,
+    library: &Library,
+) -> Result<String, Box<dyn Error>> {
+    let name = &tree_file.tree.name;
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    line(
+        &mut xml,
+        0,
+        &format!(
+            "{}>",
+            open_tag(
+                "root",
+                &[
+                    ("BTCPP_format", "4".to_string()),
+                    ("main_tree_to_execute", name.clone())
+                ]
+            )
+        ),
+    );
+    let attributes = [
+        ("ID", name.clone()),
+        ("title", tree_file.title.clone()),
+        ("version", tree_file.version.clone()),
+        ("description", tree_file.description.clone()),
+        ("participant_modules", tree_file.participants.join(",")),
+    ];
+    line(
+        &mut xml,
+        1,
+        &format!("{}>", open_tag("BehaviorTree", &attributes)),
+    );
+    write_node(&tree_file.tree, library, 2, &mut xml)?;
+    line(&mut xml, 1, "</BehaviorTree>");
+
+    line(&mut xml, 1, "<TreeNodesModel>");
+    let mut names = BTreeSet::new();
+    leaves(&tree_file.tree, &mut names);
+    for name in names {
+        let known = match library.nodes.content.get(name) {
+            Some(known) => known,
+            None => return Err(format!("Node {} is not a known node", name).into()),
+        };
+        let tag = match known.node_type {
+            NodeType::Condition => "Condition",
+            _ => "Action",
+        };
+        let attributes = [("ID", name.to_string())];
+        if known.reads.is_empty() && known.writes.is_empty() {
+            line(&mut xml, 2, &format!("{}/>", open_tag(tag, &attributes)));
+            continue;
+        }
+        line(&mut xml, 2, &format!("{}>", open_tag(tag, &attributes)));
+        for (port, keys) in [("input_port", &known.reads), ("output_port", &known.writes)] {
+            for key in keys {
+                write_port(&mut xml, 3, port, key, tree_file.blackboard.get(key))?;
+            }
+        }
+        line(&mut xml, 2, &format!("</{}>", tag));
+    }
+    let subtree = open_tag("SubTree", &[("ID", name.clone())]);
+    if tree_file.blackboard.is_empty() {
+        line(&mut xml, 2, &format!("{}/>", subtree));
+    } else {
+        line(&mut xml, 2, &format!("{}>", subtree));
+        for (key, info) in &tree_file.blackboard {
+            write_port(&mut xml, 3, "inout_port", key, Some(info))?;
+        }
+        line(&mut xml, 2, "</SubTree>");
+    }
+    line(&mut xml, 1, "</TreeNodesModel>");
+    line(&mut xml, 0, "</root>");
+    Ok(xml)
+}
+
+struct Importer<'a, 'input> {
+    library: &'a Library,
+    trees: HashMap<&'a str, roxmltree::Node<'a, 'input>>,
+    /// SubTrees being inlined, to stop on recursion
+    stack: Vec<String>,
+}
+
+fn elements<'a, 'input>(
+    element: roxmltree::Node<'a, 'input>,
+) -> impl Iterator<Item = roxmltree::Node<'a, 'input>> {
+    element.children().filter(|c| c.is_element())
+}
+
+fn parse_attribute<T: std::str::FromStr>(
+    element: roxmltree::Node,
+    name: &str,
+) -> Result<Option<T>, Box<dyn Error>> {
+    match element.attribute(name) {
+        Some(value) => Ok(Some(value.trim().parse().map_err(|_| {
+            format!(
+                "Invalid {} of {}: {}",
+                name,
+                element.tag_name().name(),
+                value
+            )
+        })?)),
+        None => Ok(None),
+    }
+}
+
+fn seconds_or_milliseconds(msec: u64) -> String {
+    match msec % 1000 {
+        0 => format!("{}s", msec / 1000),
+        _ => format!("{}ms", msec),
+    }
+}
+
+impl Importer<'_, '_> {
+    fn node(&mut self, element: roxmltree::Node) -> Result<Node, Box<dyn Error>> {
+        let tag = element.tag_name().name();
+        let mut node = match tag {
+            "Inverter" | "ForceSuccess" | "RetryUntilSuccessful" | "Timeout" => {
+                let decorator = match tag {
+                    "Inverter" => Decorator::Invert,
+                    "ForceSuccess" => Decorator::ForceSuccess,
+                    "Timeout" => Decorator::Timeout {
+                        duration: seconds_or_milliseconds(
+                            parse_attribute(element, "msec")?.ok_or("Timeout without msec")?,
+                        ),
+                    },
+                    _ => {
+                        let attempts = parse_attribute(element, "num_attempts")?
+                            .ok_or("RetryUntilSuccessful without num_attempts")?;
+                        match element.attribute("name") {
+                            Some(REPEAT_UNTIL_SUCCESS) => {
+                                Decorator::RepeatUntilSuccess { max: attempts }
+                            }
+                            name => Decorator::Retry {
+                                attempts,
+                                backoff: name
+                                    .and_then(|n| n.strip_prefix(BACKOFF))
+                                    .map(|b| b.trim().to_string()),
+                            },
+                        }
+                    }
+                };
+                let children = elements(element).collect::<Vec<_>>();
+                let child = match children[..] {
+                    [child] => child,
+                    _ => return Err(format!("{} must have exactly one child", tag).into()),
+                };
+                let mut node = self.node(child)?;
+                node.decorators.push(decorator);
+                node
+            }
+            "Sequence" | "SequenceWithMemory" | "ReactiveSequence" | "Fallback"
+            | "ReactiveFallback" | "Parallel" => {
+                match tag {
+                    "SequenceWithMemory" | "ReactiveSequence" => {
+                        warn!(
+                            "{} {:?} is imported as a Sequence",
+                            tag,
+                            element.attribute("name")
+                        )
+                    }
+                    "ReactiveFallback" => {
+                        warn!(
+                            "{} {:?} is imported as a Fallback",
+                            tag,
+                            element.attribute("name")
+                        )
+                    }
+                    _ => {}
+                }
+                let mut children = Vec::new();
+                for (i, child) in elements(element).enumerate() {
+                    let mut child = self.node(child)?;
+                    child.step_number = u8::try_from(i + 1)
+                        .map_err(|_| format!("{} has more than {} children", tag, u8::MAX))?;
+                    children.push(child);
+                }
+                let sequence = match tag {
+                    "Parallel" => {
+                        let count = children.len() as i64;
+                        let success =
+                            parse_attribute::<i64>(element, "success_count")?.unwrap_or(-1);
+                        // Negative counts are relative to the number of children, -1 is all
+                        let success = if success < 0 {
+                            count + success + 1
+                        } else {
+                            success
+                        };
+                        if success <= 0 || success > count {
+                            return Err(format!(
+                                "Parallel success_count of {} for {} children",
+                                success, count
+                            )
+                            .into());
+                        }
+                        // A parallel fails as soon as it can no longer succeed
+                        let failure =
+                            parse_attribute::<i64>(element, "failure_count")?.unwrap_or(1);
+                        let failure = if failure < 0 {
+                            count + failure + 1
+                        } else {
+                            failure
+                        };
+                        if failure != count - success + 1 {
+                            return Err(format!(
+                                "Parallel failure_count of {} for success_count {} of {} children, \
+                                 it must be {}",
+                                failure,
+                                success,
+                                count,
+                                count - success + 1
+                            )
+                            .into());
+                        }
+                        Sequence::Parallel {
+                            success_threshold: (success != count).then_some(success as usize),
+                            children,
+                        }
+                    }
+                    "Fallback" | "ReactiveFallback" => Sequence::Fallback(children),
+                    _ => Sequence::Children(children),
+                };
+                Node {
+                    name: element
+                        .attribute("name")
+                        .unwrap_or(&tag.to_lowercase())
+                        .to_string(),
+                    step_number: 1,
+                    sequence: Some(sequence),
+                    error: None,
+                    decorators: Vec::new(),
+                }
+            }
+            "SubTree" => {
+                let id = element.attribute("ID").ok_or("SubTree without ID")?;
+                if self.stack.iter().any(|s| s == id) {
+                    return Err(format!("SubTree {} includes itself", id).into());
+                }
+                match self.trees.get(id).copied() {
+                    Some(tree) => {
+                        self.stack.push(id.to_string());
+                        let node = self.root(tree)?;
+                        self.stack.pop();
+                        node
+                    }
+                    None => self
+                        .library
+                        .trees
+                        .iter()
+                        .find(|t| t.tree.name == id)
+                        .map(|t| t.tree.clone())
+                        .ok_or(format!(
+                            "SubTree {} is neither in the file nor the library",
+                            id
+                        ))?,
+                }
+            }
+            _ => {
+                let name = match tag {
+                    "Action" | "Condition" => element
+                        .attribute("ID")
+                        .ok_or(format!("{} without ID", tag))?,
+                    _ => tag,
+                };
+                let known = self
+                    .library
+                    .nodes
+                    .content
+                    .get(name)
+                    .ok_or(format!("Node {} is not a known node", name))?;
+                // Ports are the blackboard keys of the node, under their own name
+                for attribute in element.attributes() {
+                    let port = attribute.name();
+                    if matches!(port, "ID" | "name") || port.starts_with('_') {
+                        continue;
+                    }
+                    if !known.reads.iter().chain(&known.writes).any(|k| k == port) {
+                        return Err(format!("Node {} has no port {}", name, port).into());
+                    }
+                    if attribute.value() != format!("{{{}}}", port) {
+                        return Err(format!(
+                            "Port {} of {} is remapped to {}, which is not supported",
+                            port,
+                            name,
+                            attribute.value()
+                        )
+                        .into());
+                    }
+                }
+                Node {
+                    name: name.to_string(),
+                    step_number: 1,
+                    sequence: None,
+                    error: None,
+                    decorators: Vec::new(),
+                }
+            }
+        };
+        if let Some(script) = element.attribute("_onFailure") {
+            let error = script
+                .trim()
+                .strip_prefix("error := '")
+                .and_then(|s| s.strip_suffix('\''))
+                .ok_or(format!("Unsupported _onFailure of {}: {}", tag, script))?;
+            match self.library.nodes.content.get(error) {
+                Some(known) if known.node_type == NodeType::Error => {}
+                _ => return Err(format!("{} is not an error node", error).into()),
+            }
+            if node.error.is_some() {
+                return Err(format!("Node {} raises two errors", node.name).into());
+            }
+            node.error = Some(error.to_string());
+        }
+        Ok(node)
+    }
+
+    fn root(&mut self, tree: roxmltree::Node) -> Result<Node, Box<dyn Error>> {
+        let children = elements(tree).collect::<Vec<_>>();
+        match children[..] {
+            [child] => self.node(child),
+            _ => Err(format!(
+                "BehaviorTree {} must have exactly one child",
+                tree.attribute("ID").unwrap_or_default()
+            )
+            .into()),
+        }
+    }
+}
+
+fn blackboard(model: roxmltree::Node) -> Result<BTreeMap<String, BlackboardKey>, Box<dyn Error>> {
+    let mut blackboard = BTreeMap::new();
+    for port in elements(model) {
+        let name = port.attribute("name").ok_or("Port without name")?;
+        let key_type = key_type(port.attribute("type").unwrap_or_default());
+        let unit = match port.attribute("unit") {
+            Some(unit) => Some(serde_json::from_value::<Unit>(unit.into())?),
+            None => None,
+        };
+        let default = match port.attribute("default") {
+            Some(default) => Some(parse_value(default, key_type)?),
+            None => None,
+        };
+        let key = BlackboardKey {
+            key_type,
+            description: port.text().unwrap_or_default().trim().to_string(),
+            unit,
+            default,
+        };
+        blackboard.insert(name.to_string(), key);
+    }
+    Ok(blackboard)
+}
+
+/// The main tree of a BehaviorTree.CPP v4 document, with the SubTrees it uses inlined
+pub fn xml_to_tree(xml: &str, library: &Library) -> Result<BehaviorTreeFile, Box<dyn Error>> {
+    let document = roxmltree::Document::parse(xml)?;
+    let root = document.root_element();
+    if root.attribute("BTCPP_format").unwrap_or("4") != "4" {
+        return Err("Only BehaviorTree.CPP format 4 is supported".into());
+    }
+    let trees = elements(root)
+        .filter(|e| e.has_tag_name("BehaviorTree"))
+        .map(|e| Ok((e.attribute("ID").ok_or("BehaviorTree without ID")?, e)))
+        .collect::<Result<HashMap<_, _>, Box<dyn Error>>>()?;
+    let main = match root.attribute("main_tree_to_execute") {
+        Some(main) => main,
+        None if trees.len() == 1 => trees.keys().next().copied().unwrap_or_default(),
+        None => return Err("main_tree_to_execute is required with several trees".into()),
+    };
+    let tree = *trees
+        .get(main)
+        .ok_or(format!("BehaviorTree {} not found", main))?;
+    let mut importer = Importer {
+        library,
+        trees,
+        stack: vec![main.to_string()],
+    };
+    let mut node = importer.root(tree)?;
+    node.step_number = 1;
+    // A leaf on its own is named after the tree
+    if node.sequence.is_some() {
+        node.name = main.to_string();
+    }
+
+    let model = root.descendants().find(|e| {
+        e.has_tag_name("SubTree")
+            && e.attribute("ID") == Some(main)
+            && e.parent().is_some_and(|p| p.has_tag_name("TreeNodesModel"))
+    });
+    let blackboard = match model {
+        Some(model) => blackboard(model)?,
+        None => BTreeMap::new(),
+    };
+    let participants = match tree.attribute("participant_modules") {
+        Some(participants) => participants
+            .split(',')
+            .map(|p| p.trim().to_string())
+            .filter(|p| !p.is_empty())
+            .collect(),
+        None => {
+            let mut modules = node_modules(&node, library).into_iter().collect::<Vec<_>>();
+            modules.sort();
+            modules
+        }
+    };
+    let tree_file = BehaviorTreeFile::new(
+        tree.attribute("title").unwrap_or(main).to_string(),
+        tree.attribute("version").unwrap_or("0.1.0").to_string(),
+        tree.attribute("description")
+            .unwrap_or_default()
+            .to_string(),
+        participants,
+        blackboard,
+        node,
+    );
+    validate_btree(&tree_file, library)?;
+    Ok(tree_file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn library() -> Library {
+        Library {
+            modules: serde_yaml::from_str(
+                "{title: m, description: d, version: 0.1.0, endpoint: e, content: {\
+                 gantry: {info: {name: Gantry, type: t, description: d}, api: {endpoint: e, services: {}}}}}",
+            )
+            .unwrap(),
+            nodes: serde_yaml::from_str(
+                "{title: n, description: d, version: 0.1.0, content: {\
+                 is_tip_available: {type: condition, description: d, zenoh: {modules: [gantry], min_reply: any}}, \
+                 get_tip: {type: action, description: d, reads: [volume], zenoh: {modules: [gantry], min_reply: any}}, \
+                 home: {type: action, description: d, zenoh: {modules: [gantry], min_reply: any}}, \
+                 no_tip: {type: error, description: d, error: {class: consumable, message: m}}}}",
+            )
+            .unwrap(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn trees_survive_the_round_trip() {
+        let library = library();
+        let tree = serde_yaml::from_str::<BehaviorTreeFile>(
+            "{title: Get Tip, version: 0.2.0, description: Pick a tip, participant_modules: [gantry], \
+             blackboard: {volume: {type: number, description: sample volume, unit: s, default: 50.5}}, \
+             tree: {name: get_tip_tree, step_number: 1, error: no_tip, sequence: !fallback [\
+             {name: is_tip_available, step_number: 1, decorators: [invert]}, \
+             {name: both, step_number: 2, sequence: !parallel {success_threshold: 1, children: [\
+             {name: get_tip, step_number: 1, error: no_tip, \
+              decorators: [!timeout {duration: 5s}, !retry {attempts: 3, backoff: 100ms}]}, \
+             {name: home, step_number: 2, decorators: [!repeat_until_success {max: 4}, force_success]}]}}]}}",
+        )
+        .unwrap();
+        let xml = tree_to_xml(&tree, &library).unwrap();
+        assert!(xml.contains("<Fallback name=\"get_tip_tree\" _onFailure=\"error := 'no_tip'\">"));
+        assert!(xml.contains(
+            "<RetryUntilSuccessful name=\"backoff 100ms\" num_attempts=\"3\" _onFailure="
+        ));
+        assert!(xml.contains("<Parallel name=\"both\" success_count=\"1\" failure_count=\"2\">"));
+        assert!(xml.contains("<get_tip volume=\"{volume}\"/>"));
+        assert!(xml.contains("<Condition ID=\"is_tip_available\"/>"));
+        assert_eq!(xml_to_tree(&xml, &library).unwrap(), tree);
+
+        for remapped in ["volume=\"{sample_volume}\"", "speed=\"{speed}\""] {
+            let xml = xml.replace("volume=\"{volume}\"", remapped);
+            assert!(xml_to_tree(&xml, &library).is_err(), "{}", remapped);
+        }
+    }
+
+    #[test]
+    fn groot_trees_are_imported_with_their_subtrees() {
+        let xml = r#"<root BTCPP_format="4" main_tree_to_execute="MainTree">
+          <BehaviorTree ID="MainTree">
+            <ReactiveSequence>
+              <Condition ID="is_tip_available" name="check"/>
+              <SubTree ID="Homing" _autoremap="true"/>
+              <Parallel success_count="-1">
+                <Action ID="home"/>
+                <Inverter><home/></Inverter>
+              </Parallel>
+            </ReactiveSequence>
+          </BehaviorTree>
+          <BehaviorTree ID="Homing">
+            <Timeout msec="1500"><Action ID="home"/></Timeout>
+          </BehaviorTree>
+        </root>"#;
+        let tree = xml_to_tree(xml, &library()).unwrap();
+        assert_eq!(tree.title, "MainTree");
+        assert_eq!(tree.participants, vec!["gantry"]);
+        let children = tree.tree.sequence.as_ref().unwrap().nodes();
+        assert!(matches!(tree.tree.sequence, Some(Sequence::Children(_))));
+        assert_eq!(children[1].name, "home");
+        assert_eq!(children[1].step_number, 2);
+        assert_eq!(
+            children[1].decorators,
+            vec![Decorator::Timeout {
+                duration: "1500ms".to_string()
+            }]
+        );
+        assert!(matches!(
+            children[2].sequence,
+            Some(Sequence::Parallel {
+                success_threshold: None,
+                ..
+            })
+        ));
+
+        let unknown = xml.replace("<home/>", "<dance/>");
+        assert!(xml_to_tree(&unknown, &library()).is_err());
+    }
+
+    #[test]
+    fn invalid_groot_trees_are_errors() {
+        let tree = |body: &str| {
+            format!(
+                r#"<root BTCPP_format="4"><BehaviorTree ID="MainTree">{}</BehaviorTree></root>"#,
+                body
+            )
+        };
+        let many = format!("<Sequence>{}</Sequence>", "<home/>".repeat(256));
+        for body in [
+            r#"<RetryUntilSuccessful num_attempts="0"><home/></RetryUntilSuccessful>"#,
+            r#"<Timeout msec="0"><home/></Timeout>"#,
+            r#"<Parallel success_count="1" failure_count="1"><home/><home/></Parallel>"#,
+            r#"<Parallel success_count="-1" failure_count="-1"><home/><home/></Parallel>"#,
+            &many,
+        ] {
+            assert!(xml_to_tree(&tree(body), &library()).is_err(), "{}", body);
+        }
+        let parallel =
+            r#"<Parallel success_count="1" failure_count="-1"><home/><home/></Parallel>"#;
+        assert!(xml_to_tree(&tree(parallel), &library()).is_ok());
+    }
+}
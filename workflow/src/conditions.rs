@@ -0,0 +1,417 @@
+use crate::conf::{
+    get_tree_by_name, node_path, BehaviorTreeFile, Decorator, Library, Node, Sequence, WorkflowFile,
+};
+use crate::tools::plan_tool_changes;
+use log::trace;
+use std::collections::BTreeSet;
+use std::error::Error;
+
+/// Fact established by the `pick_up` node of `tool` and required by the nodes needing it
+pub fn tool_mounted(tool: &str) -> String {
+    format!("{} mounted", tool)
+}
+
+/// Facts a tree relies on and leaves behind when it succeeds
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TreeConditions {
+    /// Preconditions that no earlier node of the tree establishes
+    pub requires: BTreeSet<String>,
+    /// Facts that hold when the tree succeeded
+    pub ensures: BTreeSet<String>,
+    /// Facts that may no longer hold after the tree ran
+    pub invalidates: BTreeSet<String>,
+}
+
+/// Check that every precondition in the tree is either established by an earlier node or
+/// left to the steps before the tree
+///
+/// Fails if a node needs a fact that an earlier node of the tree certainly invalidated.
+pub fn tree_conditions(
+    tree: &BehaviorTreeFile,
+    library: &Library,
+) -> Result<TreeConditions, Box<dyn Error>> {
+    let mut checker = Checker {
+        library,
+        requires: BTreeSet::new(),
+    };
+    let state = checker.node(&tree.tree, &tree.tree.name, State::default())?;
+    let invalidates = checker.effects(&tree.tree).broken;
+    Ok(TreeConditions {
+        requires: checker.requires,
+        ensures: state.holds,
+        invalidates,
+    })
+}
+
+/// Check that the preconditions of every step are established by the steps before it
+///
+/// The run starts without any fact holding. The tools mounted by the executor before a step hold
+/// from that step on. The cleanup tree runs after a failure and is not checked.
+pub fn check_workflow(workflow: &WorkflowFile, library: &Library) -> Result<(), Box<dyn Error>> {
+    let changes = plan_tool_changes(&workflow.workflow, &workflow.parameters, library)?;
+    let mut holds = BTreeSet::new();
+    for (index, step) in workflow.workflow.iter().enumerate() {
+        for change in changes.iter().filter(|c| c.step_index == index) {
+            if change.pick_up.is_some() {
+                holds.insert(tool_mounted(&change.tool));
+            }
+        }
+        let tree = get_tree_by_name(&step.name, library)?;
+        let conditions = tree_conditions(tree, library)?;
+        let missing = conditions
+            .requires
+            .difference(&holds)
+            .cloned()
+            .collect::<Vec<_>>();
+        if !missing.is_empty() {
+            return Err(format!(
+                "Step {} of workflow {} runs {} which needs {}, but no earlier step establishes it",
+                index + 1,
+                workflow.title,
+                step.name,
+                missing.join(", ")
+            )
+            .into());
+        }
+        holds.retain(|fact| !conditions.invalidates.contains(fact));
+        holds.extend(conditions.ensures);
+        trace!("Facts after step {}: {:?}", index + 1, holds);
+    }
+    Ok(())
+}
+
+/// What is certain about the facts at some point of a run
+#[derive(Debug, Clone, Default, PartialEq)]
+struct State {
+    holds: BTreeSet<String>,
+    broken: BTreeSet<String>,
+}
+
+impl State {
+    /// What is certain whichever of the two states the run is in
+    fn meet(&self, other: &State) -> State {
+        State {
+            holds: self.holds.intersection(&other.holds).cloned().collect(),
+            broken: self.broken.intersection(&other.broken).cloned().collect(),
+        }
+    }
+
+    fn establish(&mut self, fact: &str) {
+        self.broken.remove(fact);
+        self.holds.insert(fact.to_string());
+    }
+
+    fn break_fact(&mut self, fact: &str) {
+        self.holds.remove(fact);
+        self.broken.insert(fact.to_string());
+    }
+
+    /// A node that failed may have run some of its leaves, so none of their effects is certain
+    fn forget(&self, effects: &State) -> State {
+        State {
+            holds: self.holds.difference(&effects.broken).cloned().collect(),
+            broken: self.broken.difference(&effects.holds).cloned().collect(),
+        }
+    }
+}
+
+struct Checker<'a> {
+    library: &'a Library,
+    requires: BTreeSet<String>,
+}
+
+impl<'a> Checker<'a> {
+    /// Every fact a leaf under `node` may establish, in `holds`, or break, in `broken`
+    fn effects(&self, node: &Node) -> State {
+        let mut effects = State::default();
+        match &node.sequence {
+            Some(sequence) => {
+                for child in sequence.nodes() {
+                    let child = self.effects(child);
+                    effects.holds.extend(child.holds);
+                    effects.broken.extend(child.broken);
+                }
+            }
+            None => {
+                for (fact, holds) in self.postconditions(&node.name) {
+                    match holds {
+                        true => effects.holds.insert(fact),
+                        false => effects.broken.insert(fact),
+                    };
+                }
+            }
+        }
+        effects
+    }
+
+    /// Facts set by the leaf `name` when it succeeds, with `false` for the ones it breaks
+    fn postconditions(&self, name: &str) -> Vec<(String, bool)> {
+        let mut facts = Vec::new();
+        if let Some(known_node) = self.library.nodes.content.get(name) {
+            for fact in &known_node.postconditions {
+                match fact.strip_prefix('!') {
+                    Some(broken) => facts.push((broken.trim().to_string(), false)),
+                    None => facts.push((fact.trim().to_string(), true)),
+                }
+            }
+        }
+        for (abbr, tool) in &self.library.tools.content {
+            if tool.pick_up.as_deref() == Some(name) {
+                facts.push((tool_mounted(abbr), true));
+            }
+        }
+        facts
+    }
+
+    /// State once `node` succeeded, starting from `state`
+    fn node(&mut self, node: &Node, path: &str, state: State) -> Result<State, Box<dyn Error>> {
+        let failed = state.forget(&self.effects(node));
+        // A retried node may succeed after attempts that failed halfway
+        let retried = node.decorators.iter().any(|d| {
+            matches!(
+                d,
+                Decorator::Retry { .. } | Decorator::RepeatUntilSuccess { .. }
+            )
+        });
+        let entry = if retried { failed.clone() } else { state };
+        let mut success = self.sequence(node, path, entry)?;
+        for decorator in &node.decorators {
+            success = match decorator {
+                Decorator::Invert => failed.clone(),
+                Decorator::ForceSuccess => success.meet(&failed),
+                Decorator::Retry { .. }
+                | Decorator::RepeatUntilSuccess { .. }
+                | Decorator::Timeout { .. } => success,
+            };
+        }
+        Ok(success)
+    }
+
+    fn sequence(&mut self, node: &Node, path: &str, state: State) -> Result<State, Box<dyn Error>> {
+        match &node.sequence {
+            Some(Sequence::Children(children)) => {
+                let mut state = state;
+                for (index, child) in children.iter().enumerate() {
+                    state = self.node(child, &node_path(path, index, child), state)?;
+                }
+                Ok(state)
+            }
+            Some(Sequence::Fallback(children)) => {
+                // Each child runs after the ones before it failed
+                let mut entry = state;
+                let mut success: Option<State> = None;
+                for (index, child) in children.iter().enumerate() {
+                    let child_success =
+                        self.node(child, &node_path(path, index, child), entry.clone())?;
+                    success = Some(match success {
+                        Some(success) => success.meet(&child_success),
+                        None => child_success,
+                    });
+                    entry = entry.forget(&self.effects(child));
+                }
+                Ok(success.unwrap_or(entry))
+            }
+            Some(Sequence::Parallel {
+                success_threshold,
+                children,
+            }) => {
+                // Branches run at the same time, so none can rely on what another establishes
+                let mut results = Vec::new();
+                for (index, child) in children.iter().enumerate() {
+                    let result = self.node(child, &node_path(path, index, child), state.clone())?;
+                    results.push((result, self.effects(child)));
+                }
+                if success_threshold.is_some_and(|t| t < children.len()) {
+                    return Ok(state.forget(&self.effects(node)));
+                }
+                let others = |index: usize| {
+                    let mut others = State::default();
+                    for (other, (_, effects)) in results.iter().enumerate() {
+                        if other != index {
+                            others.holds.extend(effects.holds.iter().cloned());
+                            others.broken.extend(effects.broken.iter().cloned());
+                        }
+                    }
+                    others
+                };
+                let mut merged = state.forget(&self.effects(node));
+                for (index, (result, _)) in results.iter().enumerate() {
+                    let others = others(index);
+                    for fact in result.holds.difference(&others.broken) {
+                        merged.establish(fact);
+                    }
+                    for fact in result.broken.difference(&others.holds) {
+                        merged.break_fact(fact);
+                    }
+                }
+                Ok(merged)
+            }
+            None => self.leaf(node, path, state),
+        }
+    }
+
+    fn leaf(&mut self, node: &Node, path: &str, mut state: State) -> Result<State, Box<dyn Error>> {
+        let known_node = match self.library.nodes.content.get(&node.name) {
+            Some(known_node) => known_node,
+            None => return Err(format!("Node {} is not a known node", node.name).into()),
+        };
+        let needs = known_node
+            .preconditions
+            .iter()
+            .map(|fact| fact.trim().to_string())
+            .chain(known_node.needs_tool.iter().map(|tool| tool_mounted(tool)));
+        for fact in needs {
+            if state.broken.contains(&fact) {
+                return Err(format!(
+                    "{}: {} needs {}, which an earlier node invalidated",
+                    path, node.name, fact
+                )
+                .into());
+            }
+            if !state.holds.contains(&fact) {
+                trace!("{}: {} is left to the steps before the tree", path, fact);
+                self.requires.insert(fact.clone());
+                state.establish(&fact);
+            }
+        }
+        for (fact, holds) in self.postconditions(&node.name) {
+            match holds {
+                true => state.establish(&fact),
+                false => state.break_fact(&fact),
+            }
+        }
+        Ok(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conf::{KnownNode, NodeType, WorkflowStep};
+    use std::collections::BTreeMap;
+
+    fn known_node(preconditions: &[&str], postconditions: &[&str]) -> KnownNode {
+        KnownNode {
+            node_type: NodeType::Action,
+            zenoh: None,
+            description: String::new(),
+            reads: Vec::new(),
+            writes: Vec::new(),
+            expression: None,
+            duration: None,
+            consumes: BTreeMap::new(),
+            error: None,
+            preconditions: preconditions.iter().map(|f| f.to_string()).collect(),
+            postconditions: postconditions.iter().map(|f| f.to_string()).collect(),
+            needs_tool: None,
+        }
+    }
+
+    fn leaf(name: &str) -> Node {
+        Node {
+            name: name.to_string(),
+            step_number: 0,
+            sequence: None,
+            error: None,
+            decorators: Vec::new(),
+        }
+    }
+
+    fn tree(name: &str, sequence: Sequence) -> BehaviorTreeFile {
+        let mut file = serde_yaml::from_str::<BehaviorTreeFile>(
+            "{title: t, version: 0.1.0, description: d, participant_modules: [], \
+             tree: {name: root, step_number: 0, sequence: null, error: null}}",
+        )
+        .unwrap();
+        file.tree.name = name.to_string();
+        file.tree.sequence = Some(sequence);
+        file
+    }
+
+    fn library() -> Library {
+        let mut library = Library::default();
+        for (name, node) in [
+            ("get_tip", known_node(&[], &["tip attached"])),
+            (
+                "aspirate",
+                known_node(&["tip attached"], &["liquid in tip"]),
+            ),
+            (
+                "drop_tip",
+                known_node(&["tip attached"], &["!tip attached"]),
+            ),
+        ] {
+            library.nodes.content.insert(name.to_string(), node);
+        }
+        library
+    }
+
+    fn workflow(steps: &[&str]) -> WorkflowFile {
+        let mut file = serde_yaml::from_str::<WorkflowFile>(
+            "{title: test, description: d, version: 0.1.0, workflow: [], parameters: [], \
+             process_tldr: t}",
+        )
+        .unwrap();
+        file.workflow = steps
+            .iter()
+            .map(|name| WorkflowStep {
+                name: name.to_string(),
+                why: String::new(),
+                parameters: BTreeMap::new(),
+            })
+            .collect();
+        file
+    }
+
+    #[test]
+    fn preconditions_are_established_by_earlier_nodes() {
+        let mut library = library();
+        library.trees.push(tree(
+            "aspirate_first",
+            Sequence::Children(vec![leaf("aspirate"), leaf("get_tip")]),
+        ));
+        library.trees.push(tree(
+            "get_tip_first",
+            Sequence::Children(vec![leaf("get_tip"), leaf("aspirate")]),
+        ));
+
+        let conditions = tree_conditions(&library.trees[0], &library).unwrap();
+        assert_eq!(
+            conditions.requires,
+            BTreeSet::from(["tip attached".to_string()])
+        );
+        assert!(check_workflow(&workflow(&["aspirate_first"]), &library).is_err());
+        assert!(check_workflow(&workflow(&["get_tip_first"]), &library).is_ok());
+        assert!(check_workflow(&workflow(&["get_tip_first", "aspirate_first"]), &library).is_ok());
+
+        let dropped = tree(
+            "dropped",
+            Sequence::Children(vec![leaf("get_tip"), leaf("drop_tip"), leaf("aspirate")]),
+        );
+        assert!(tree_conditions(&dropped, &library).is_err());
+    }
+
+    #[test]
+    fn fallbacks_only_ensure_what_every_child_does() {
+        let library = library();
+        let either = tree(
+            "either",
+            Sequence::Fallback(vec![
+                Node {
+                    name: "attached".to_string(),
+                    step_number: 0,
+                    sequence: Some(Sequence::Children(vec![leaf("get_tip"), leaf("aspirate")])),
+                    error: None,
+                    decorators: Vec::new(),
+                },
+                leaf("get_tip"),
+            ]),
+        );
+        let conditions = tree_conditions(&either, &library).unwrap();
+        assert!(conditions.requires.is_empty());
+        assert_eq!(
+            conditions.ensures,
+            BTreeSet::from(["tip attached".to_string()])
+        );
+    }
+}
@@ -1,17 +1,22 @@
+use crate::analysis::analyse_library;
+use crate::blackboard::Expression;
+use crate::conditions::{check_workflow, tree_conditions};
+use crate::tools::plan_tool_changes;
 use log::{
     debug,
     trace,
+    warn,
     //info
 };
-use schemars::{schema_for, JsonSchema};
+use schemars::JsonSchema;
 use semver::Version;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::error::Error;
 use std::fs;
-use std::fs::File;
-use std::io::Write;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 const DONE: &str = "→ ✅";
 const OK: &str = "↓ ✔️";
@@ -20,7 +25,7 @@ const OK: &str = "↓ ✔️";
 #[serde(deny_unknown_fields)]
 pub struct Endpoint(String);
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Default, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct Library {
     pub modules: ModuleFile,
@@ -30,14 +35,20 @@ pub struct Library {
     pub workflows: Vec<WorkflowFile>,
 }
 
-#[derive(Debug, Serialize, JsonSchema, Deserialize)]
+/// # Modules
+///
+/// The modules of the instrument and the services they answer on Zenoh, `modules.yaml`
+#[derive(Debug, Default, Serialize, JsonSchema, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct ModuleFile {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     file_name: Option<String>,
     title: String,
     description: String,
+    /// Semantic version of the file, e.g. `0.1.0`
     pub version: String,
     pub endpoint: String,
+    /// Modules by abbreviation, the names nodes use in `zenoh.modules`
     pub content: HashMap<String, Module>,
 }
 
@@ -51,24 +62,58 @@ pub struct Module {
 #[derive(Debug, PartialEq, Serialize, JsonSchema, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct ModuleInfo {
+    /// Human readable name, e.g. `Centrifuge`
     pub name: String,
     #[serde(rename = "type")]
     pub module_type: String,
     pub description: String,
+    #[serde(default)]
+    pub access: Access,
+}
+
+/// How trees running at the same time may use a module
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, JsonSchema, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Access {
+    /// One tree at a time
+    #[default]
+    Exclusive,
+    /// Any number of trees at once
+    Shared,
 }
 
 #[derive(Debug, PartialEq, Serialize, JsonSchema, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct API {
+    /// Zenoh key expression the services are below
     pub endpoint: String,
     pub variables: Option<HashMap<String, String>>, // TODO: Later <String, data_type>
-    pub services: HashMap<String, Service>,         // TODO Later <<path>/, Service>
+    /// Services by path, each answering some of `get`, `post`, `put` and `delete`
+    pub services: HashMap<String, Service>, // TODO Later <<path>/, Service>
 }
 
 #[derive(Debug, PartialEq, Serialize, JsonSchema, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct Service(HashMap<RequestType, RequestSchema>);
 
+impl Service {
+    pub fn requests(&self) -> &HashMap<RequestType, RequestSchema> {
+        &self.0
+    }
+
+    /// The longest timeout declared by any request type of this service
+    pub fn timeout(&self) -> Result<Option<Duration>, Box<dyn Error>> {
+        let mut longest = None;
+        for request in self.0.values() {
+            let timeout = request.timeout_duration()?;
+            if longest.is_none_or(|l| timeout > l) {
+                longest = Some(timeout);
+            }
+        }
+        Ok(longest)
+    }
+}
+
 #[derive(Debug, Hash, PartialEq, Eq, Serialize, JsonSchema, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub enum RequestType {
@@ -86,11 +131,41 @@ pub enum RequestType {
 #[serde(deny_unknown_fields)]
 pub struct RequestSchema {
     pub summary: String,
+    /// How long to wait for the replies, e.g. `10s`
+    #[schemars(example = "crate::schema::example_timeout")]
     pub timeout: String, // TODO: Parse to Duration
+    /// Values sent with the request
     pub parameters: Option<Vec<ValueSchema>>,
+    /// Values of the reply
     pub response: Option<Vec<ValueSchema>>,
 }
 
+impl RequestSchema {
+    pub fn timeout_duration(&self) -> Result<Duration, Box<dyn Error>> {
+        parse_duration(&self.timeout)
+    }
+}
+
+/// Parse a duration written as a number followed by a time unit, e.g. `500ms`, `10s` or `2m`
+pub fn parse_duration(text: &str) -> Result<Duration, Box<dyn Error>> {
+    let text = text.trim();
+    let split = text
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("Invalid duration: {}", text))?;
+    let seconds = match unit.trim() {
+        "ms" => number / 1000.0,
+        "s" | "" => number,
+        "m" => number * 60.0,
+        _ => return Err(format!("Invalid duration unit: {}", text).into()),
+    };
+    Duration::try_from_secs_f64(seconds)
+        .map_err(|e| format!("Invalid duration {}: {}", text, e).into())
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, JsonSchema, Deserialize)]
 pub enum Unit {
     #[serde(rename = "C")]
@@ -108,6 +183,7 @@ pub enum Unit {
 #[serde(deny_unknown_fields)]
 pub struct ValueSchema {
     pub name: String,
+    /// Type of the value, e.g. `float`
     #[serde(rename = "type")]
     pub data_type: String, // TODO: Parse to DataType
     pub description: String,
@@ -122,12 +198,18 @@ pub struct Value {
     pub value: f64,
 }
 
-#[derive(Debug, PartialEq, Serialize, JsonSchema, Deserialize)]
+/// # Tools
+///
+/// The tools the modules mount, with the variants to choose from, `tools.yaml`
+#[derive(Debug, Default, PartialEq, Serialize, JsonSchema, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct ToolFile {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     file_name: Option<String>,
     pub name: String,
+    /// Semantic version of the file, e.g. `0.1.0`
     pub version: String,
+    /// Tools by abbreviation, the names nodes use in `needs_tool`
     pub content: HashMap<String, Tool>,
 }
 
@@ -136,6 +218,7 @@ pub struct ToolFile {
 pub struct Tool {
     pub name: String,
     pub description: String,
+    /// Node that mounts the tool, it reads the variant from the `<tool>_variant` blackboard key
     //#[serde(default)]
     pub pick_up: Option<String>,
     //#[serde(default)]
@@ -148,6 +231,8 @@ pub struct Variant {
     pub name: String,
     pub description: Option<String>,
     pub abbr: String,
+    /// Expression over the parameters of a step, e.g. `volume >= 10 && volume < 100`
+    #[schemars(example = "crate::schema::example_rule")]
     pub preffered_when: String,
 }
 
@@ -161,13 +246,19 @@ pub enum NodeType {
     Error,
 }
 
-#[derive(Debug, PartialEq, Serialize, JsonSchema, Deserialize)]
+/// # Nodes
+///
+/// The leaves trees are built from and the errors they raise, `nodes.yaml`
+#[derive(Debug, Default, PartialEq, Serialize, JsonSchema, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct KnownNodesFile {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     file_name: Option<String>,
     pub title: String,
     pub description: String,
+    /// Semantic version of the file, e.g. `0.1.0`
     pub version: String,
+    /// Nodes by name, the names of the leaves of the trees
     pub content: HashMap<String, KnownNode>,
 }
 
@@ -176,12 +267,108 @@ pub struct KnownNodesFile {
 pub struct KnownNode {
     #[serde(rename = "type")]
     pub node_type: NodeType,
+    /// Modules the node sends its request to
     #[serde(default)]
     pub zenoh: Option<Zenoh>,
     pub description: String,
+    /// Blackboard keys sent along with the request
+    #[serde(default)]
+    pub reads: Vec<String>,
+    /// Blackboard keys set from the values of the response with the same name
+    #[serde(default)]
+    pub writes: Vec<String>,
+    /// Blackboard expression evaluated by a condition node, e.g. `tip_slot >= 0`
+    #[serde(default)]
+    #[schemars(example = "crate::schema::example_expression")]
+    pub expression: Option<String>,
+    /// How long the node usually takes, used to estimate runs
+    #[serde(default)]
+    #[schemars(example = "crate::schema::example_duration")]
+    pub duration: Option<ExpectedDuration>,
+    /// Consumables used every time the node runs, e.g. `tip: 1`
+    #[serde(default)]
+    #[schemars(example = "crate::schema::example_consumes")]
+    pub consumes: BTreeMap<String, f64>,
+    /// Required for, and only allowed on, nodes of type `error`
+    #[serde(default)]
+    pub error: Option<ErrorInfo>,
+    /// Facts that have to hold before the node runs, e.g. `tip attached`
+    #[serde(default)]
+    #[schemars(example = "crate::schema::example_preconditions")]
+    pub preconditions: Vec<String>,
+    /// Facts that hold once the node succeeded, `!tip attached` for one that no longer does
+    #[serde(default)]
+    #[schemars(example = "crate::schema::example_postconditions")]
+    pub postconditions: Vec<String>,
+    /// Tool of the tools file that has to be mounted, see `tool_mounted`
+    #[serde(default)]
+    pub needs_tool: Option<String>,
 }
 
-#[derive(Debug, PartialEq, Serialize, JsonSchema, Deserialize)]
+/// Entry of the error catalogue, raised by the nodes that name it in `Node::error`
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ErrorInfo {
+    pub class: ErrorClass,
+    /// What the operator is told when the error is raised
+    pub message: String,
+    /// Tree that brings the instrument back to a known state
+    #[serde(default)]
+    pub recovery: Option<String>,
+    /// The run cannot continue without someone at the instrument
+    #[serde(default)]
+    pub requires_human: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorClass {
+    /// A module is faulty or out of position
+    Hardware,
+    /// Tips, reagents or cartridges ran out
+    Consumable,
+    /// The sample cannot be processed
+    Sample,
+    /// A module did not answer
+    Communication,
+    Software,
+}
+
+/// Durations written like `2s`, `min` and `max` default to `expected`
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExpectedDuration {
+    pub expected: String,
+    #[serde(default)]
+    pub min: Option<String>,
+    #[serde(default)]
+    pub max: Option<String>,
+}
+
+impl ExpectedDuration {
+    /// The minimum, expected and maximum duration
+    pub fn parse(&self) -> Result<(Duration, Duration, Duration), Box<dyn Error>> {
+        let expected = parse_duration(&self.expected)?;
+        let min = match &self.min {
+            Some(min) => parse_duration(min)?,
+            None => expected,
+        };
+        let max = match &self.max {
+            Some(max) => parse_duration(max)?,
+            None => expected,
+        };
+        if min > expected || expected > max {
+            return Err(format!(
+                "Expected duration {:?} is not between {:?} and {:?}",
+                expected, min, max
+            )
+            .into());
+        }
+        Ok((min, expected, max))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, JsonSchema, Deserialize)]
 #[serde(deny_unknown_fields)]
 #[serde(rename_all = "snake_case")]
 pub enum ReplyMode {
@@ -190,10 +377,12 @@ pub enum ReplyMode {
     One,
 }
 
-#[derive(Debug, PartialEq, Serialize, JsonSchema, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct Zenoh {
+    /// Abbreviations of the modules and tools to send the request to
     pub modules: Vec<String>,
+    /// How many of the modules have to reply
     pub min_reply: ReplyMode,
 }
 
@@ -202,46 +391,209 @@ pub struct Zenoh {
 pub enum Sequence {
     Children(Vec<Node>), // HAS
     Fallback(Vec<Node>), // HAS
+    /// Run the children concurrently, branches sharing a participant module take turns
+    Parallel {
+        /// Number of children that have to succeed, all of them if not set
+        #[serde(default)]
+        success_threshold: Option<usize>,
+        children: Vec<Node>,
+    },
+}
+
+impl Sequence {
+    pub fn nodes(&self) -> &Vec<Node> {
+        match self {
+            Sequence::Children(nodes) | Sequence::Fallback(nodes) => nodes,
+            Sequence::Parallel { children, .. } => children,
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Serialize, JsonSchema, Deserialize, Clone)]
 #[serde(deny_unknown_fields)]
 pub struct Node {
+    /// A node of the nodes file for leaves, any name for the others
     pub name: String,
+    /// Position among the children of the parent, starting at 1
     pub step_number: u8,
+    /// Children of the node, leaves have none
     pub sequence: Option<Sequence>,
+    /// Error node of the nodes file raised when the node fails
     pub error: Option<String>,
+    /// Applied from the inside out, the first one wraps the node itself
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub decorators: Vec<Decorator>,
+}
+
+/// Changes how the result of a node is obtained or reported
+#[derive(Debug, PartialEq, Serialize, JsonSchema, Deserialize, Clone)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum Decorator {
+    /// Run the node up to `attempts` times until it succeeds
+    Retry {
+        attempts: usize,
+        /// Wait before the first retry, doubled before every following one, e.g. `500ms`
+        #[serde(default)]
+        #[schemars(example = "crate::schema::example_backoff")]
+        backoff: Option<String>,
+    },
+    /// Fail the node if it takes longer than `duration`, e.g. `30s`
+    Timeout { duration: String },
+    /// Succeed when the node fails and fail when it succeeds
+    Invert,
+    /// Succeed even if the node fails
+    ForceSuccess,
+    /// Run the node again right away until it succeeds, at most `max` times. For polling conditions
+    RepeatUntilSuccess { max: usize },
 }
 
+impl std::fmt::Display for Decorator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Decorator::Retry {
+                attempts,
+                backoff: Some(backoff),
+            } => write!(f, "retry({}, {})", attempts, backoff),
+            Decorator::Retry { attempts, .. } => write!(f, "retry({})", attempts),
+            Decorator::Timeout { duration } => write!(f, "timeout({})", duration),
+            Decorator::Invert => write!(f, "invert"),
+            Decorator::ForceSuccess => write!(f, "force_success"),
+            Decorator::RepeatUntilSuccess { max } => write!(f, "repeat_until_success({})", max),
+        }
+    }
+}
+
+/// Identifier of a child node that stays unique when the same node appears twice in a tree,
+/// e.g. `get_tip/0.is_tip_available`
+pub fn node_path(parent: &str, index: usize, child: &Node) -> String {
+    format!("{}/{}.{}", parent, index, child.name)
+}
+
+/// # Behavior tree
+///
+/// A tree of nodes run as one step of a workflow, `trees/<name>.yaml`
 #[derive(Debug, PartialEq, Serialize, JsonSchema, Deserialize, Clone)]
 #[serde(deny_unknown_fields)]
 pub struct BehaviorTreeFile {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     file_name: Option<String>,
     pub title: String,
+    /// Semantic version of the file, e.g. `0.1.0`
     pub version: String,
     pub description: String,
+    /// Modules held by the tree while it runs
     #[serde(rename = "participant_modules")]
     pub participants: Vec<String>,
+    /// Keys the nodes of this tree read and write
+    #[serde(default)]
+    pub blackboard: BTreeMap<String, BlackboardKey>,
     pub tree: Node,
 }
 
+impl BehaviorTreeFile {
+    pub fn new(
+        title: String,
+        version: String,
+        description: String,
+        participants: Vec<String>,
+        blackboard: BTreeMap<String, BlackboardKey>,
+        tree: Node,
+    ) -> Self {
+        BehaviorTreeFile {
+            file_name: None,
+            title,
+            version,
+            description,
+            participants,
+            blackboard,
+            tree,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, JsonSchema, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyType {
+    Bool,
+    Integer,
+    Number,
+    String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BlackboardKey {
+    #[serde(rename = "type")]
+    pub key_type: KeyType,
+    pub description: String,
+    #[serde(default)]
+    pub unit: Option<Unit>,
+    #[serde(default)]
+    pub default: Option<BlackboardValue>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema, Deserialize)]
+#[serde(untagged)]
+pub enum BlackboardValue {
+    Bool(bool),
+    Integer(i64),
+    Number(f64),
+    String(String),
+}
+
+impl BlackboardValue {
+    pub fn key_type(&self) -> KeyType {
+        match self {
+            BlackboardValue::Bool(_) => KeyType::Bool,
+            BlackboardValue::Integer(_) => KeyType::Integer,
+            BlackboardValue::Number(_) => KeyType::Number,
+            BlackboardValue::String(_) => KeyType::String,
+        }
+    }
+
+    pub fn as_number(&self) -> Option<f64> {
+        match self {
+            BlackboardValue::Integer(i) => Some(*i as f64),
+            BlackboardValue::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+}
+
+/// # Workflow
+///
+/// The trees run one after the other to process a sample, `workflows/<name>.yaml`
 #[derive(Debug, Clone, PartialEq, Serialize, JsonSchema, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct WorkflowFile {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     file_name: Option<String>,
     pub title: String,
     pub description: String,
+    /// Semantic version of the file, e.g. `0.1.0`
     pub version: String,
+    /// Steps run in order
     pub workflow: Vec<WorkflowStep>,
+    /// Values of the whole run
     pub parameters: Vec<Value>,
+    /// One line summary of the process
     pub process_tldr: String,
+    /// Tree run to bring the modules to a safe state when the workflow is aborted
+    #[serde(default)]
+    pub cleanup: Option<String>,
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, JsonSchema, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct WorkflowStep {
+    /// Name of the tree to run
     pub name: String,
+    /// What the step does for the process
     pub why: String,
+    /// Values of this step, e.g. `volume: 50`, used to choose the tool variants
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    #[schemars(example = "crate::schema::example_parameters")]
+    pub parameters: BTreeMap<String, BlackboardValue>,
 }
 
 //fn serialize_behaviour_tree(tree: &BehaviorTreeFile, file_name: &str) -> Result<(), Box<dyn Error>> {
@@ -251,16 +603,106 @@ pub struct WorkflowStep {
 //    Ok(())
 //}
 
+/// Format of a library file, known from its extension
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileFormat {
+    Yaml,
+    Json,
+    Toml,
+}
+
+impl FileFormat {
+    /// `None` for files that are not library files, e.g. a README
+    pub fn from_path(path: &Path) -> Option<FileFormat> {
+        match path.extension()?.to_str()? {
+            "yaml" | "yml" => Some(FileFormat::Yaml),
+            "json" => Some(FileFormat::Json),
+            "toml" => Some(FileFormat::Toml),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            FileFormat::Yaml => "yaml",
+            FileFormat::Json => "json",
+            FileFormat::Toml => "toml",
+        }
+    }
+
+    pub fn parse<T: DeserializeOwned>(&self, text: &str) -> Result<T, Box<dyn Error>> {
+        Ok(match self {
+            FileFormat::Yaml => serde_yaml::from_str(text)?,
+            FileFormat::Json => serde_json::from_str(text)?,
+            FileFormat::Toml => toml::from_str(text)?,
+        })
+    }
+
+    pub fn write<T: Serialize>(&self, value: &T) -> Result<String, Box<dyn Error>> {
+        Ok(match self {
+            FileFormat::Yaml => serde_yaml::to_string(value)?,
+            FileFormat::Json => serde_json::to_string_pretty(value)?,
+            FileFormat::Toml => {
+                // TOML has no null and no enum variants holding a value, JSON maps them to
+                // missing keys and tables
+                let mut value = serde_json::to_value(value)?;
+                remove_nulls(&mut value);
+                toml::to_string_pretty(&value)?
+            }
+        })
+    }
+}
+
+fn remove_nulls(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(object) => {
+            object.retain(|_, v| !v.is_null());
+            object.values_mut().for_each(remove_nulls);
+        }
+        serde_json::Value::Array(array) => array.iter_mut().for_each(remove_nulls),
+        _ => {}
+    }
+}
+
+impl std::str::FromStr for FileFormat {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        FileFormat::from_path(Path::new(&format!("library.{}", text)))
+            .ok_or(format!("Unknown format {}, use yaml, json or toml", text))
+    }
+}
+
+/// Read a library file in any of the formats
+pub fn read_library_file<T: DeserializeOwned>(path: &Path) -> Result<T, Box<dyn Error>> {
+    let format = FileFormat::from_path(path).ok_or(format!("Unknown file type {:?}", path))?;
+    let text = fs::read_to_string(path).map_err(|e| format!("Cannot read {:?}: {}", path, e))?;
+    format
+        .parse(&text)
+        .map_err(|e| format!("Cannot parse {:?}: {}", path, e).into())
+}
+
 fn load_a_behaviour_tree(file_name: &str) -> Result<BehaviorTreeFile, Box<dyn Error>> {
-    let file = File::open(file_name).expect("Failed to open file");
-    let tree: BehaviorTreeFile = serde_yaml::from_reader(file).expect("Failed to deserialize tree");
+    let tree: BehaviorTreeFile = read_library_file(Path::new(file_name))?;
     trace!("{:#?}", tree);
     Ok(tree)
 }
 
+/// The file in `trees` of the library that holds the tree `name`
+pub fn tree_file_path(library_path: &Path, name: &str) -> Result<Option<PathBuf>, Box<dyn Error>> {
+    for entry in fs::read_dir(library_path.join("trees"))? {
+        let path = entry?.path();
+        if FileFormat::from_path(&path).is_some()
+            && read_library_file::<BehaviorTreeFile>(&path)?.tree.name == name
+        {
+            return Ok(Some(path));
+        }
+    }
+    Ok(None)
+}
+
 fn load_a_workflow(file_name: &str) -> Result<WorkflowFile, Box<dyn Error>> {
-    let file = File::open(file_name).expect("Failed to open file");
-    let workflow: WorkflowFile = serde_yaml::from_reader(file).expect("Failed to deserialize tree");
+    let workflow: WorkflowFile = read_library_file(Path::new(file_name))?;
     trace!("{:#?}", workflow);
     Ok(workflow)
 }
@@ -276,10 +718,7 @@ fn load_a_workflow(file_name: &str) -> Result<WorkflowFile, Box<dyn Error>> {
 
 fn load_file_modules(path: &Path) -> Result<ModuleFile, Box<dyn Error>> {
     // Load the modules
-    let modules_file =
-        File::open(path).unwrap_or_else(|_| panic!("Failed to open module file {:#?}", path));
-    let modules_file_data: ModuleFile =
-        serde_yaml::from_reader(modules_file).expect("Unable to parse modules");
+    let modules_file_data: ModuleFile = read_library_file(path).expect("Unable to parse modules");
     Version::parse(&modules_file_data.version).expect("Version is not a valid semver version");
 
     debug!("{} All Modules in the modules file are valid.", OK);
@@ -288,9 +727,7 @@ fn load_file_modules(path: &Path) -> Result<ModuleFile, Box<dyn Error>> {
 
 fn load_file_tools(path: &Path) -> Result<ToolFile, Box<dyn Error>> {
     // Load the tools
-    let tools_file = File::open(path).expect("Unable to open file");
-    let tools_file_data: ToolFile =
-        serde_yaml::from_reader(tools_file).expect("Unable to parse tools");
+    let tools_file_data: ToolFile = read_library_file(path).expect("Unable to parse tools");
     Version::parse(&tools_file_data.version).expect("Version is not a valid semver version");
 
     debug!("{} All Tools in the tools file are valid.", OK);
@@ -299,9 +736,7 @@ fn load_file_tools(path: &Path) -> Result<ToolFile, Box<dyn Error>> {
 
 fn load_file_nodes(path: &Path) -> Result<KnownNodesFile, Box<dyn Error>> {
     // Load the nodes
-    let nodes_file = File::open(path).expect("Unable to open file");
-    let nodes_file_data: KnownNodesFile =
-        serde_yaml::from_reader(nodes_file).expect("Unable to parse nodes");
+    let nodes_file_data: KnownNodesFile = read_library_file(path).expect("Unable to parse nodes");
     Version::parse(&nodes_file_data.version).expect("Version is not a valid semver version");
 
     trace!("Nodes Library file has parsed correctly");
@@ -321,20 +756,85 @@ fn dependencies_abbr(modules_file_data: &ModuleFile, tools_file_data: &ToolFile)
     abbrs
 }
 
+/// Check that the tools are picked up by known nodes and their variant rules are expressions
+fn validate_tools(tools: &ToolFile, nodes: &KnownNodesFile) -> Result<(), Box<dyn Error>> {
+    for (name, tool) in &tools.content {
+        if let Some(pick_up) = &tool.pick_up {
+            if !nodes.content.contains_key(pick_up) {
+                return Err(
+                    format!("Tool {} is picked up by unknown node {}", name, pick_up).into(),
+                );
+            }
+        }
+        for variant in tool.variants.iter().flatten() {
+            Expression::parse(&variant.preffered_when)
+                .map_err(|e| format!("Variant {} of tool {}: {}", variant.abbr, name, e))?;
+        }
+    }
+    Ok(())
+}
+
 /// Check if the dependencies of a module are in the library
 fn validate_nodes_library(
     nodes_file_data: &KnownNodesFile,
     known_dependencies: &[String],
+    tools: &ToolFile,
 ) -> Result<(), Box<dyn Error>> {
     // Throw an error if a node is using a module that is not known
     for (name, node) in nodes_file_data.content.iter() {
+        if let Some(expression) = &node.expression {
+            if node.node_type != NodeType::Condition {
+                return Err(
+                    format!("Node {} has an expression but is not a condition", name).into(),
+                );
+            }
+            Expression::parse(expression)?;
+        }
+        if let Some(duration) = &node.duration {
+            duration
+                .parse()
+                .map_err(|e| format!("Node {} has an invalid duration: {}", name, e))?;
+        }
+        match (&node.node_type, &node.error) {
+            (NodeType::Error, None) => {
+                return Err(format!("Error node {} has no error class and message", name).into())
+            }
+            (NodeType::Error, Some(_)) | (_, None) => {}
+            (_, Some(_)) => {
+                return Err(format!("Node {} describes an error but is not an error", name).into())
+            }
+        }
+        if let Some(tool) = &node.needs_tool {
+            if !tools.content.contains_key(tool) {
+                return Err(format!("Node {} needs an unknown tool {}", name, tool).into());
+            }
+        }
+        for fact in node.preconditions.iter().chain(&node.postconditions) {
+            if fact.trim_start_matches('!').trim().is_empty() {
+                return Err(format!("Node {} has an empty condition", name).into());
+            }
+        }
+        if let Some(fact) = node.preconditions.iter().find(|f| f.starts_with('!')) {
+            return Err(format!(
+                "Node {} has the precondition {}, only postconditions can be negated",
+                name, fact
+            )
+            .into());
+        }
         if let Some(zenoh) = &node.zenoh {
-            for module in &zenoh.modules {
+            // Replies are matched to the modules by name
+            if zenoh.modules.is_empty() {
+                return Err(format!("Node {} sends its request to no module", name).into());
+            }
+            for (i, module) in zenoh.modules.iter().enumerate() {
                 if !known_dependencies.contains(module) {
                     return Err(
                         format!("Node {} is using an unknown module {}", name, module).into(),
                     );
                 }
+                if zenoh.modules[..i].contains(module) {
+                    return Err(format!("Node {} lists module {} twice", name, module).into());
+                }
             }
         }
     }
@@ -342,41 +842,39 @@ fn validate_nodes_library(
     Ok(())
 }
 
+/// Kind of a library file, the part of its name before the first dot, e.g. `modules` for
+/// `modules.v2.json`
 fn name_from_path(path: &Path) -> String {
     let file_name = path.file_name().unwrap().to_str().unwrap();
-    //println!("file_name: {:#?}", file_name.clone().split(".yaml"));
-    let file_name_no_ext = file_name
-        .split(".yaml")
-        .next()
-        .expect(format!("Invalid file name: {}", file_name).as_str());
-    trace!("{} file found.", file_name_no_ext);
-
-    let file_name_no_prefix = file_name_no_ext
-        .split(".")
-        .next()
-        .expect(format!("Invalid file name: {}", file_name_no_ext).as_str());
+    trace!("{} file found.", file_name);
+
+    let file_name_no_prefix = file_name.split('.').next().unwrap_or(file_name);
 
     trace!("file_name_no_prefix: {:#?}", file_name_no_prefix);
 
     file_name_no_prefix.to_string()
 }
 
-fn list_files_in_dir(path: &Path) -> HashMap<String, PathBuf> {
+fn list_files_in_dir(path: &Path) -> Result<HashMap<String, PathBuf>, Box<dyn Error>> {
     let mut files = HashMap::new();
     for entry in fs::read_dir(path).expect("Failed to read directory") {
         let entry = entry.expect("Failed to get entry");
         let path = entry.path();
-        if path.is_file() {
+        if path.is_file() && FileFormat::from_path(&path).is_some() {
             let name = name_from_path(&path);
-            files.insert(name, path.to_path_buf());
+            if let Some(other) = files.insert(name.clone(), path.to_path_buf()) {
+                return Err(
+                    format!("Both {:?} and {:?} are the {} file", other, path, name).into(),
+                );
+            }
         }
     }
     trace!("{:#?} files found.", files);
-    files
+    Ok(files)
 }
 
 pub fn load_library(library_path: &Path) -> Result<Library, Box<dyn Error>> {
-    let library_list = list_files_in_dir(library_path);
+    let library_list = list_files_in_dir(library_path)?;
     debug!("{:#?}", library_list);
 
     // 1. Load the modules file
@@ -385,7 +883,9 @@ pub fn load_library(library_path: &Path) -> Result<Library, Box<dyn Error>> {
     let known_dependencies = dependencies_abbr(&modules, &tools);
 
     let nodes = load_file_nodes(&library_list["nodes"]).expect("Failed to load nodes");
-    validate_nodes_library(&nodes, &known_dependencies).expect("Failed to validate nodes library");
+    validate_tools(&tools, &nodes).expect("Failed to validate tools");
+    validate_nodes_library(&nodes, &known_dependencies, &tools)
+        .expect("Failed to validate nodes library");
 
     let trees = Vec::new();
     let workflows = Vec::new();
@@ -427,12 +927,76 @@ pub fn load_library(library_path: &Path) -> Result<Library, Box<dyn Error>> {
     )
     .expect("Failed to validate workflows");
     debug!("{} All workflows in the library are valid.", OK);
+    validate_recovery_trees(&library).expect("Failed to validate error nodes");
+    // 6. Lint the behavior trees
+    for finding in analyse_library(&library) {
+        warn!("{}", finding);
+    }
 
     debug!("{} Library loading and validation complete.", DONE);
     Ok(library)
 }
 
-fn validate_btree(tree_file: &BehaviorTreeFile, library: &Library) -> Result<(), Box<dyn Error>> {
+type ConvertFile = fn(&Path, &Path, FileFormat) -> Result<PathBuf, Box<dyn Error>>;
+
+/// Write the library file at `path` to `output_dir` in `format`, under the same name
+fn convert_file<T: DeserializeOwned + Serialize>(
+    path: &Path,
+    output_dir: &Path,
+    format: FileFormat,
+) -> Result<PathBuf, Box<dyn Error>> {
+    let file: T = read_library_file(path)?;
+    let stem = path
+        .file_stem()
+        .ok_or(format!("Invalid file name {:?}", path))?;
+    let output = output_dir.join(format!("{}.{}", stem.to_string_lossy(), format.extension()));
+    fs::write(&output, format.write(&file)?)?;
+    trace!("{:?} written to {:?}", path, output);
+    Ok(output)
+}
+
+/// Write every file of the library at `library_path` to `output_path` in `format`, in the same
+/// layout. Comments are not kept.
+pub fn convert_library(
+    library_path: &Path,
+    output_path: &Path,
+    format: FileFormat,
+) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut converted = Vec::new();
+    fs::create_dir_all(output_path)?;
+    for (name, path) in list_files_in_dir(library_path)? {
+        let convert: ConvertFile = match name.as_str() {
+            "modules" => convert_file::<ModuleFile>,
+            "tools" => convert_file::<ToolFile>,
+            "nodes" => convert_file::<KnownNodesFile>,
+            _ => {
+                warn!("{:?} is not a library file, it is not converted", path);
+                continue;
+            }
+        };
+        converted.push(convert(&path, output_path, format)?);
+    }
+    let dirs: [(&str, ConvertFile); 2] = [
+        ("trees", convert_file::<BehaviorTreeFile>),
+        ("workflows", convert_file::<WorkflowFile>),
+    ];
+    for (dir, convert) in dirs {
+        let output_dir = output_path.join(dir);
+        fs::create_dir_all(&output_dir)?;
+        for entry in fs::read_dir(library_path.join(dir))? {
+            let path = entry?.path();
+            if FileFormat::from_path(&path).is_some() {
+                converted.push(convert(&path, &output_dir, format)?);
+            }
+        }
+    }
+    Ok(converted)
+}
+
+pub(crate) fn validate_btree(
+    tree_file: &BehaviorTreeFile,
+    library: &Library,
+) -> Result<(), Box<dyn Error>> {
     // Check if btree name is not a known node
     if library.nodes.content.contains_key(&tree_file.tree.name) {
         return Err(format!(
@@ -448,7 +1012,22 @@ fn validate_btree(tree_file: &BehaviorTreeFile, library: &Library) -> Result<(),
         }
     }
 
-    validate_node(&tree_file.tree, library).expect("Failed to validate node");
+    validate_node(&tree_file.tree, library)?;
+    validate_parallel_participants(&tree_file.tree, &tree_file.participants, library)?;
+    for (name, key) in &tree_file.blackboard {
+        if let Some(default) = &key.default {
+            let numeric = key.key_type == KeyType::Number && default.key_type() == KeyType::Integer;
+            if default.key_type() != key.key_type && !numeric {
+                return Err(format!(
+                    "Default of blackboard key {} is not a {:?}",
+                    name, key.key_type
+                )
+                .into());
+            }
+        }
+    }
+    validate_blackboard_keys(&tree_file.tree, tree_file, library)?;
+    tree_conditions(tree_file, library)?;
     trace!("Behavior Tree {} is valid.", tree_file.tree.name);
     Ok(())
 }
@@ -461,14 +1040,45 @@ fn validate_node(tree: &Node, library: &Library) -> Result<(), Box<dyn Error>> {
     // 2. Else If node has fallback, check if all fallback nodes are valid
     // 3. If all nodes are valid, the tree is valid
 
+    if let Some(error) = &tree.error {
+        match library.nodes.content.get(error) {
+            Some(known_node) if known_node.node_type == NodeType::Error => {}
+            _ => {
+                return Err(format!(
+                    "Node {} raises {} which is not an error node of the nodes file",
+                    tree.name, error
+                )
+                .into())
+            }
+        }
+    }
+
+    for decorator in &tree.decorators {
+        validate_decorator(decorator)
+            .map_err(|e| format!("Invalid {} on node {}: {}", decorator, tree.name, e))?;
+    }
+
     match &tree.sequence {
-        Some(sequence) => match sequence {
-            Sequence::Children(children) | Sequence::Fallback(children) => {
-                for child in children {
-                    validate_node(&child, library).expect("Failed to validate node");
+        Some(sequence) => {
+            if let Sequence::Parallel {
+                success_threshold: Some(threshold),
+                children,
+            } = sequence
+            {
+                if *threshold == 0 || *threshold > children.len() {
+                    return Err(format!(
+                        "Parallel node {} has a success threshold of {} for {} children",
+                        tree.name,
+                        threshold,
+                        children.len()
+                    )
+                    .into());
                 }
             }
-        },
+            for child in sequence.nodes() {
+                validate_node(child, library)?;
+            }
+        }
         None => {
             if !library.nodes.content.contains_key(&tree.name) {
                 return Err(format!("Node {} is not a known node", tree.name).into());
@@ -479,6 +1089,120 @@ fn validate_node(tree: &Node, library: &Library) -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+/// Modules called by the leaves under `node`
+pub fn node_modules(node: &Node, library: &Library) -> HashSet<String> {
+    match &node.sequence {
+        Some(sequence) => sequence
+            .nodes()
+            .iter()
+            .flat_map(|child| node_modules(child, library))
+            .collect(),
+        None => library
+            .nodes
+            .content
+            .get(&node.name)
+            .and_then(|n| n.zenoh.as_ref())
+            .map(|z| z.modules.iter().cloned().collect())
+            .unwrap_or_default(),
+    }
+}
+
+/// Parallel branches are scheduled on the participant modules, so they may not call any other module
+fn validate_parallel_participants(
+    node: &Node,
+    participants: &[String],
+    library: &Library,
+) -> Result<(), Box<dyn Error>> {
+    if let Some(sequence) = &node.sequence {
+        if let Sequence::Parallel { .. } = sequence {
+            for module in node_modules(node, library) {
+                if !participants.contains(&module) {
+                    return Err(format!(
+                        "Parallel node {} uses module {} which is not a participant",
+                        node.name, module
+                    )
+                    .into());
+                }
+            }
+        }
+        for child in sequence.nodes() {
+            validate_parallel_participants(child, participants, library)?;
+        }
+    }
+    Ok(())
+}
+
+/// Decorators need at least one attempt or repetition, and valid durations
+fn validate_decorator(decorator: &Decorator) -> Result<(), Box<dyn Error>> {
+    match decorator {
+        Decorator::Retry { attempts, backoff } => {
+            if *attempts == 0 {
+                return Err("at least one attempt is required".into());
+            }
+            if let Some(backoff) = backoff {
+                parse_duration(backoff)?;
+            }
+        }
+        Decorator::Timeout { duration } => {
+            if parse_duration(duration)?.is_zero() {
+                return Err("the timeout has to be longer than zero".into());
+            }
+        }
+        Decorator::RepeatUntilSuccess { max } => {
+            if *max == 0 {
+                return Err("at least one repetition is required".into());
+            }
+        }
+        Decorator::Invert | Decorator::ForceSuccess => {}
+    }
+    Ok(())
+}
+
+/// Every key used by the leaves of the tree has to be declared in its blackboard
+fn validate_blackboard_keys(
+    node: &Node,
+    tree_file: &BehaviorTreeFile,
+    library: &Library,
+) -> Result<(), Box<dyn Error>> {
+    match &node.sequence {
+        Some(sequence) => {
+            for child in sequence.nodes() {
+                validate_blackboard_keys(child, tree_file, library)?;
+            }
+        }
+        None => {
+            let known_node = match library.nodes.content.get(&node.name) {
+                Some(known_node) => known_node,
+                None => return Ok(()),
+            };
+            let mut keys = known_node
+                .reads
+                .iter()
+                .chain(known_node.writes.iter())
+                .cloned()
+                .collect::<Vec<String>>();
+            if let Some(expression) = &known_node.expression {
+                keys.extend(
+                    Expression::parse(expression)?
+                        .keys()
+                        .into_iter()
+                        .map(String::from),
+                );
+            }
+            for key in keys {
+                if !tree_file.blackboard.contains_key(&key) {
+                    return Err(format!(
+                        "Node {} uses blackboard key {} which is not declared in {}",
+                        node.name, key, tree_file.tree.name
+                    )
+                    .into());
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
 pub fn root_library_path() -> Result<PathBuf, Box<dyn Error>> {
     let root_dir = if cfg!(windows) {
         Path::new("C:\\")
@@ -495,6 +1219,51 @@ pub fn root_library_path() -> Result<PathBuf, Box<dyn Error>> {
     Ok(library_path)
 }
 
+/// Check that every recovery tree of the error catalogue is in the library
+fn validate_recovery_trees(library: &Library) -> Result<(), Box<dyn Error>> {
+    for (name, node) in &library.nodes.content {
+        if let Some(recovery) = node.error.as_ref().and_then(|e| e.recovery.as_ref()) {
+            if !library.trees.iter().any(|t| &t.tree.name == recovery) {
+                return Err(format!(
+                    "Recovery tree {} of error {} is not a known tree",
+                    recovery, name
+                )
+                .into());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Error nodes of the library and the trees that raise them, ordered by name
+pub fn error_catalogue(library: &Library) -> Vec<(&String, &ErrorInfo, Vec<&String>)> {
+    let mut catalogue = library
+        .nodes
+        .content
+        .iter()
+        .filter_map(|(name, node)| node.error.as_ref().map(|info| (name, info)))
+        .map(|(name, info)| {
+            let raised_by = library
+                .trees
+                .iter()
+                .filter(|t| raises(&t.tree, name))
+                .map(|t| &t.tree.name)
+                .collect();
+            (name, info, raised_by)
+        })
+        .collect::<Vec<_>>();
+    catalogue.sort_by_key(|(name, _, _)| *name);
+    catalogue
+}
+
+fn raises(node: &Node, error: &str) -> bool {
+    node.error.as_deref() == Some(error)
+        || node
+            .sequence
+            .as_ref()
+            .is_some_and(|s| s.nodes().iter().any(|c| raises(c, error)))
+}
+
 fn validate_workflow(
     workflow_file: &WorkflowFile,
     library: &Library,
@@ -511,6 +1280,13 @@ fn validate_workflow(
             return Err(format!("Node {} is not a known node", step.name).into());
         }
     }
+    if let Some(cleanup) = &workflow_file.cleanup {
+        if !know_trees.contains(&cleanup) {
+            return Err(format!("Cleanup tree {} is not a known tree", cleanup).into());
+        }
+    }
+    plan_tool_changes(&workflow_file.workflow, &workflow_file.parameters, library)?;
+    check_workflow(workflow_file, library)?;
     trace!("Workflow {} is valid.", workflow_file.title);
     Ok(())
 }
@@ -528,7 +1304,7 @@ fn validate_dir_library<T>(
     for entry in fs::read_dir(dir_path).expect("Failed to read directory") {
         let entry = entry.expect("Failed to read entry");
         let path = entry.path();
-        if path.extension().unwrap() == "yaml" {
+        if FileFormat::from_path(&path).is_some() {
             // Validate the btree
             let file = loader(path.to_str().unwrap()).expect("Failed to deserialize tree");
             file_validator(&file, library).expect("Failed to validate tree");
@@ -563,35 +1339,122 @@ pub fn get_tree_by_name<'a>(
     Ok(tree)
 }
 
-pub fn file_type_to_schema<T: JsonSchema>(
-    name: String,
-    schema_path: PathBuf,
-) -> Result<(), Box<dyn Error>> {
-    let file_schema = schema_for!(T);
-    // Write this file to root_dir/schema/modules_schema.json
-    let mut file_schema_path = schema_path.clone();
-    file_schema_path.push(format!("{}_schema.json", name));
-
-    let mut file_schema_file = File::create(file_schema_path)?;
-    file_schema_file.write_all(
-        serde_json::to_string_pretty(&file_schema)
-            .unwrap()
-            .as_bytes(),
-    )?;
+/// Load a tree file that may be outside the library and check it against the library
+pub fn load_tree_file(path: &Path, library: &Library) -> Result<BehaviorTreeFile, Box<dyn Error>> {
+    let file_name = path.to_str().ok_or(format!("Invalid path {:?}", path))?;
+    let tree = load_a_behaviour_tree(file_name)?;
+    validate_btree(&tree, library).map_err(|e| format!("{}: {}", file_name, e))?;
+    Ok(tree)
+}
 
-    Ok(())
+/// Load a workflow file that may be outside the library and check it against the library
+pub fn load_workflow_file(path: &Path, library: &Library) -> Result<WorkflowFile, Box<dyn Error>> {
+    let file_name = path.to_str().ok_or(format!("Invalid path {:?}", path))?;
+    let workflow = load_a_workflow(file_name)?;
+    validate_workflow(&workflow, library).map_err(|e| format!("{}: {}", file_name, e))?;
+    Ok(workflow)
 }
 
-pub fn generate_json_schemas() -> Result<(), Box<dyn Error>> {
-    let mut schema_path = root_library_path()?;
-    schema_path.push("schema");
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trees_survive_every_format() {
+        let tree: BehaviorTreeFile = serde_yaml::from_str(
+            "{title: t, version: 0.1.0, description: d, participant_modules: [cfg], \
+             blackboard: {volume: {type: number, description: v, default: 5}}, \
+             tree: {name: prep, step_number: 0, sequence: !parallel {success_threshold: 1, \
+             children: [{name: spin, step_number: 1, error: no_tip, \
+             decorators: [invert, !retry {attempts: 3, backoff: 500ms}]}, \
+             {name: shake, step_number: 2, sequence: !fallback [{name: home, step_number: 1}]}]}}}",
+        )
+        .unwrap();
+        for format in [FileFormat::Yaml, FileFormat::Json, FileFormat::Toml] {
+            let text = format.write(&tree).unwrap();
+            let read: BehaviorTreeFile = format.parse(&text).unwrap();
+            assert_eq!(read, tree, "{:?}:\n{}", format, text);
+        }
+        assert_eq!("yml".parse::<FileFormat>(), Ok(FileFormat::Yaml));
+        assert_eq!(name_from_path(Path::new("lib/modules.v2.json")), "modules");
+    }
+
+    #[test]
+    fn nodes_need_distinct_modules() {
+        let tools = ToolFile::default();
+        let modules = vec!["cfg".to_string()];
+        for (zenoh, valid) in [("[cfg]", true), ("[cfg, cfg]", false), ("[]", false)] {
+            let nodes: KnownNodesFile = serde_yaml::from_str(&format!(
+                "{{title: n, description: d, version: 0.1.0, content: {{\
+                 spin: {{type: action, description: d, zenoh: {{modules: {}, min_reply: all}}}}}}}}",
+                zenoh
+            ))
+            .unwrap();
+            assert_eq!(
+                validate_nodes_library(&nodes, &modules, &tools).is_ok(),
+                valid,
+                "{}",
+                zenoh
+            );
+        }
+        assert!(parse_duration("100000000000000000000000s").is_err());
+        assert_eq!(parse_duration("1.5s").unwrap(), Duration::from_millis(1500));
+    }
 
-    // Create the schema json files for each, modules, nodes, trees, worflows, etc.
-    file_type_to_schema::<ModuleFile>("modules".to_string(), schema_path.clone())?;
-    file_type_to_schema::<KnownNodesFile>("nodes".to_string(), schema_path.clone())?;
-    file_type_to_schema::<BehaviorTreeFile>("trees".to_string(), schema_path.clone())?;
-    file_type_to_schema::<WorkflowFile>("workflows".to_string(), schema_path.clone())?;
-    file_type_to_schema::<ToolFile>("tools".to_string(), schema_path.clone())?;
+    #[test]
+    fn errors_are_catalogued() {
+        let mut library = Library {
+            nodes: serde_yaml::from_str(
+                "{title: n, description: d, version: 0.1.0, content: {\
+                 spin: {type: action, description: d}, \
+                 no_tip: {type: error, description: d, \
+                 error: {class: consumable, message: Load tips, recovery: reload_tips}}}}",
+            )
+            .unwrap(),
+            ..Default::default()
+        };
+        let tree: BehaviorTreeFile = serde_yaml::from_str(
+            "{title: t, version: 0.1.0, description: d, participant_modules: [], \
+             tree: {name: prep, step_number: 0, sequence: !children [\
+             {name: spin, step_number: 1, error: no_tip}, {name: spin, step_number: 2, error: jammed}]}}",
+        )
+        .unwrap();
+        assert!(validate_node(&tree.tree, &library).is_err());
+        library.trees.push(tree);
+        assert!(validate_recovery_trees(&library).is_err());
+
+        let catalogue = error_catalogue(&library);
+        assert_eq!(catalogue.len(), 1);
+        let (name, info, raised_by) = &catalogue[0];
+        assert_eq!(name.as_str(), "no_tip");
+        assert_eq!(info.class, ErrorClass::Consumable);
+        assert_eq!(raised_by, &vec!["prep"]);
+    }
 
-    Ok(())
+    #[test]
+    fn bad_tree_and_workflow_files_are_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("garbage.yaml");
+        fs::write(&path, "tree: [unclosed").unwrap();
+        let library = Library::default();
+        assert!(load_tree_file(&path, &library).is_err());
+        assert!(load_workflow_file(&path, &library).is_err());
+        assert!(load_tree_file(&dir.path().join("missing.yaml"), &library).is_err());
+
+        let library = Library {
+            nodes: serde_yaml::from_str(
+                "{title: n, description: d, version: 0.1.0, content: {spin: {type: action, description: d}}}",
+            )
+            .unwrap(),
+            ..Default::default()
+        };
+        fs::write(
+            &path,
+            "{title: t, version: 0.1.0, description: d, participant_modules: [], \
+             tree: {name: prep, step_number: 0, decorators: [!retry {attempts: 0}], \
+             sequence: !children [{name: spin, step_number: 1}]}}",
+        )
+        .unwrap();
+        assert!(load_tree_file(&path, &library).is_err());
+    }
 }
@@ -0,0 +1,421 @@
+use crate::conf::{
+    get_tree_by_name, node_path, parse_duration, BehaviorTreeFile, Decorator, Library, Node,
+    Sequence, WorkflowFile,
+};
+use crate::profile::Profile;
+use crate::reply::service_timeout;
+use log::{debug, warn};
+use std::collections::BTreeMap;
+use std::error::Error;
+
+/// Best case, expected and worst case of a quantity
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Bounds {
+    pub min: f64,
+    pub expected: f64,
+    pub max: f64,
+}
+
+impl Bounds {
+    pub fn exactly(value: f64) -> Self {
+        Self {
+            min: value,
+            expected: value,
+            max: value,
+        }
+    }
+
+    fn add(&mut self, other: &Bounds) {
+        self.min += other.min;
+        self.expected += other.expected;
+        self.max += other.max;
+    }
+}
+
+/// Time in seconds and consumables used by a node
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct NodeEstimate {
+    pub seconds: Bounds,
+    pub consumables: BTreeMap<String, Bounds>,
+}
+
+impl NodeEstimate {
+    /// Add a node that runs after this one
+    pub fn then(&mut self, other: &NodeEstimate) {
+        self.seconds.add(&other.seconds);
+        for (name, amount) in &other.consumables {
+            self.consumables
+                .entry(name.clone())
+                .or_default()
+                .add(amount);
+        }
+    }
+
+    /// Apply `f` to the time and to every consumable
+    fn map(&self, f: impl Fn(Bounds) -> Bounds) -> NodeEstimate {
+        NodeEstimate {
+            seconds: f(self.seconds),
+            consumables: self
+                .consumables
+                .iter()
+                .map(|(name, amount)| (name.clone(), f(*amount)))
+                .collect(),
+        }
+    }
+}
+
+/// Predicts runs from the library without touching the hardware
+///
+/// Leaves take the durations measured in past runs if there are any, else the
+/// duration declared in the nodes file, else anything up to the service timeout.
+pub struct Estimator<'a> {
+    library: &'a Library,
+    history: Profile,
+}
+
+impl<'a> Estimator<'a> {
+    pub fn new(library: &'a Library, history: Profile) -> Self {
+        Self { library, history }
+    }
+
+    /// Estimate of every step of the workflow, in order
+    pub fn workflow(
+        &self,
+        workflow: &WorkflowFile,
+    ) -> Result<Vec<(String, NodeEstimate)>, Box<dyn Error>> {
+        workflow
+            .workflow
+            .iter()
+            .map(|step| {
+                let tree = get_tree_by_name(&step.name, self.library)?;
+                Ok((step.name.clone(), self.tree(tree)?))
+            })
+            .collect()
+    }
+
+    pub fn tree(&self, tree: &BehaviorTreeFile) -> Result<NodeEstimate, Box<dyn Error>> {
+        self.node(&tree.tree, &tree.tree.name)
+    }
+
+    /// Share of the runs in which the node at `path` failed in the past
+    fn failure_rate(&self, path: &str) -> f64 {
+        self.history
+            .nodes
+            .get(path)
+            .map_or(0.0, |stats| stats.failure_rate())
+    }
+
+    fn attempt_failure_rate(&self, path: &str) -> f64 {
+        self.history
+            .nodes
+            .get(path)
+            .map_or(0.0, |stats| stats.attempt_failure_rate())
+    }
+
+    fn node(&self, node: &Node, path: &str) -> Result<NodeEstimate, Box<dyn Error>> {
+        let mut estimate = match &node.sequence {
+            Some(Sequence::Children(children)) => {
+                let mut estimate = NodeEstimate::default();
+                for (index, child) in children.iter().enumerate() {
+                    estimate.then(&self.node(child, &node_path(path, index, child))?);
+                }
+                estimate
+            }
+            Some(Sequence::Fallback(children)) => self.fallback(children, path)?,
+            Some(Sequence::Parallel { children, .. }) => {
+                let mut estimate = NodeEstimate::default();
+                let mut longest = Bounds::default();
+                for (index, child) in children.iter().enumerate() {
+                    let child = self.node(child, &node_path(path, index, child))?;
+                    longest.min = longest.min.max(child.seconds.min);
+                    longest.expected = longest.expected.max(child.seconds.expected);
+                    estimate.then(&child);
+                }
+                // At worst every branch waits for the module another one is using
+                estimate.seconds.min = longest.min;
+                estimate.seconds.expected = longest.expected;
+                estimate
+            }
+            None => self.leaf(node, path)?,
+        };
+
+        for decorator in &node.decorators {
+            estimate = self.decorate(&estimate, decorator, path)?;
+        }
+        Ok(estimate)
+    }
+
+    /// The first child succeeding at best, every child running at worst and in between
+    /// weighted by how often each child failed in past runs
+    fn fallback(&self, children: &[Node], path: &str) -> Result<NodeEstimate, Box<dyn Error>> {
+        let mut estimate = NodeEstimate::default();
+        let mut reached = 1.0;
+        for (index, child) in children.iter().enumerate() {
+            let child_path = node_path(path, index, child);
+            let child_estimate = self.node(child, &child_path)?;
+            let weight = |bounds: Bounds| Bounds {
+                min: if index == 0 { bounds.min } else { 0.0 },
+                expected: bounds.expected * reached,
+                max: bounds.max,
+            };
+            estimate.then(&child_estimate.map(weight));
+            reached *= self.failure_rate(&child_path);
+        }
+        Ok(estimate)
+    }
+
+    fn leaf(&self, node: &Node, path: &str) -> Result<NodeEstimate, Box<dyn Error>> {
+        let known_node = self
+            .library
+            .nodes
+            .content
+            .get(&node.name)
+            .ok_or(format!("Node {} is not a known node", node.name))?;
+
+        let history = self
+            .history
+            .node(&node.name)
+            .filter(|stats| !stats.attempts.is_empty());
+        // Per attempt like the other sources, the decorators add the retries and waits
+        let seconds = if let Some(stats) = history {
+            debug!("{}: learned from {} attempt(s)", path, stats.attempts.len());
+            let attempts = stats.attempts.iter().map(|d| d.as_secs_f64());
+            Bounds {
+                min: attempts.clone().fold(f64::INFINITY, f64::min),
+                expected: attempts.clone().sum::<f64>() / stats.attempts.len() as f64,
+                max: attempts.fold(0.0, f64::max),
+            }
+        } else if let Some(duration) = &known_node.duration {
+            let (min, expected, max) = duration.parse()?;
+            Bounds {
+                min: min.as_secs_f64(),
+                expected: expected.as_secs_f64(),
+                max: max.as_secs_f64(),
+            }
+        } else {
+            let timeout = known_node
+                .zenoh
+                .iter()
+                .flat_map(|zenoh| zenoh.modules.iter())
+                .map(|module| service_timeout(self.library, module, &node.name))
+                .collect::<Result<Vec<_>, _>>()?
+                .into_iter()
+                .max()
+                .unwrap_or_default();
+            if !timeout.is_zero() {
+                warn!(
+                    "{} has no duration and has never run, assuming up to {:?}",
+                    node.name, timeout
+                );
+            }
+            Bounds {
+                min: 0.0,
+                expected: 0.0,
+                max: timeout.as_secs_f64(),
+            }
+        };
+
+        Ok(NodeEstimate {
+            seconds,
+            consumables: known_node
+                .consumes
+                .iter()
+                .map(|(name, amount)| (name.clone(), Bounds::exactly(*amount)))
+                .collect(),
+        })
+    }
+
+    fn decorate(
+        &self,
+        estimate: &NodeEstimate,
+        decorator: &Decorator,
+        path: &str,
+    ) -> Result<NodeEstimate, Box<dyn Error>> {
+        let failure = self.attempt_failure_rate(path);
+        // Expected number of attempts when every attempt fails with the same probability
+        let attempts = |max: usize| (0..max as i32).map(|k| failure.powi(k)).sum::<f64>();
+        Ok(match decorator {
+            Decorator::Retry {
+                attempts: max,
+                backoff,
+            } => {
+                let backoff = match backoff {
+                    Some(backoff) => parse_duration(backoff)?.as_secs_f64(),
+                    None => 0.0,
+                };
+                let expected_attempts = attempts(*max);
+                let mut decorated = estimate.map(|b| Bounds {
+                    min: b.min,
+                    expected: b.expected * expected_attempts,
+                    max: b.max * *max as f64,
+                });
+                // Waits of backoff, 2 * backoff, 4 * backoff... between the attempts
+                let expected_wait = (1..*max as i32)
+                    .map(|k| failure.powi(k) * backoff * 2f64.powi(k - 1))
+                    .sum::<f64>();
+                decorated.seconds.expected += expected_wait;
+                decorated.seconds.max += backoff * (2f64.powi(*max as i32 - 1) - 1.0);
+                decorated
+            }
+            Decorator::RepeatUntilSuccess { max } => {
+                let expected_attempts = attempts(*max);
+                estimate.map(|b| Bounds {
+                    min: b.min,
+                    expected: b.expected * expected_attempts,
+                    max: b.max * *max as f64,
+                })
+            }
+            Decorator::Timeout { duration } => {
+                let limit = parse_duration(duration)?.as_secs_f64();
+                let mut decorated = estimate.clone();
+                decorated.seconds = Bounds {
+                    min: estimate.seconds.min.min(limit),
+                    expected: estimate.seconds.expected.min(limit),
+                    max: estimate.seconds.max.min(limit),
+                };
+                decorated
+            }
+            Decorator::Invert | Decorator::ForceSuccess => estimate.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conf::{ExpectedDuration, KnownNode, NodeType};
+    use crate::events::{Event, EventKind};
+    use std::time::{Duration, SystemTime};
+
+    fn known_node(seconds: &str, tips: f64) -> KnownNode {
+        KnownNode {
+            node_type: NodeType::Action,
+            zenoh: None,
+            description: String::new(),
+            reads: Vec::new(),
+            writes: Vec::new(),
+            expression: None,
+            duration: Some(ExpectedDuration {
+                expected: seconds.to_string(),
+                min: None,
+                max: None,
+            }),
+            consumes: BTreeMap::from([("tip".to_string(), tips)]),
+            error: None,
+            preconditions: Vec::new(),
+            postconditions: Vec::new(),
+            needs_tool: None,
+        }
+    }
+
+    fn leaf(name: &str, decorators: Vec<Decorator>) -> Node {
+        Node {
+            name: name.to_string(),
+            step_number: 0,
+            sequence: None,
+            error: None,
+            decorators,
+        }
+    }
+
+    #[test]
+    fn fallbacks_count_every_branch_at_worst() {
+        let mut library = Library::default();
+        library
+            .nodes
+            .content
+            .insert("get_tip".to_string(), known_node("2s", 1.0));
+        library
+            .nodes
+            .content
+            .insert("get_tip_from_rack".to_string(), known_node("10s", 1.0));
+        let tree = Node {
+            name: "tip".to_string(),
+            step_number: 0,
+            sequence: Some(Sequence::Fallback(vec![
+                leaf(
+                    "get_tip",
+                    vec![Decorator::Retry {
+                        attempts: 3,
+                        backoff: Some("1s".to_string()),
+                    }],
+                ),
+                leaf("get_tip_from_rack", Vec::new()),
+            ])),
+            error: None,
+            decorators: Vec::new(),
+        };
+        let estimate = Estimator::new(&library, Profile::default())
+            .node(&tree, "tip")
+            .unwrap();
+        // 3 attempts of 2s with 1s and 2s between them, then the rack
+        assert_eq!(
+            estimate.seconds,
+            Bounds {
+                min: 2.0,
+                expected: 2.0,
+                max: 19.0
+            }
+        );
+        assert_eq!(
+            estimate.consumables["tip"],
+            Bounds {
+                min: 1.0,
+                expected: 1.0,
+                max: 4.0
+            }
+        );
+    }
+
+    #[test]
+    fn history_is_counted_per_attempt() {
+        let mut library = Library::default();
+        library
+            .nodes
+            .content
+            .insert("get_tip".to_string(), known_node("10s", 1.0));
+        let event = |seconds: u64, kind: EventKind| Event {
+            timestamp: SystemTime::now(),
+            step_index: 0,
+            path: "get_tip".to_string(),
+            node: "get_tip".to_string(),
+            duration: Duration::from_secs(seconds),
+            kind,
+        };
+        let success = || EventKind::NodeResult {
+            success: true,
+            error: None,
+        };
+        let mut history = Profile::default();
+        // 2s failing, 1s backoff and 3s succeeding, then a run that needed no retry
+        history.add_run(&[
+            event(
+                2,
+                EventKind::Retry {
+                    attempt: 1,
+                    error: "no tip".to_string(),
+                    wait: Duration::from_secs(1),
+                },
+            ),
+            event(6, success()),
+        ]);
+        history.add_run(&[event(2, success())]);
+
+        let node = leaf(
+            "get_tip",
+            vec![Decorator::Retry {
+                attempts: 3,
+                backoff: Some("1s".to_string()),
+            }],
+        );
+        let estimate = Estimator::new(&library, history)
+            .node(&node, "get_tip")
+            .unwrap();
+        // Attempts of 2s, 3s and 2s, a third of which fail: 7s / 3 * (1 + 1/3 + 1/9) plus
+        // 1s / 3 + 2s / 9 of waits
+        assert_eq!(estimate.seconds.min, 2.0);
+        assert!((estimate.seconds.expected - 106.0 / 27.0).abs() < 1e-9);
+        // 3 attempts of 3s with 1s and 2s between them
+        assert_eq!(estimate.seconds.max, 12.0);
+    }
+}
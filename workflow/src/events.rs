@@ -0,0 +1,184 @@
+use async_std::channel::Sender;
+use log::{error, warn};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::error::Error;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// What happened during a run
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum EventKind {
+    RunStarted {
+        /// Trees of the steps, in order
+        steps: Vec<String>,
+    },
+    NodeEntered,
+    NodeResult {
+        success: bool,
+        error: Option<String>,
+    },
+    /// A failed node is run again, the duration is the one of the failed attempt
+    Retry {
+        attempt: usize,
+        error: String,
+        /// Backoff before the next attempt
+        #[serde(default)]
+        wait: Duration,
+    },
+    /// A node that declares an error node failed
+    ///
+    /// Only reports the error and its catalogue entry, no handler runs.
+    ErrorHandler {
+        handler: String,
+        error: String,
+    },
+    RunFinished {
+        success: bool,
+    },
+}
+
+/// A single line of the run log
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Event {
+    pub timestamp: SystemTime,
+    pub step_index: usize,
+    /// Position of the node in the tree, see `node_path`. The title of the run for run events
+    pub path: String,
+    pub node: String,
+    /// Time spent in the node or the run, zero for events that start something
+    pub duration: Duration,
+    #[serde(flatten)]
+    pub kind: EventKind,
+}
+
+/// Receives every event of a run as it happens
+pub type Subscriber<'a> = Box<dyn Fn(&Event) + 'a>;
+
+/// Where the log of a run started now is written in `dir`
+///
+/// Characters of the title other than letters, digits, `_` and `-` are replaced by `_`.
+pub fn run_log_path(dir: &Path, title: &str) -> PathBuf {
+    let started = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let name = title
+        .chars()
+        .map(
+            |c| match c.is_ascii_alphanumeric() || c == '_' || c == '-' {
+                true => c,
+                false => '_',
+            },
+        )
+        .collect::<String>();
+    dir.join(format!("{}-{}.jsonl", name, started))
+}
+
+/// Subscriber appending every event to `path` as one JSON object per line
+pub fn json_lines(path: &Path) -> Result<impl Fn(&Event), Box<dyn Error>> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| format!("Failed to open run log {}: {}", path.display(), e))?;
+    let file = RefCell::new(file);
+    let path = path.to_path_buf();
+    Ok(move |event: &Event| {
+        let line = match serde_json::to_string(event) {
+            Ok(line) => line,
+            Err(e) => {
+                error!("Failed to serialize {:?}: {}", event, e);
+                return;
+            }
+        };
+        // A single write per event keeps lines whole if the process is killed
+        if let Err(e) = file
+            .borrow_mut()
+            .write_all(format!("{}\n", line).as_bytes())
+        {
+            error!("Failed to write the run log {}: {}", path.display(), e);
+        }
+    })
+}
+
+/// Subscriber forwarding the events to a channel, dropping them if the receiver falls behind
+pub fn channel(sender: Sender<Event>) -> impl Fn(&Event) {
+    move |event: &Event| {
+        if sender.try_send(event.clone()).is_err() {
+            warn!(
+                "Event subscriber is not keeping up, dropped {:?}",
+                event.kind
+            );
+        }
+    }
+}
+
+/// Read back the events written by `json_lines`
+pub fn read_run_log(path: &Path) -> Result<Vec<Event>, Box<dyn Error>> {
+    let file = File::open(path)
+        .map_err(|e| format!("Failed to open run log {}: {}", path.display(), e))?;
+    let mut events = Vec::new();
+    for (number, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let event = serde_json::from_str(&line)
+            .map_err(|e| format!("{}:{}: {}", path.display(), number + 1, e))?;
+        events.push(event);
+    }
+    Ok(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events_round_trip_through_the_log() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("events.jsonl");
+        let events = vec![
+            Event {
+                timestamp: SystemTime::now(),
+                step_index: 0,
+                path: "get_tip".to_string(),
+                node: "get_tip".to_string(),
+                duration: Duration::ZERO,
+                kind: EventKind::NodeEntered,
+            },
+            Event {
+                timestamp: SystemTime::now(),
+                step_index: 0,
+                path: "get_tip".to_string(),
+                node: "get_tip".to_string(),
+                duration: Duration::from_millis(250),
+                kind: EventKind::NodeResult {
+                    success: false,
+                    error: Some("no tip".to_string()),
+                },
+            },
+        ];
+        let subscriber = json_lines(&path).unwrap();
+        for event in &events {
+            subscriber(event);
+        }
+        assert_eq!(read_run_log(&path).unwrap(), events);
+    }
+
+    #[test]
+    fn run_logs_stay_in_their_directory() {
+        let dir = Path::new("/tmp/tcr/runs");
+        let path = run_log_path(dir, "../PCR run/tb_pcr#1");
+        assert_eq!(path.parent(), Some(dir));
+        let name = path.file_name().unwrap().to_str().unwrap();
+        assert!(name.starts_with("___PCR_run_tb_pcr_1-"), "{}", name);
+    }
+}
@@ -0,0 +1,1452 @@
+use crate::blackboard::{Blackboard, Expression};
+use crate::conf::{
+    get_tree_by_name, node_modules, node_path, parse_duration, BehaviorTreeFile, BlackboardKey,
+    BlackboardValue, Decorator, KeyType, Library, Node, Sequence, Value, WorkflowFile,
+    WorkflowStep,
+};
+use crate::events::{json_lines, Event, EventKind, Subscriber};
+use crate::journal::{Journal, JournalEntry, ResumePoint};
+use crate::reply::{fan_out, LeafReply, ReplyStatus, Transport};
+use crate::tools::{plan_tool_changes, variant_key, ToolChange};
+use async_recursion::async_recursion;
+use async_std::channel::Receiver;
+use futures::stream::{FuturesUnordered, StreamExt};
+use log::{debug, error, info, trace, warn};
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashSet};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Operator commands, checked by the executor between leaf nodes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Control {
+    Pause,
+    Resume,
+    /// Run the next leaf node and pause again
+    Step,
+    /// Stop the run and execute the cleanup tree
+    Abort,
+}
+
+impl FromStr for Control {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "p" | "pause" => Ok(Control::Pause),
+            "r" | "resume" => Ok(Control::Resume),
+            "s" | "step" => Ok(Control::Step),
+            "a" | "abort" => Ok(Control::Abort),
+            other => Err(format!("Unknown command: {}", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RunState {
+    Running,
+    Paused,
+    Stepping,
+    Aborted,
+    /// Running the cleanup tree, commands are ignored
+    CleaningUp,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StepStatus {
+    Success,
+    Failure(String),
+    /// Stopped by the operator
+    Aborted,
+    /// Not run because an earlier step failed or the run was aborted
+    Skipped,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StepReport {
+    pub index: usize,
+    pub name: String,
+    pub status: StepStatus,
+    pub elapsed: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunReport {
+    pub title: String,
+    pub steps: Vec<StepReport>,
+    /// The cleanup tree, if one was run after an abort
+    pub cleanup: Option<StepReport>,
+    /// Final values of the blackboard
+    pub blackboard: BTreeMap<String, BlackboardValue>,
+    pub elapsed: Duration,
+}
+
+impl RunReport {
+    pub fn succeeded(&self) -> bool {
+        self.steps.iter().all(|s| s.status == StepStatus::Success)
+    }
+}
+
+/// Executes behavior trees against the modules of a library
+pub struct Executor<'a> {
+    pub library: &'a Library,
+    pub transport: Transport,
+    /// Parameters sent along with every leaf request
+    pub parameters: Vec<Value>,
+    control: Option<Receiver<Control>>,
+    state: Cell<RunState>,
+    journal: RefCell<Option<Journal>>,
+    journal_path: Option<PathBuf>,
+    resume: Option<ResumePoint>,
+    step_index: Cell<usize>,
+    blackboard: RefCell<Blackboard>,
+    subscribers: Vec<Subscriber<'a>>,
+}
+
+impl<'a> Executor<'a> {
+    pub fn new(library: &'a Library, transport: Transport) -> Self {
+        Self {
+            library,
+            transport,
+            parameters: Vec::new(),
+            control: None,
+            state: Cell::new(RunState::Running),
+            journal: RefCell::new(None),
+            journal_path: None,
+            resume: None,
+            step_index: Cell::new(0),
+            blackboard: RefCell::new(Blackboard::default()),
+            subscribers: Vec::new(),
+        }
+    }
+
+    /// Call `subscriber` with every event of the run
+    pub fn subscribe(mut self, subscriber: impl Fn(&Event) + 'a) -> Self {
+        self.subscribers.push(Box::new(subscriber));
+        self
+    }
+
+    /// Append the events of the run to `path` as JSON lines
+    pub fn with_run_log(self, path: &Path) -> Result<Self, Box<dyn Error>> {
+        info!("Logging the run to {}", path.display());
+        Ok(self.subscribe(json_lines(path)?))
+    }
+
+    pub(crate) fn emit(&self, path: &str, node: &str, duration: Duration, kind: EventKind) {
+        if self.subscribers.is_empty() {
+            return;
+        }
+        let event = Event {
+            timestamp: SystemTime::now(),
+            step_index: self.step_index.get(),
+            path: path.to_string(),
+            node: node.to_string(),
+            duration,
+            kind,
+        };
+        for subscriber in &self.subscribers {
+            subscriber(&event);
+        }
+    }
+
+    /// Save a journal of the run to `path` after every leaf node
+    pub fn with_journal(mut self, path: PathBuf) -> Self {
+        self.journal_path = Some(path);
+        self
+    }
+
+    /// Continue the run recorded in `journal`, saving further progress to `path`
+    pub fn resume(mut self, journal: Journal, path: PathBuf) -> Result<Self, Box<dyn Error>> {
+        let resume = journal
+            .resume_point(self.library)
+            .ok_or(format!("{} has already finished", journal.title))?;
+        info!(
+            "Resuming '{}' at step {} with {} completed action(s)",
+            journal.title,
+            resume.step_index + 1,
+            resume.completed.len()
+        );
+        if resume.aborted {
+            warn!("'{}' was aborted, running its cleanup again", journal.title);
+            self.state.set(RunState::Aborted);
+        }
+        self.parameters = journal.parameters.clone();
+        self.blackboard = RefCell::new(journal.blackboard.clone());
+        self.resume = Some(resume);
+        self.journal = RefCell::new(Some(journal));
+        self.journal_path = Some(path);
+        Ok(self)
+    }
+
+    fn start_journal(&self, journal: Journal) {
+        if self.journal_path.is_some() {
+            *self.journal.borrow_mut() = Some(journal);
+            self.save_journal(|_| ());
+        }
+    }
+
+    fn save_journal(&self, update: impl FnOnce(&mut Journal)) {
+        if let (Some(path), Some(journal)) =
+            (&self.journal_path, self.journal.borrow_mut().as_mut())
+        {
+            update(journal);
+            if let Err(e) = journal.save(path) {
+                error!("Failed to save the journal to {}: {}", path.display(), e);
+            }
+        }
+    }
+
+    /// True if the leaf at `path` completed before the run was resumed
+    fn completed_before(&self, path: &str) -> bool {
+        match &self.resume {
+            Some(resume) => {
+                resume.step_index == self.step_index.get() && resume.completed.contains(path)
+            }
+            None => false,
+        }
+    }
+
+    /// Accept pause, resume, step and abort commands while running
+    pub fn with_control(mut self, receiver: Receiver<Control>) -> Self {
+        self.control = Some(receiver);
+        self
+    }
+
+    pub fn is_aborted(&self) -> bool {
+        self.state.get() == RunState::Aborted
+    }
+
+    fn apply(&self, command: Control) {
+        let state = match (self.state.get(), command) {
+            (RunState::Aborted, _) | (RunState::CleaningUp, _) => return,
+            (_, Control::Pause) => RunState::Paused,
+            (_, Control::Resume) => RunState::Running,
+            (_, Control::Step) => RunState::Stepping,
+            (_, Control::Abort) => RunState::Aborted,
+        };
+        info!("{:?}: {:?} -> {:?}", command, self.state.get(), state);
+        self.state.set(state);
+    }
+
+    /// Apply the commands sent since the last call, without waiting
+    fn apply_pending(&self) {
+        if let Some(receiver) = &self.control {
+            while let Ok(command) = receiver.try_recv() {
+                self.apply(command);
+            }
+        }
+    }
+
+    /// Apply pending commands and wait while paused
+    async fn checkpoint(&self, node: &Node) -> Result<(), Box<dyn Error>> {
+        let receiver = match &self.control {
+            Some(receiver) => receiver,
+            None => return Ok(()),
+        };
+        self.apply_pending();
+        while self.state.get() == RunState::Paused {
+            info!(
+                "Paused before '{}', waiting for resume, step or abort",
+                node.name
+            );
+            match receiver.recv().await {
+                Ok(command) => self.apply(command),
+                Err(_) => {
+                    warn!("Control channel closed, resuming");
+                    self.state.set(RunState::Running);
+                }
+            }
+        }
+        if self.is_aborted() {
+            return Err(format!("Run aborted before {}", node.name).into());
+        }
+        Ok(())
+    }
+
+    pub async fn execute_leaf_node(
+        &self,
+        node: &Node,
+        path: &str,
+    ) -> Result<Option<LeafReply>, Box<dyn Error>> {
+        if self.completed_before(path) {
+            info!("Skipping {}, it completed before the run was resumed", path);
+            return Ok(None);
+        }
+        self.checkpoint(node).await?;
+        let result = self.dispatch_leaf_node(node).await;
+        if self.state.get() == RunState::Stepping {
+            self.state.set(RunState::Paused);
+        }
+
+        let (reply, error) = match &result {
+            Ok(reply) => (reply.clone(), None),
+            Err(e) => (None, Some(e.to_string())),
+        };
+        let cleanup = self.state.get() == RunState::CleaningUp;
+        self.save_journal(|journal| {
+            journal.blackboard = self.blackboard.borrow().clone();
+            let step_index = self.step_index.get();
+            let tree = match cleanup {
+                true => journal.cleanup.clone(),
+                false => journal.steps.get(step_index).map(|s| s.name.clone()),
+            };
+            journal.entries.push(JournalEntry {
+                step_index,
+                tree: tree.unwrap_or_default(),
+                path: path.to_string(),
+                node: node.name.clone(),
+                parameters: self.parameters.clone(),
+                reply,
+                error,
+                timestamp: SystemTime::now(),
+                cleanup,
+            })
+        });
+        // An abort sent while the last leaf of a run was busy must not be lost
+        self.apply_pending();
+        if result.is_ok() && self.is_aborted() {
+            return Err(format!("Run aborted after {}", node.name).into());
+        }
+        result
+    }
+
+    async fn dispatch_leaf_node(&self, node: &Node) -> Result<Option<LeafReply>, Box<dyn Error>> {
+        info!("Executing leaf node: {}", node.name);
+        let known_node = self
+            .library
+            .nodes
+            .content
+            .get(&node.name)
+            .ok_or(format!("Node {} is not a known node", node.name))?;
+        if let Some(expression) = &known_node.expression {
+            let holds = Expression::parse(expression)?.evaluate(&self.blackboard.borrow())?;
+            info!("  {} = {}", expression, holds);
+            if !holds {
+                return Err(format!("{} is false ({})", node.name, expression).into());
+            }
+        }
+        let zenoh = match &known_node.zenoh {
+            Some(zenoh) => zenoh,
+            None => {
+                debug!("{} has no modules to call", node.name);
+                return Ok(None);
+            }
+        };
+
+        let inputs = self.blackboard.borrow().select(&known_node.reads);
+        let reply = fan_out(
+            zenoh,
+            &node.name,
+            &self.parameters,
+            &inputs,
+            self.library,
+            &self.transport,
+        )
+        .await?;
+        for module_reply in &reply.replies {
+            match &module_reply.status {
+                ReplyStatus::Replied(values) => info!(
+                    "  {} replied in {:?}: {:?}",
+                    module_reply.module, module_reply.elapsed, values
+                ),
+                status => info!("  {}: {:?}", module_reply.module, status),
+            }
+        }
+        reply.evaluate()?;
+
+        let mut blackboard = self.blackboard.borrow_mut();
+        for module_reply in reply.replied() {
+            if let ReplyStatus::Replied(values) = &module_reply.status {
+                for value in values {
+                    if known_node.writes.contains(&value.schema.name) {
+                        blackboard.set_number(&value.schema.name, value.value)?;
+                    }
+                }
+            }
+        }
+        Ok(Some(reply))
+    }
+
+    /// Execute a node with its decorators and children
+    #[async_recursion(?Send)]
+    pub async fn execute_node(&self, node: &Node, path: &str) -> Result<(), Box<dyn Error>> {
+        trace!("Executing node: '{}'", path);
+        let start = Instant::now();
+        self.emit(path, &node.name, Duration::ZERO, EventKind::NodeEntered);
+        let result = self.execute_decorated(node, path, &node.decorators).await;
+
+        if let (Err(e), Some(error)) = (&result, &node.error) {
+            warn!("{} failed with {}: {}", node.name, error, e);
+            let info = self
+                .library
+                .nodes
+                .content
+                .get(error)
+                .and_then(|n| n.error.as_ref());
+            if let Some(info) = info {
+                error!("{} ({:?}): {}", error, info.class, info.message);
+                if info.requires_human {
+                    error!("{} needs someone at the instrument", error);
+                }
+            }
+            self.emit(
+                path,
+                &node.name,
+                start.elapsed(),
+                EventKind::ErrorHandler {
+                    handler: error.clone(),
+                    error: e.to_string(),
+                },
+            );
+        }
+        self.emit(
+            path,
+            &node.name,
+            start.elapsed(),
+            EventKind::NodeResult {
+                success: result.is_ok(),
+                error: result.as_ref().err().map(|e| e.to_string()),
+            },
+        );
+        result
+    }
+
+    /// Apply the last of `decorators` around the ones before it, which wrap the node itself
+    ///
+    /// An aborted run is never retried, inverted or forced to succeed.
+    #[async_recursion(?Send)]
+    async fn execute_decorated(
+        &self,
+        node: &Node,
+        path: &str,
+        decorators: &[Decorator],
+    ) -> Result<(), Box<dyn Error>> {
+        let (decorator, inner) = match decorators.split_last() {
+            Some(split) => split,
+            None => return self.execute_sequence(node, path).await,
+        };
+        match decorator {
+            Decorator::Retry { attempts, backoff } => {
+                let mut delay = match backoff {
+                    Some(backoff) => parse_duration(backoff)?,
+                    None => Duration::ZERO,
+                };
+                let mut attempt = 1;
+                loop {
+                    let start = Instant::now();
+                    match self.execute_decorated(node, path, inner).await {
+                        Ok(()) => return Ok(()),
+                        Err(e) if self.is_aborted() || attempt >= *attempts => return Err(e),
+                        Err(e) => {
+                            warn!(
+                                "{} failed on attempt {}/{}, retrying in {:?}: {}",
+                                node.name, attempt, attempts, delay, e
+                            );
+                            self.emit(
+                                path,
+                                &node.name,
+                                start.elapsed(),
+                                EventKind::Retry {
+                                    attempt,
+                                    error: e.to_string(),
+                                    wait: delay,
+                                },
+                            );
+                        }
+                    }
+                    async_std::task::sleep(delay).await;
+                    delay = delay.saturating_mul(2);
+                    attempt += 1;
+                }
+            }
+            Decorator::RepeatUntilSuccess { max } => {
+                let mut attempt = 1;
+                loop {
+                    let start = Instant::now();
+                    match self.execute_decorated(node, path, inner).await {
+                        Ok(()) => return Ok(()),
+                        Err(e) if self.is_aborted() || attempt >= *max => return Err(e),
+                        Err(e) => {
+                            debug!("{} repeat {}/{}: {}", node.name, attempt, max, e);
+                            self.emit(
+                                path,
+                                &node.name,
+                                start.elapsed(),
+                                EventKind::Retry {
+                                    attempt,
+                                    error: e.to_string(),
+                                    wait: Duration::ZERO,
+                                },
+                            );
+                        }
+                    }
+                    attempt += 1;
+                }
+            }
+            Decorator::Timeout { duration } => {
+                let timeout = parse_duration(duration)?;
+                match async_std::future::timeout(timeout, self.execute_decorated(node, path, inner))
+                    .await
+                {
+                    Ok(result) => result,
+                    Err(_) => Err(format!("{} timed out after {:?}", node.name, timeout).into()),
+                }
+            }
+            Decorator::Invert => match self.execute_decorated(node, path, inner).await {
+                Ok(()) => Err(format!("{} succeeded and is inverted", node.name).into()),
+                Err(e) if self.is_aborted() => Err(e),
+                Err(e) => {
+                    debug!("{} failed and is inverted: {}", node.name, e);
+                    Ok(())
+                }
+            },
+            Decorator::ForceSuccess => match self.execute_decorated(node, path, inner).await {
+                Err(e) if self.is_aborted() => Err(e),
+                Err(e) => {
+                    info!("{} failed, forced to succeed: {}", node.name, e);
+                    Ok(())
+                }
+                Ok(()) => Ok(()),
+            },
+        }
+    }
+
+    /// Execute a node and its children
+    /// - `children` succeed when every child succeeds, stopping at the first failure
+    /// - `fallback` succeeds at the first child that succeeds, failing if they all fail
+    async fn execute_sequence(&self, node: &Node, path: &str) -> Result<(), Box<dyn Error>> {
+        match &node.sequence {
+            Some(Sequence::Children(children)) => {
+                let mut result = Ok(());
+                for (index, child) in children.iter().enumerate() {
+                    result = self
+                        .execute_node(child, &node_path(path, index, child))
+                        .await;
+                    if result.is_err() {
+                        break;
+                    }
+                }
+                result
+            }
+            Some(Sequence::Fallback(children)) => {
+                let mut errors = Vec::new();
+                for (index, child) in children.iter().enumerate() {
+                    match self
+                        .execute_node(child, &node_path(path, index, child))
+                        .await
+                    {
+                        Ok(()) => break,
+                        Err(e) if self.is_aborted() => return Err(e),
+                        Err(e) => {
+                            debug!("Fallback '{}': '{}' failed, {}", node.name, child.name, e);
+                            errors.push(e.to_string());
+                        }
+                    }
+                }
+                if errors.len() == children.len() && !children.is_empty() {
+                    Err(format!(
+                        "All fallbacks of {} failed: {}",
+                        node.name,
+                        errors.join("; ")
+                    )
+                    .into())
+                } else {
+                    Ok(())
+                }
+            }
+            Some(Sequence::Parallel {
+                success_threshold,
+                children,
+            }) => {
+                let threshold = success_threshold.unwrap_or(children.len());
+                self.execute_parallel(node, path, children, threshold).await
+            }
+            None => self.execute_leaf_node(node, path).await.map(|_| ()),
+        }
+    }
+
+    /// Run the children concurrently, never two at once that call the same module
+    ///
+    /// Branches are started in order as soon as their modules are free. Once
+    /// `threshold` children succeeded, or too many failed to reach it, no more
+    /// branches are started and the running ones are left to finish.
+    async fn execute_parallel(
+        &self,
+        node: &Node,
+        path: &str,
+        children: &[Node],
+        threshold: usize,
+    ) -> Result<(), Box<dyn Error>> {
+        // Trees built in code or imported skip the validation of the library
+        if threshold == 0 || threshold > children.len() {
+            return Err(format!(
+                "Parallel node {} has a success threshold of {} for {} children",
+                node.name,
+                threshold,
+                children.len()
+            )
+            .into());
+        }
+        let modules = children
+            .iter()
+            .map(|child| node_modules(child, self.library))
+            .collect::<Vec<HashSet<String>>>();
+        let mut pending = (0..children.len()).collect::<Vec<usize>>();
+        let mut busy = HashSet::new();
+        let mut running = FuturesUnordered::new();
+        let mut succeeded = 0;
+        let mut errors = Vec::new();
+        let mut decided = false;
+
+        loop {
+            if !decided && !self.is_aborted() {
+                let mut waiting = Vec::new();
+                for index in pending.drain(..) {
+                    if !modules[index].is_disjoint(&busy) {
+                        debug!(
+                            "Parallel '{}': '{}' waits for {:?}",
+                            node.name,
+                            children[index].name,
+                            modules[index].intersection(&busy).collect::<Vec<_>>()
+                        );
+                        waiting.push(index);
+                        continue;
+                    }
+                    busy.extend(modules[index].iter().cloned());
+                    let child = &children[index];
+                    let child_path = node_path(path, index, child);
+                    running
+                        .push(async move { (index, self.execute_node(child, &child_path).await) });
+                }
+                pending = waiting;
+            }
+
+            let (index, result) = match running.next().await {
+                Some(finished) => finished,
+                None => break,
+            };
+            for module in &modules[index] {
+                busy.remove(module);
+            }
+            match result {
+                Ok(()) => succeeded += 1,
+                Err(e) => {
+                    debug!(
+                        "Parallel '{}': '{}' failed, {}",
+                        node.name, children[index].name, e
+                    );
+                    errors.push(format!("{}: {}", children[index].name, e));
+                }
+            }
+            decided = succeeded >= threshold || errors.len() > children.len() - threshold;
+        }
+
+        if !pending.is_empty() {
+            debug!(
+                "Parallel '{}': {} branch(es) not started",
+                node.name,
+                pending.len()
+            );
+        }
+        if self.is_aborted() {
+            return Err(format!("Run aborted in {}", node.name).into());
+        }
+        if succeeded >= threshold {
+            Ok(())
+        } else {
+            Err(format!(
+                "{} of {} branches of {} succeeded, {} required: {}",
+                succeeded,
+                children.len(),
+                node.name,
+                threshold,
+                errors.join("; ")
+            )
+            .into())
+        }
+    }
+
+    pub async fn execute_tree(&self, tree: &BehaviorTreeFile) -> Result<(), Box<dyn Error>> {
+        info!(
+            "Starting execution of a tree: '{}' ({})",
+            tree.title, tree.description
+        );
+        self.blackboard.borrow_mut().declare(&tree.blackboard)?;
+        self.execute_node(&tree.tree, &tree.tree.name).await
+    }
+
+    pub fn blackboard(&self) -> BTreeMap<String, BlackboardValue> {
+        self.blackboard.borrow().values().clone()
+    }
+
+    /// Execute the tree of every step in order, skipping the remaining steps after a failure
+    pub async fn execute_workflow(&self, workflow: &WorkflowFile) -> RunReport {
+        info!(
+            "Starting execution of a workflow: '{}' ({})",
+            workflow.title, workflow.description
+        );
+        self.start_journal(Journal::new(
+            &workflow.title,
+            Some(workflow.title.clone()),
+            workflow.workflow.clone(),
+            self.parameters.clone(),
+            workflow.cleanup.clone(),
+        ));
+        self.execute_steps(
+            &workflow.title,
+            &workflow.workflow,
+            workflow.cleanup.as_deref(),
+        )
+        .await
+    }
+
+    /// Execute a single tree as a one step run
+    pub async fn run_tree(&self, tree: &BehaviorTreeFile, cleanup: Option<&str>) -> RunReport {
+        let step = WorkflowStep {
+            name: tree.tree.name.clone(),
+            why: tree.description.clone(),
+            parameters: BTreeMap::new(),
+        };
+        self.start_journal(Journal::new(
+            &tree.title,
+            None,
+            vec![step.clone()],
+            self.parameters.clone(),
+            cleanup.map(String::from),
+        ));
+        self.execute_steps(&tree.title, &[step], cleanup).await
+    }
+
+    /// Continue the run loaded with `resume`
+    pub async fn execute_journal(&self) -> Result<RunReport, Box<dyn Error>> {
+        let (title, steps, cleanup) = match self.journal.borrow().as_ref() {
+            Some(journal) => (
+                journal.title.clone(),
+                journal.steps.clone(),
+                journal.cleanup.clone(),
+            ),
+            None => return Err("No journal to resume".into()),
+        };
+        Ok(self.execute_steps(&title, &steps, cleanup.as_deref()).await)
+    }
+
+    async fn execute_steps(
+        &self,
+        title: &str,
+        workflow_steps: &[WorkflowStep],
+        cleanup: Option<&str>,
+    ) -> RunReport {
+        let start = Instant::now();
+        let mut steps = Vec::new();
+        let mut failed = false;
+        self.emit(
+            title,
+            title,
+            Duration::ZERO,
+            EventKind::RunStarted {
+                steps: workflow_steps.iter().map(|s| s.name.clone()).collect(),
+            },
+        );
+        let resume_step = self.resume.as_ref().map_or(0, |r| r.step_index);
+        let tool_changes = plan_tool_changes(workflow_steps, &self.parameters, self.library)
+            .map_err(|e| e.to_string());
+        for (index, step) in workflow_steps.iter().enumerate() {
+            self.step_index.set(index);
+            let step_start = Instant::now();
+            let status = if index < resume_step {
+                info!(
+                    "Step {} {} completed before the run was resumed",
+                    index + 1,
+                    step.name
+                );
+                StepStatus::Success
+            } else if failed {
+                StepStatus::Skipped
+            } else if self.is_aborted() {
+                failed = true;
+                StepStatus::Aborted
+            } else {
+                info!(
+                    "Step {}/{}: {} ({})",
+                    index + 1,
+                    workflow_steps.len(),
+                    step.name,
+                    step.why
+                );
+                match self
+                    .execute_step_with_tools(index, step, &tool_changes)
+                    .await
+                {
+                    Ok(()) => StepStatus::Success,
+                    Err(_) if self.is_aborted() => {
+                        failed = true;
+                        StepStatus::Aborted
+                    }
+                    Err(e) => {
+                        failed = true;
+                        StepStatus::Failure(e.to_string())
+                    }
+                }
+            };
+            info!("Step {} {}: {:?}", index + 1, step.name, status);
+            steps.push(StepReport {
+                index,
+                name: step.name.clone(),
+                status,
+                elapsed: step_start.elapsed(),
+            });
+        }
+
+        let cleanup = match cleanup {
+            Some(cleanup) if self.is_aborted() => {
+                Some(self.execute_cleanup(steps.len(), cleanup).await)
+            }
+            _ => None,
+        };
+
+        // A failed run can be resumed once the cause is fixed
+        if !failed || self.is_aborted() {
+            self.save_journal(|journal| journal.finished = true);
+        }
+
+        let report = RunReport {
+            title: title.to_string(),
+            steps,
+            cleanup,
+            blackboard: self.blackboard.borrow().values().clone(),
+            elapsed: start.elapsed(),
+        };
+        self.emit(
+            title,
+            title,
+            report.elapsed,
+            EventKind::RunFinished {
+                success: report.succeeded(),
+            },
+        );
+        report
+    }
+
+    /// Execute the tree of the `index`th step of a run driven from outside the executor
+    pub(crate) async fn execute_tree_step(
+        &self,
+        index: usize,
+        tree: &BehaviorTreeFile,
+    ) -> Result<(), Box<dyn Error>> {
+        self.step_index.set(index);
+        self.execute_tree(tree).await
+    }
+
+    /// Mount the tools the step needs, then execute its tree
+    async fn execute_step_with_tools(
+        &self,
+        index: usize,
+        step: &WorkflowStep,
+        tool_changes: &Result<Vec<ToolChange>, String>,
+    ) -> Result<(), Box<dyn Error>> {
+        let tool_changes = tool_changes
+            .as_ref()
+            .map_err(|e| format!("Failed to choose the tools: {}", e))?;
+        for change in tool_changes.iter().filter(|c| c.step_index == index) {
+            self.change_tool(&step.name, change).await?;
+        }
+        self.execute_step(&step.name).await
+    }
+
+    /// Run the `pick_up` node of the tool at `<tree>/pick_up.<node>`, with the chosen variant on
+    /// the blackboard
+    async fn change_tool(
+        &self,
+        tree_name: &str,
+        change: &ToolChange,
+    ) -> Result<(), Box<dyn Error>> {
+        let path = change
+            .pick_up
+            .as_ref()
+            .map(|pick_up| format!("{}/pick_up.{}", tree_name, pick_up));
+        if path
+            .as_ref()
+            .is_some_and(|path| self.completed_before(path))
+        {
+            info!("{} was mounted before the run was resumed", change.tool);
+            return Ok(());
+        }
+        info!(
+            "Mounting {} {}",
+            change.tool,
+            change.variant.as_deref().unwrap_or_default()
+        );
+        if let Some(variant) = &change.variant {
+            let key = variant_key(&change.tool);
+            let mut blackboard = self.blackboard.borrow_mut();
+            blackboard.declare(&BTreeMap::from([(
+                key.clone(),
+                BlackboardKey {
+                    key_type: KeyType::String,
+                    description: format!("Variant of the {} in use", change.tool),
+                    unit: None,
+                    default: None,
+                },
+            )]))?;
+            blackboard.set(&key, BlackboardValue::String(variant.clone()))?;
+        }
+        match (&change.pick_up, &path) {
+            (Some(pick_up), Some(path)) => {
+                let node = Node {
+                    name: pick_up.clone(),
+                    step_number: 0,
+                    sequence: None,
+                    error: None,
+                    decorators: Vec::new(),
+                };
+                self.execute_node(&node, path).await
+            }
+            _ => {
+                warn!("{} has no pick_up node, mount it by hand", change.tool);
+                Ok(())
+            }
+        }
+    }
+
+    async fn execute_step(&self, tree_name: &str) -> Result<(), Box<dyn Error>> {
+        let tree = get_tree_by_name(tree_name, self.library)?;
+        self.execute_tree(tree).await
+    }
+
+    async fn execute_cleanup(&self, index: usize, tree_name: &str) -> StepReport {
+        warn!("Run aborted, running cleanup tree {}", tree_name);
+        self.state.set(RunState::CleaningUp);
+        self.step_index.set(index);
+        let start = Instant::now();
+        let status = match self.execute_step(tree_name).await {
+            Ok(()) => StepStatus::Success,
+            Err(e) => StepStatus::Failure(e.to_string()),
+        };
+        self.state.set(RunState::Aborted);
+        StepReport {
+            index,
+            name: tree_name.to_string(),
+            status,
+            elapsed: start.elapsed(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conf::{KnownNode, NodeType, ReplyMode, Zenoh};
+    use crate::reply::ModuleRequest;
+    use async_std::task::block_on;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    /// Fails the first two requests, counting them in `calls`
+    fn flaky(calls: &Arc<AtomicUsize>) -> Transport {
+        let calls = calls.clone();
+        Arc::new(move |_| {
+            let call = calls.fetch_add(1, Ordering::SeqCst);
+            Box::pin(async move {
+                match call {
+                    0 | 1 => Err("no tip found".to_string()),
+                    _ => Ok(vec![]),
+                }
+            })
+        })
+    }
+
+    fn working() -> Transport {
+        Arc::new(|_| Box::pin(async { Ok(vec![]) }))
+    }
+
+    fn library() -> Library {
+        let mut library = Library::default();
+        library.nodes.content.insert(
+            "get_tip".to_string(),
+            KnownNode {
+                node_type: NodeType::Action,
+                zenoh: Some(Zenoh {
+                    modules: vec!["gantry".to_string()],
+                    min_reply: ReplyMode::All,
+                }),
+                description: String::new(),
+                reads: Vec::new(),
+                writes: Vec::new(),
+                expression: None,
+                duration: None,
+                consumes: BTreeMap::new(),
+                error: None,
+                preconditions: Vec::new(),
+                postconditions: Vec::new(),
+                needs_tool: None,
+            },
+        );
+        library
+    }
+
+    /// Fails services starting with `fail`, answers those starting with `slow` after 50ms
+    #[derive(Default)]
+    struct Script {
+        /// Requests sent so far, as `module/service`
+        sent: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Script {
+        fn transport(&self) -> Transport {
+            let sent = self.sent.clone();
+            Arc::new(move |request: ModuleRequest| {
+                let name = format!("{}/{}", request.module, request.service);
+                sent.lock().unwrap().push(name);
+                Box::pin(async move {
+                    if request.service.starts_with("slow") {
+                        async_std::task::sleep(Duration::from_millis(50)).await;
+                    }
+                    match request.service.starts_with("fail") {
+                        true => Err(format!("{} failed", request.service)),
+                        false => Ok(vec![]),
+                    }
+                })
+            })
+        }
+
+        /// Take the requests sent since the last call
+        fn sent(&self) -> Vec<String> {
+            std::mem::take(&mut self.sent.lock().unwrap())
+        }
+
+        fn was_sent(&self, request: &str) -> bool {
+            self.sent.lock().unwrap().iter().any(|r| r == request)
+        }
+    }
+
+    /// Leaves of the gantry, pipette and centrifuge named after what `Script` does with them
+    fn scripted_library(trees: &[&str]) -> Library {
+        let mut library = Library {
+            nodes: serde_yaml::from_str(
+                "{title: n, description: d, version: 0.1.0, content: {\
+                 home: {type: action, description: d, zenoh: {modules: [gantry], min_reply: all}}, \
+                 get_tip: {type: action, description: d, zenoh: {modules: [gantry], min_reply: all}}, \
+                 fail_get_tip: {type: action, description: d, zenoh: {modules: [gantry], min_reply: all}}, \
+                 aspirate: {type: action, description: d, zenoh: {modules: [pipette], min_reply: all}}, \
+                 fail_aspirate: {type: action, description: d, zenoh: {modules: [pipette], min_reply: all}}, \
+                 slow_aspirate: {type: action, description: d, zenoh: {modules: [pipette], min_reply: all}}, \
+                 spin: {type: action, description: d, zenoh: {modules: [centrifuge], min_reply: all}}, \
+                 slow_spin: {type: action, description: d, zenoh: {modules: [centrifuge], min_reply: all}}}}",
+            )
+            .unwrap(),
+            ..Default::default()
+        };
+        for tree in trees {
+            library.trees.push(tree_file(tree));
+        }
+        library
+    }
+
+    /// A tree file around `tree`, e.g. `{name: t, step_number: 1, sequence: ...}`
+    fn tree_file(tree: &str) -> BehaviorTreeFile {
+        serde_yaml::from_str(&format!(
+            "{{title: t, version: 0.1.0, description: d, participant_modules: [], tree: {}}}",
+            tree
+        ))
+        .unwrap()
+    }
+
+    fn workflow(steps: &[&str], cleanup: Option<&str>) -> WorkflowFile {
+        let steps = steps
+            .iter()
+            .map(|s| format!("{{name: {}, why: w}}", s))
+            .collect::<Vec<_>>();
+        serde_yaml::from_str(&format!(
+            "{{title: w, description: d, version: 0.1.0, parameters: [], process_tldr: p, \
+             workflow: [{}], cleanup: {}}}",
+            steps.join(", "),
+            cleanup.unwrap_or("null")
+        ))
+        .unwrap()
+    }
+
+    fn get_tip(decorators: Vec<Decorator>) -> Node {
+        Node {
+            name: "get_tip".to_string(),
+            step_number: 1,
+            sequence: None,
+            error: None,
+            decorators,
+        }
+    }
+
+    #[test]
+    fn retry_runs_a_flaky_node_again() {
+        let library = library();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let executor = Executor::new(&library, flaky(&calls));
+        let node = get_tip(vec![Decorator::Retry {
+            attempts: 3,
+            backoff: None,
+        }]);
+        assert!(block_on(executor.execute_node(&node, "get_tip")).is_ok());
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn decorators_apply_from_the_inside_out() {
+        let library = library();
+        let executor = Executor::new(&library, working());
+        let node = get_tip(vec![Decorator::Invert]);
+        assert!(block_on(executor.execute_node(&node, "get_tip")).is_err());
+        let node = get_tip(vec![Decorator::Invert, Decorator::ForceSuccess]);
+        assert!(block_on(executor.execute_node(&node, "get_tip")).is_ok());
+        let node = get_tip(vec![Decorator::ForceSuccess, Decorator::Invert]);
+        assert!(block_on(executor.execute_node(&node, "get_tip")).is_err());
+    }
+
+    #[test]
+    fn fallback_stops_at_the_first_child_that_succeeds() {
+        let script = Script::default();
+        let library = scripted_library(&[]);
+        let executor = Executor::new(&library, script.transport());
+        let tree = tree_file(
+            "{name: pick, step_number: 1, sequence: !fallback [\
+             {name: fail_get_tip, step_number: 1}, {name: get_tip, step_number: 2}, \
+             {name: home, step_number: 3}]}",
+        );
+        assert!(block_on(executor.execute_tree(&tree)).is_ok());
+        assert_eq!(script.sent(), vec!["gantry/fail_get_tip", "gantry/get_tip"]);
+    }
+
+    #[test]
+    fn fallback_fails_when_every_child_fails() {
+        let script = Script::default();
+        let library = scripted_library(&[]);
+        let executor = Executor::new(&library, script.transport());
+        let tree = tree_file(
+            "{name: pick, step_number: 1, sequence: !fallback [\
+             {name: fail_get_tip, step_number: 1}, {name: fail_aspirate, step_number: 2}]}",
+        );
+        let error = block_on(executor.execute_tree(&tree)).unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("All fallbacks of pick failed"));
+        assert_eq!(
+            script.sent(),
+            vec!["gantry/fail_get_tip", "pipette/fail_aspirate"]
+        );
+    }
+
+    #[test]
+    fn steps_after_a_failure_are_skipped() {
+        let script = Script::default();
+        let library = scripted_library(&[
+            "{name: tip, step_number: 1, sequence: !children [{name: get_tip, step_number: 1}]}",
+            "{name: prep, step_number: 1, sequence: !children [\
+             {name: aspirate, step_number: 1}, {name: fail_aspirate, step_number: 2}]}",
+        ]);
+        let executor = Executor::new(&library, script.transport());
+        let report = block_on(executor.execute_workflow(&workflow(&["tip", "prep", "tip"], None)));
+        let statuses = report.steps.iter().map(|s| &s.status).collect::<Vec<_>>();
+        assert_eq!(statuses[0], &StepStatus::Success);
+        assert!(matches!(statuses[1], StepStatus::Failure(e) if e.contains("fail_aspirate")));
+        assert_eq!(statuses[2], &StepStatus::Skipped);
+        assert_eq!(
+            report.steps.iter().map(|s| s.index).collect::<Vec<_>>(),
+            vec![0, 1, 2]
+        );
+        assert!(!report.succeeded());
+        assert_eq!(report.cleanup, None);
+        assert_eq!(
+            script.sent(),
+            vec![
+                "gantry/get_tip",
+                "pipette/aspirate",
+                "pipette/fail_aspirate"
+            ]
+        );
+    }
+
+    async fn wait_until(condition: impl Fn() -> bool) {
+        while !condition() {
+            async_std::task::sleep(Duration::from_millis(1)).await;
+        }
+    }
+
+    #[test]
+    fn step_runs_one_leaf_while_paused() {
+        let script = Script::default();
+        let library = scripted_library(&[]);
+        let (sender, receiver) = async_std::channel::unbounded();
+        let executor = Executor::new(&library, script.transport()).with_control(receiver);
+        let tree = tree_file(
+            "{name: prep, step_number: 1, sequence: !children [\
+             {name: get_tip, step_number: 1}, {name: aspirate, step_number: 2}, \
+             {name: home, step_number: 3}]}",
+        );
+        sender.try_send(Control::Pause).unwrap();
+        let operator = async {
+            wait_until(|| executor.state.get() == RunState::Paused).await;
+            assert!(script.sent().is_empty());
+            sender.send(Control::Step).await.unwrap();
+            wait_until(|| {
+                executor.state.get() == RunState::Paused && script.was_sent("gantry/get_tip")
+            })
+            .await;
+            assert_eq!(script.sent(), vec!["gantry/get_tip"]);
+            sender.send(Control::Resume).await.unwrap();
+        };
+        let (result, ()) = block_on(futures::future::join(
+            executor.execute_tree(&tree),
+            operator,
+        ));
+        assert!(result.is_ok());
+        assert_eq!(script.sent(), vec!["pipette/aspirate", "gantry/home"]);
+        assert_eq!(executor.state.get(), RunState::Running);
+    }
+
+    #[test]
+    fn abort_skips_the_rest_and_runs_the_cleanup() {
+        let script = Script::default();
+        let library = scripted_library(&[
+            "{name: prep, step_number: 1, sequence: !children [\
+             {name: slow_aspirate, step_number: 1}, {name: aspirate, step_number: 2}]}",
+            "{name: tip, step_number: 1, sequence: !children [{name: get_tip, step_number: 1}]}",
+            "{name: park, step_number: 1, sequence: !children [{name: home, step_number: 1}]}",
+        ]);
+        let (sender, receiver) = async_std::channel::unbounded();
+        let executor = Executor::new(&library, script.transport()).with_control(receiver);
+        let operator = async {
+            wait_until(|| script.was_sent("pipette/slow_aspirate")).await;
+            sender.send(Control::Abort).await.unwrap();
+        };
+        let workflow = workflow(&["prep", "tip"], Some("park"));
+        let (report, ()) = block_on(futures::future::join(
+            executor.execute_workflow(&workflow),
+            operator,
+        ));
+        assert_eq!(report.steps[0].status, StepStatus::Aborted);
+        assert_eq!(report.steps[1].status, StepStatus::Skipped);
+        let cleanup = report.cleanup.unwrap();
+        assert_eq!(
+            (cleanup.name.as_str(), cleanup.status),
+            ("park", StepStatus::Success)
+        );
+        assert_eq!(script.sent(), vec!["pipette/slow_aspirate", "gantry/home"]);
+        assert!(executor.is_aborted());
+    }
+
+    #[test]
+    fn abort_during_the_last_leaf_is_not_lost() {
+        let script = Script::default();
+        let library = scripted_library(&[
+            "{name: prep, step_number: 1, sequence: !children [\
+             {name: get_tip, step_number: 1}, {name: slow_spin, step_number: 2}]}",
+            "{name: park, step_number: 1, sequence: !children [{name: home, step_number: 1}]}",
+        ]);
+        let (sender, receiver) = async_std::channel::unbounded();
+        let executor = Executor::new(&library, script.transport()).with_control(receiver);
+        let operator = async {
+            wait_until(|| script.was_sent("centrifuge/slow_spin")).await;
+            sender.send(Control::Abort).await.unwrap();
+        };
+        let workflow = workflow(&["prep"], Some("park"));
+        let (report, ()) = block_on(futures::future::join(
+            executor.execute_workflow(&workflow),
+            operator,
+        ));
+        assert_eq!(report.steps[0].status, StepStatus::Aborted);
+        assert!(!report.succeeded());
+        assert_eq!(report.cleanup.unwrap().status, StepStatus::Success);
+        assert_eq!(
+            script.sent(),
+            vec!["gantry/get_tip", "centrifuge/slow_spin", "gantry/home"]
+        );
+    }
+
+    #[test]
+    fn resuming_a_crashed_cleanup_runs_it_again() {
+        let script = Script::default();
+        let library = scripted_library(&[
+            "{name: tip, step_number: 1, sequence: !children [{name: get_tip, step_number: 1}]}",
+            "{name: park, step_number: 1, sequence: !children [{name: home, step_number: 1}]}",
+        ]);
+        let workflow = workflow(&["tip", "tip"], Some("park"));
+        let mut journal = Journal::new(
+            "w",
+            Some("w".to_string()),
+            workflow.workflow.clone(),
+            Vec::new(),
+            workflow.cleanup.clone(),
+        );
+        for (step_index, path, cleanup) in [(0, "tip/0.get_tip", false), (2, "park/0.home", true)] {
+            journal.entries.push(JournalEntry {
+                step_index,
+                tree: String::new(),
+                path: path.to_string(),
+                node: path.rsplit('.').next().unwrap().to_string(),
+                parameters: Vec::new(),
+                reply: None,
+                error: None,
+                timestamp: SystemTime::now(),
+                cleanup,
+            });
+        }
+        let dir = tempfile::tempdir().unwrap();
+        let executor = Executor::new(&library, script.transport())
+            .resume(journal, dir.path().join("w.json"))
+            .unwrap();
+        let report = block_on(executor.execute_journal()).unwrap();
+        assert_eq!(report.steps[0].status, StepStatus::Aborted);
+        assert_eq!(report.steps[1].status, StepStatus::Skipped);
+        assert_eq!(report.cleanup.unwrap().status, StepStatus::Success);
+        assert_eq!(script.sent(), vec!["gantry/home"]);
+    }
+
+    #[test]
+    fn aborting_a_resumed_run_keeps_the_steps_done_before() {
+        let script = Script::default();
+        let library = scripted_library(&[
+            "{name: tip, step_number: 1, sequence: !children [{name: get_tip, step_number: 1}]}",
+            "{name: park, step_number: 1, sequence: !children [{name: home, step_number: 1}]}",
+        ]);
+        let workflow = workflow(&["tip", "tip", "tip"], Some("park"));
+        let mut journal = Journal::new(
+            "w",
+            Some("w".to_string()),
+            workflow.workflow.clone(),
+            Vec::new(),
+            workflow.cleanup.clone(),
+        );
+        // Aborted during the second step, the cleanup died before it finished
+        for (step_index, path, cleanup) in [
+            (0, "tip/0.get_tip", false),
+            (1, "tip/0.get_tip", false),
+            (3, "park/0.home", true),
+        ] {
+            journal.entries.push(JournalEntry {
+                step_index,
+                tree: String::new(),
+                path: path.to_string(),
+                node: path.rsplit('.').next().unwrap().to_string(),
+                parameters: Vec::new(),
+                reply: None,
+                error: None,
+                timestamp: SystemTime::now(),
+                cleanup,
+            });
+        }
+        let dir = tempfile::tempdir().unwrap();
+        let executor = Executor::new(&library, script.transport())
+            .resume(journal, dir.path().join("w.json"))
+            .unwrap();
+        let report = block_on(executor.execute_journal()).unwrap();
+        let statuses = report
+            .steps
+            .iter()
+            .map(|s| s.status.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            statuses,
+            vec![
+                StepStatus::Success,
+                StepStatus::Aborted,
+                StepStatus::Skipped
+            ]
+        );
+        assert_eq!(script.sent(), vec!["gantry/home"]);
+    }
+
+    fn parallel(threshold: Option<usize>, children: &[&str]) -> BehaviorTreeFile {
+        let children = children
+            .iter()
+            .enumerate()
+            .map(|(i, name)| format!("{{name: {}, step_number: {}}}", name, i + 1))
+            .collect::<Vec<_>>();
+        tree_file(&format!(
+            "{{name: both, step_number: 1, sequence: !parallel {{success_threshold: {}, \
+             children: [{}]}}}}",
+            threshold.map_or("null".to_string(), |t| t.to_string()),
+            children.join(", ")
+        ))
+    }
+
+    #[test]
+    fn parallel_succeeds_once_the_threshold_is_reached() {
+        let script = Script::default();
+        let library = scripted_library(&[]);
+        let executor = Executor::new(&library, script.transport());
+        let tree = parallel(Some(1), &["fail_aspirate", "spin"]);
+        assert!(block_on(executor.execute_tree(&tree)).is_ok());
+
+        let tree = parallel(Some(2), &["fail_aspirate", "fail_get_tip", "spin"]);
+        let error = block_on(executor.execute_tree(&tree)).unwrap_err();
+        assert!(error.to_string().contains("2 required"), "{}", error);
+
+        let tree = parallel(Some(3), &["aspirate", "spin"]);
+        assert!(block_on(executor.execute_tree(&tree)).is_err());
+    }
+
+    #[test]
+    fn parallel_starts_no_branch_once_decided() {
+        let script = Script::default();
+        let library = scripted_library(&[]);
+        let executor = Executor::new(&library, script.transport());
+        // home waits for the gantry, get_tip reaches the threshold first
+        let tree = parallel(Some(1), &["get_tip", "home"]);
+        assert!(block_on(executor.execute_tree(&tree)).is_ok());
+        assert_eq!(script.sent(), vec!["gantry/get_tip"]);
+    }
+
+    #[test]
+    fn parallel_branches_take_turns_on_a_module() {
+        let script = Script::default();
+        let library = scripted_library(&[]);
+        let executor = Executor::new(&library, script.transport());
+        let tree = parallel(None, &["slow_aspirate", "aspirate", "spin"]);
+        assert!(block_on(executor.execute_tree(&tree)).is_ok());
+        let sent = script.sent();
+        let mut first = sent[..2].to_vec();
+        first.sort();
+        assert_eq!(first, vec!["centrifuge/spin", "pipette/slow_aspirate"]);
+        assert_eq!(sent[2], "pipette/aspirate");
+    }
+
+    #[test]
+    fn tools_mounted_before_a_crash_are_not_mounted_again() {
+        let script = Script::default();
+        let mut library = scripted_library(&["{name: prep, step_number: 1, sequence: !children [\
+             {name: aspirate, step_number: 1}, {name: fail_aspirate, step_number: 2}]}"]);
+        library.tools = serde_yaml::from_str(
+            "{name: Tools, version: 0.1.0, content: {pipette: {name: Pipette, description: p, \
+             pick_up: get_tip}}}",
+        )
+        .unwrap();
+        library
+            .nodes
+            .content
+            .get_mut("aspirate")
+            .unwrap()
+            .needs_tool = Some("pipette".into());
+        let workflow = workflow(&["prep"], None);
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("w.json");
+
+        let executor = Executor::new(&library, script.transport()).with_journal(path.clone());
+        assert!(!block_on(executor.execute_workflow(&workflow)).succeeded());
+        assert_eq!(
+            script.sent(),
+            vec![
+                "gantry/get_tip",
+                "pipette/aspirate",
+                "pipette/fail_aspirate"
+            ]
+        );
+        let journal = Journal::load(&path).unwrap();
+        assert_eq!(journal.entries[0].path, "prep/pick_up.get_tip");
+
+        let executor = Executor::new(&library, script.transport())
+            .resume(journal, path)
+            .unwrap();
+        assert!(!block_on(executor.execute_journal()).unwrap().succeeded());
+        assert_eq!(script.sent(), vec!["pipette/fail_aspirate"]);
+    }
+}
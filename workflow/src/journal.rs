@@ -0,0 +1,214 @@
+use crate::blackboard::Blackboard;
+use crate::conf::{Library, NodeType, Value, WorkflowStep};
+use crate::reply::LeafReply;
+use log::{debug, trace};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Record of a run, saved after every leaf node so the run can be resumed after a crash
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Journal {
+    pub title: String,
+    /// Title of the workflow, `None` for single tree runs
+    pub workflow: Option<String>,
+    pub steps: Vec<WorkflowStep>,
+    pub parameters: Vec<Value>,
+    pub cleanup: Option<String>,
+    pub started_at: SystemTime,
+    pub entries: Vec<JournalEntry>,
+    /// The blackboard after the last leaf node
+    #[serde(default)]
+    pub blackboard: Blackboard,
+    /// The run completed or was aborted, there is nothing left to resume
+    pub finished: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub step_index: usize,
+    pub tree: String,
+    /// Position of the leaf in the tree, see `node_path`
+    pub path: String,
+    pub node: String,
+    pub parameters: Vec<Value>,
+    pub reply: Option<LeafReply>,
+    pub error: Option<String>,
+    pub timestamp: SystemTime,
+    /// Recorded while the cleanup tree ran after an abort, not progress of a step
+    #[serde(default)]
+    pub cleanup: bool,
+}
+
+impl JournalEntry {
+    pub fn succeeded(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// Where a resumed run picks up
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResumePoint {
+    pub step_index: usize,
+    /// Paths of the actions that completed in that step and are not repeated
+    pub completed: HashSet<String>,
+    /// The run was aborted and stopped while its cleanup tree ran, which is run again
+    pub aborted: bool,
+}
+
+impl Journal {
+    pub fn new(
+        title: &str,
+        workflow: Option<String>,
+        steps: Vec<WorkflowStep>,
+        parameters: Vec<Value>,
+        cleanup: Option<String>,
+    ) -> Self {
+        Self {
+            title: title.to_string(),
+            workflow,
+            steps,
+            parameters,
+            cleanup,
+            started_at: SystemTime::now(),
+            entries: Vec::new(),
+            blackboard: Blackboard::default(),
+            finished: false,
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let file = File::open(path)
+            .map_err(|e| format!("Failed to open journal {}: {}", path.display(), e))?;
+        let journal: Journal = serde_json::from_reader(file)?;
+        trace!("{:#?}", journal);
+        Ok(journal)
+    }
+
+    /// Write the journal next to `path` and move it in place, so a crash never leaves half a journal
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut tmp_path = PathBuf::from(path);
+        tmp_path.set_extension("tmp");
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(serde_json::to_string_pretty(self)?.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, path)?;
+        debug!("Journal saved to {}", path.display());
+        Ok(())
+    }
+
+    /// The step that was running when the journal was last saved
+    ///
+    /// Actions that succeeded in that step are skipped when resuming, condition
+    /// nodes are evaluated again so the tree follows the current state of the hardware.
+    pub fn resume_point(&self, library: &Library) -> Option<ResumePoint> {
+        if self.finished {
+            return None;
+        }
+        let steps = self.entries.iter().filter(|e| !e.cleanup);
+        let step_index = steps.clone().next_back().map_or(0, |e| e.step_index);
+        let completed = steps
+            .filter(|e| e.step_index == step_index && e.succeeded())
+            .filter(|e| {
+                library
+                    .nodes
+                    .content
+                    .get(&e.node)
+                    .is_none_or(|n| n.node_type != NodeType::Condition)
+            })
+            .map(|e| e.path.clone())
+            .collect();
+        Some(ResumePoint {
+            step_index,
+            completed,
+            aborted: self.entries.iter().any(|e| e.cleanup),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conf::KnownNode;
+    use std::collections::BTreeMap;
+
+    fn entry(step_index: usize, path: &str, node: &str, error: Option<&str>) -> JournalEntry {
+        JournalEntry {
+            step_index,
+            tree: String::new(),
+            path: path.to_string(),
+            node: node.to_string(),
+            parameters: Vec::new(),
+            reply: None,
+            error: error.map(String::from),
+            timestamp: SystemTime::now(),
+            cleanup: false,
+        }
+    }
+
+    fn known_node(node_type: NodeType) -> KnownNode {
+        KnownNode {
+            node_type,
+            zenoh: None,
+            description: String::new(),
+            reads: Vec::new(),
+            writes: Vec::new(),
+            expression: None,
+            duration: None,
+            consumes: BTreeMap::new(),
+            error: None,
+            preconditions: Vec::new(),
+            postconditions: Vec::new(),
+            needs_tool: None,
+        }
+    }
+
+    #[test]
+    fn resume_repeats_conditions_and_failed_actions() {
+        let mut library = Library::default();
+        let nodes = &mut library.nodes.content;
+        nodes.insert(
+            "is_tip_available".to_string(),
+            known_node(NodeType::Condition),
+        );
+        nodes.insert("get_tip".to_string(), known_node(NodeType::Action));
+        nodes.insert("aspirate".to_string(), known_node(NodeType::Action));
+
+        let mut journal = Journal::new("tb_pcr", None, Vec::new(), Vec::new(), None);
+        journal.entries = vec![
+            entry(0, "home/0.home", "home", None),
+            entry(1, "tip/0.is_tip_available", "is_tip_available", None),
+            entry(1, "tip/1.get_tip", "get_tip", None),
+            entry(1, "tip/2.aspirate", "aspirate", Some("timed out")),
+        ];
+
+        let resume = journal.resume_point(&library).unwrap();
+        assert_eq!(resume.step_index, 1);
+        assert_eq!(
+            resume.completed,
+            HashSet::from(["tip/1.get_tip".to_string()])
+        );
+
+        assert!(!resume.aborted);
+
+        // A crash while cleaning up resumes the abort, not the last step
+        journal.entries.push(JournalEntry {
+            cleanup: true,
+            ..entry(3, "park/0.home", "home", None)
+        });
+        let resume = journal.resume_point(&library).unwrap();
+        assert_eq!(resume.step_index, 1);
+        assert!(!resume.completed.contains("park/0.home"));
+        assert!(resume.aborted);
+
+        journal.finished = true;
+        assert!(journal.resume_point(&library).is_none());
+    }
+}
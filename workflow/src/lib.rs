@@ -1,5 +1,19 @@
 // Declare the module
+pub mod analysis;
+pub mod apispec;
+pub mod blackboard;
+pub mod btcpp;
+pub mod conditions;
 pub mod conf;
+pub mod estimate;
+pub mod events;
+pub mod executor;
+pub mod journal;
+pub mod profile;
+pub mod reply;
+pub mod scheduler;
+pub mod schema;
+pub mod tools;
 
 // Reexport the module
 pub use conf::*;
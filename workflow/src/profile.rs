@@ -0,0 +1,369 @@
+use crate::conf::{get_tree_by_name, node_path, Library, Node, Sequence, WorkflowFile};
+use crate::events::{read_run_log, Event, EventKind};
+use log::{debug, warn};
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+/// Durations of one node across runs
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct NodeStats {
+    pub node: String,
+    pub durations: Vec<Duration>,
+    pub failures: usize,
+    /// Attempts that failed and were retried by a decorator
+    pub retries: usize,
+    /// Durations of every single attempt, retried or not. `durations` include every attempt
+    /// and the waits between them
+    pub attempts: Vec<Duration>,
+}
+
+impl NodeStats {
+    pub fn count(&self) -> usize {
+        self.durations.len()
+    }
+
+    pub fn total(&self) -> Duration {
+        self.durations.iter().sum()
+    }
+
+    pub fn mean(&self) -> Duration {
+        match self.count() {
+            0 => Duration::ZERO,
+            count => self.total() / count as u32,
+        }
+    }
+
+    pub fn min(&self) -> Duration {
+        self.durations.iter().min().copied().unwrap_or_default()
+    }
+
+    pub fn max(&self) -> Duration {
+        self.durations.iter().max().copied().unwrap_or_default()
+    }
+
+    /// Share of the runs in which the node failed, retries included
+    pub fn failure_rate(&self) -> f64 {
+        match self.count() {
+            0 => 0.0,
+            count => self.failures as f64 / count as f64,
+        }
+    }
+
+    /// Share of the attempts that failed, counting every retry as an attempt
+    pub fn attempt_failure_rate(&self) -> f64 {
+        match self.count() + self.retries {
+            0 => 0.0,
+            attempts => (self.failures + self.retries) as f64 / attempts as f64,
+        }
+    }
+
+    /// The duration `p` percent of the runs stayed under, nearest rank
+    pub fn percentile(&self, p: f64) -> Duration {
+        let mut sorted = self.durations.clone();
+        sorted.sort();
+        match sorted.len() {
+            0 => Duration::ZERO,
+            len => {
+                let rank = ((p / 100.0) * len as f64).ceil() as usize;
+                sorted[rank.clamp(1, len) - 1]
+            }
+        }
+    }
+
+    /// Number of runs in each of `bins` equal width buckets between the fastest and slowest run
+    pub fn histogram(&self, bins: usize) -> Vec<usize> {
+        let mut counts = vec![0; bins];
+        let min = self.min();
+        let width = (self.max() - min).as_secs_f64() / bins as f64;
+        for duration in &self.durations {
+            let bin = if width > 0.0 {
+                ((*duration - min).as_secs_f64() / width) as usize
+            } else {
+                0
+            };
+            counts[bin.min(bins - 1)] += 1;
+        }
+        counts
+    }
+}
+
+/// Timing of every node across the run logs of a workflow
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Profile {
+    pub runs: usize,
+    /// Keyed by node path, see `node_path`
+    pub nodes: BTreeMap<String, NodeStats>,
+}
+
+impl Profile {
+    /// Add the `node_result` and `retry` events of a run
+    pub fn add_run(&mut self, events: &[Event]) {
+        self.runs += 1;
+        // Time spent in the retried attempts and their waits since each node last finished
+        let mut retried = BTreeMap::new();
+        for event in events {
+            let stats = match &event.kind {
+                EventKind::NodeResult { .. } | EventKind::Retry { .. } => self
+                    .nodes
+                    .entry(event.path.clone())
+                    .or_insert_with(|| NodeStats {
+                        node: event.node.clone(),
+                        ..Default::default()
+                    }),
+                _ => continue,
+            };
+            match &event.kind {
+                EventKind::NodeResult { success, .. } => {
+                    stats.durations.push(event.duration);
+                    if !success {
+                        stats.failures += 1;
+                    }
+                    let spent = retried.remove(&event.path).unwrap_or_default();
+                    stats.attempts.push(event.duration.saturating_sub(spent));
+                }
+                EventKind::Retry { wait, .. } => {
+                    stats.retries += 1;
+                    stats.attempts.push(event.duration);
+                    *retried.entry(event.path.clone()).or_default() += event.duration + *wait;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Statistics of `node` wherever it appears in a tree
+    pub fn node(&self, node: &str) -> Option<NodeStats> {
+        let mut merged: Option<NodeStats> = None;
+        for stats in self.nodes.values().filter(|s| s.node == node) {
+            let merged = merged.get_or_insert_with(|| NodeStats {
+                node: node.to_string(),
+                ..Default::default()
+            });
+            merged.durations.extend(stats.durations.iter().copied());
+            merged.failures += stats.failures;
+            merged.retries += stats.retries;
+            merged.attempts.extend(stats.attempts.iter().copied());
+        }
+        merged
+    }
+
+    /// Read every run log in `dir`, only keeping the runs of the workflow titled `title` if given
+    pub fn load_dir(dir: &Path, title: Option<&str>) -> Result<Self, Box<dyn Error>> {
+        let mut profile = Profile::default();
+        let entries = fs::read_dir(dir)
+            .map_err(|e| format!("Failed to read run logs in {}: {}", dir.display(), e))?;
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension() != Some(OsStr::new("jsonl")) {
+                continue;
+            }
+            let events = match read_run_log(&path) {
+                Ok(events) => events,
+                Err(e) => {
+                    warn!("Skipping run log: {}", e);
+                    continue;
+                }
+            };
+            let run_title = events.iter().find_map(|e| match e.kind {
+                EventKind::RunStarted { .. } => Some(e.path.as_str()),
+                _ => None,
+            });
+            if let Some(title) = title {
+                if !run_title.is_some_and(|run| is_run_of(run, title)) {
+                    continue;
+                }
+            }
+            debug!("Profiling {}", path.display());
+            profile.add_run(&events);
+        }
+        Ok(profile)
+    }
+
+    /// Leaf nodes, the ones taking the most time across all runs first
+    pub fn slowest(&self, library: &Library) -> Vec<(&String, &NodeStats)> {
+        let mut leaves = self
+            .nodes
+            .iter()
+            .filter(|(_, stats)| library.nodes.content.contains_key(&stats.node))
+            .collect::<Vec<_>>();
+        leaves.sort_by_key(|(_, stats)| Reverse(stats.total()));
+        leaves
+    }
+
+    /// Time spent in the leaf nodes calling each module
+    ///
+    /// A leaf calling several modules counts towards all of them.
+    pub fn module_time(&self, library: &Library) -> BTreeMap<String, Duration> {
+        let mut modules = BTreeMap::new();
+        for stats in self.nodes.values() {
+            let zenoh = library
+                .nodes
+                .content
+                .get(&stats.node)
+                .and_then(|n| n.zenoh.as_ref());
+            for module in zenoh.iter().flat_map(|z| z.modules.iter()) {
+                *modules.entry(module.clone()).or_insert(Duration::ZERO) += stats.total();
+            }
+        }
+        modules
+    }
+
+    /// The leaves that add up to the duration of a run of `workflow`, with their mean duration
+    ///
+    /// Every child of a sequence or fallback that ran is on the path, of a parallel node
+    /// only the slowest branch is.
+    pub fn critical_path(
+        &self,
+        workflow: &WorkflowFile,
+        library: &Library,
+    ) -> Result<Vec<(String, Duration)>, Box<dyn Error>> {
+        let mut path = Vec::new();
+        for step in &workflow.workflow {
+            let tree = get_tree_by_name(&step.name, library)?;
+            self.node_critical_path(&tree.tree, &tree.tree.name, &mut path);
+        }
+        Ok(path)
+    }
+
+    fn node_critical_path(&self, node: &Node, path: &str, critical: &mut Vec<(String, Duration)>) {
+        let stats = match self.nodes.get(path) {
+            Some(stats) => stats,
+            None => return,
+        };
+        match &node.sequence {
+            Some(Sequence::Parallel { children, .. }) => {
+                let slowest = children
+                    .iter()
+                    .enumerate()
+                    .map(|(index, child)| (node_path(path, index, child), child))
+                    .max_by_key(|(child_path, _)| {
+                        self.nodes
+                            .get(child_path)
+                            .map_or(Duration::ZERO, |s| s.mean())
+                    });
+                if let Some((child_path, child)) = slowest {
+                    self.node_critical_path(child, &child_path, critical);
+                }
+            }
+            Some(sequence) => {
+                for (index, child) in sequence.nodes().iter().enumerate() {
+                    self.node_critical_path(child, &node_path(path, index, child), critical);
+                }
+            }
+            None => critical.push((path.to_string(), stats.mean())),
+        }
+    }
+}
+
+/// Whether a run titled `run` is one of the workflow `title`, including the runs started by
+/// `schedule` that are titled `<title>#<n>`
+fn is_run_of(run: &str, title: &str) -> bool {
+    match run.strip_prefix(title) {
+        Some("") => true,
+        Some(rest) => rest
+            .strip_prefix('#')
+            .is_some_and(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit())),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::SystemTime;
+
+    fn result(path: &str, millis: u64) -> Event {
+        Event {
+            timestamp: SystemTime::now(),
+            step_index: 0,
+            path: path.to_string(),
+            node: path.rsplit('.').next().unwrap().to_string(),
+            duration: Duration::from_millis(millis),
+            kind: EventKind::NodeResult {
+                success: true,
+                error: None,
+            },
+        }
+    }
+
+    fn leaf(name: &str) -> Node {
+        Node {
+            name: name.to_string(),
+            step_number: 0,
+            sequence: None,
+            error: None,
+            decorators: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn critical_path_takes_the_slowest_parallel_branch() {
+        let tree = Node {
+            name: "prep".to_string(),
+            step_number: 0,
+            sequence: Some(Sequence::Children(vec![
+                leaf("home"),
+                Node {
+                    name: "both".to_string(),
+                    step_number: 0,
+                    sequence: Some(Sequence::Parallel {
+                        success_threshold: None,
+                        children: vec![leaf("spin"), leaf("aspirate")],
+                    }),
+                    error: None,
+                    decorators: Vec::new(),
+                },
+            ])),
+            error: None,
+            decorators: Vec::new(),
+        };
+        let mut profile = Profile::default();
+        profile.add_run(&[
+            result("prep/0.home", 100),
+            result("prep/1.both/0.spin", 900),
+            result("prep/1.both/1.aspirate", 300),
+            result("prep/1.both", 900),
+            result("prep", 1000),
+        ]);
+        let mut critical = Vec::new();
+        profile.node_critical_path(&tree, "prep", &mut critical);
+        assert_eq!(
+            critical,
+            vec![
+                ("prep/0.home".to_string(), Duration::from_millis(100)),
+                ("prep/1.both/0.spin".to_string(), Duration::from_millis(900)),
+            ]
+        );
+    }
+
+    #[test]
+    fn percentiles_and_histogram() {
+        let stats = NodeStats {
+            node: "spin".to_string(),
+            durations: [1, 2, 3, 4, 10]
+                .iter()
+                .map(|s| Duration::from_secs(*s))
+                .collect(),
+            failures: 0,
+            retries: 0,
+            attempts: Vec::new(),
+        };
+        assert_eq!(stats.percentile(50.0), Duration::from_secs(3));
+        assert_eq!(stats.percentile(90.0), Duration::from_secs(10));
+        assert_eq!(stats.histogram(3), vec![3, 1, 1]);
+    }
+
+    #[test]
+    fn scheduled_runs_belong_to_their_workflow() {
+        assert!(is_run_of("tb_pcr", "tb_pcr"));
+        assert!(is_run_of("tb_pcr#2", "tb_pcr"));
+        assert!(!is_run_of("tb_pcr_long", "tb_pcr"));
+        assert!(!is_run_of("tb_pcr#", "tb_pcr"));
+        assert!(!is_run_of("prep", "tb_pcr"));
+    }
+}
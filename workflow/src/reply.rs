@@ -0,0 +1,290 @@
+use crate::conf::{BlackboardValue, Library, ReplyMode, Value, Zenoh};
+use futures::future::BoxFuture;
+use futures::stream::{FuturesUnordered, StreamExt};
+use log::{debug, trace, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Timeout used when the module does not declare the service being called
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A single request sent to one module on behalf of a leaf node
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModuleRequest {
+    pub module: String,
+    pub service: String,
+    pub parameters: Vec<Value>,
+    /// The blackboard keys the node reads
+    pub blackboard: BTreeMap<String, BlackboardValue>,
+}
+
+/// Sends a request to a module and resolves with the values it responded with
+///
+/// A closure, so it can hold the session it sends the requests through.
+pub type Transport =
+    Arc<dyn Fn(ModuleRequest) -> BoxFuture<'static, Result<Vec<Value>, String>> + Send + Sync>;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ReplyStatus {
+    Replied(Vec<Value>),
+    Failed(String),
+    TimedOut(Duration),
+    /// The mode was satisfied before this module replied
+    NotAwaited,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModuleReply {
+    pub module: String,
+    pub status: ReplyStatus,
+    pub elapsed: Duration,
+}
+
+/// Per-module results of a leaf request
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LeafReply {
+    pub service: String,
+    pub mode: ReplyMode,
+    pub replies: Vec<ModuleReply>,
+}
+
+impl LeafReply {
+    pub fn replied(&self) -> impl Iterator<Item = &ModuleReply> {
+        self.replies
+            .iter()
+            .filter(|r| matches!(r.status, ReplyStatus::Replied(_)))
+    }
+
+    /// Check the replies against the reply mode
+    /// - `Any`: at least one module replied
+    /// - `All`: every module replied
+    /// - `One`: exactly one module replied
+    pub fn evaluate(&self) -> Result<(), Box<dyn Error>> {
+        let replied = self.replied().count();
+        let expected = self.replies.len();
+        let satisfied = match self.mode {
+            ReplyMode::Any => replied >= 1,
+            ReplyMode::All => replied == expected,
+            ReplyMode::One => replied == 1,
+        };
+        if satisfied {
+            return Ok(());
+        }
+
+        let failures = self
+            .replies
+            .iter()
+            .filter_map(|r| match &r.status {
+                ReplyStatus::Failed(e) => Some(format!("{}: {}", r.module, e)),
+                ReplyStatus::TimedOut(t) => Some(format!("{}: timed out after {:?}", r.module, t)),
+                _ => None,
+            })
+            .collect::<Vec<String>>();
+        Err(format!(
+            "{} expected {:?} reply from {} module(s), got {} [{}]",
+            self.service,
+            self.mode,
+            expected,
+            replied,
+            failures.join(", ")
+        )
+        .into())
+    }
+}
+
+/// Timeout of `service` as declared in the API of `module`
+pub fn service_timeout(
+    library: &Library,
+    module: &str,
+    service: &str,
+) -> Result<Duration, Box<dyn Error>> {
+    let declared = match library
+        .modules
+        .content
+        .get(module)
+        .and_then(|m| m.api.services.get(service))
+    {
+        Some(service) => service.timeout()?,
+        None => None,
+    };
+    Ok(declared.unwrap_or(DEFAULT_TIMEOUT))
+}
+
+/// Send the request to every module in `zenoh.modules` and wait according to `zenoh.min_reply`
+///
+/// With `Any` the remaining requests are dropped as soon as one module replies,
+/// `All` and `One` wait for every module to reply, fail or time out.
+pub async fn fan_out(
+    zenoh: &Zenoh,
+    service: &str,
+    parameters: &[Value],
+    blackboard: &BTreeMap<String, BlackboardValue>,
+    library: &Library,
+    transport: &Transport,
+) -> Result<LeafReply, Box<dyn Error>> {
+    let mut pending = FuturesUnordered::new();
+    for module in &zenoh.modules {
+        let timeout = service_timeout(library, module, service)?;
+        let request = ModuleRequest {
+            module: module.clone(),
+            service: service.to_string(),
+            parameters: parameters.to_vec(),
+            blackboard: blackboard.clone(),
+        };
+        trace!("Sending {:?} with timeout {:?}", request, timeout);
+        let response = transport(request);
+        let module = module.clone();
+        pending.push(async move {
+            let start = Instant::now();
+            let status = match async_std::future::timeout(timeout, response).await {
+                Ok(Ok(values)) => ReplyStatus::Replied(values),
+                Ok(Err(e)) => ReplyStatus::Failed(e),
+                Err(_) => ReplyStatus::TimedOut(timeout),
+            };
+            ModuleReply {
+                module,
+                status,
+                elapsed: start.elapsed(),
+            }
+        });
+    }
+
+    let mut replies = Vec::new();
+    while let Some(reply) = pending.next().await {
+        debug!(
+            "{} replied to {}: {:?}",
+            reply.module, service, reply.status
+        );
+        if let ReplyStatus::Failed(_) | ReplyStatus::TimedOut(_) = reply.status {
+            warn!("{} did not reply to {}", reply.module, service);
+        }
+        let done =
+            zenoh.min_reply == ReplyMode::Any && matches!(reply.status, ReplyStatus::Replied(_));
+        replies.push(reply);
+        if done {
+            break;
+        }
+    }
+    drop(pending);
+
+    // Keep the replies in the order the modules were declared
+    let replies = zenoh
+        .modules
+        .iter()
+        .map(|module| {
+            replies
+                .iter()
+                .find(|r| &r.module == module)
+                .cloned()
+                .unwrap_or(ModuleReply {
+                    module: module.clone(),
+                    status: ReplyStatus::NotAwaited,
+                    elapsed: Duration::ZERO,
+                })
+        })
+        .collect();
+
+    Ok(LeafReply {
+        service: service.to_string(),
+        mode: zenoh.min_reply,
+        replies,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reply(module: &str, status: ReplyStatus) -> ModuleReply {
+        ModuleReply {
+            module: module.to_string(),
+            status,
+            elapsed: Duration::ZERO,
+        }
+    }
+
+    fn leaf(mode: ReplyMode, replies: Vec<ModuleReply>) -> LeafReply {
+        LeafReply {
+            service: "aspirate".to_string(),
+            mode,
+            replies,
+        }
+    }
+
+    #[test]
+    fn any_needs_a_single_reply() {
+        let replies = vec![
+            reply("pipette_a", ReplyStatus::Replied(vec![])),
+            reply("pipette_b", ReplyStatus::NotAwaited),
+        ];
+        assert!(leaf(ReplyMode::Any, replies).evaluate().is_ok());
+
+        let replies = vec![
+            reply("pipette_a", ReplyStatus::Failed("jammed".to_string())),
+            reply("pipette_b", ReplyStatus::TimedOut(Duration::from_secs(1))),
+        ];
+        assert!(leaf(ReplyMode::Any, replies).evaluate().is_err());
+    }
+
+    #[test]
+    fn all_fails_on_a_missing_reply() {
+        let replies = vec![
+            reply("pipette_a", ReplyStatus::Replied(vec![])),
+            reply("pipette_b", ReplyStatus::Replied(vec![])),
+        ];
+        assert!(leaf(ReplyMode::All, replies).evaluate().is_ok());
+
+        let replies = vec![
+            reply("pipette_a", ReplyStatus::Replied(vec![])),
+            reply("pipette_b", ReplyStatus::TimedOut(Duration::from_secs(1))),
+        ];
+        assert!(leaf(ReplyMode::All, replies).evaluate().is_err());
+    }
+
+    #[test]
+    fn one_needs_exactly_one_reply() {
+        let replies = vec![
+            reply("gantry", ReplyStatus::Replied(vec![])),
+            reply("slider", ReplyStatus::Failed("busy".to_string())),
+        ];
+        assert!(leaf(ReplyMode::One, replies).evaluate().is_ok());
+
+        let replies = vec![
+            reply("gantry", ReplyStatus::Replied(vec![])),
+            reply("slider", ReplyStatus::Replied(vec![])),
+        ];
+        assert!(leaf(ReplyMode::One, replies).evaluate().is_err());
+    }
+
+    #[test]
+    fn fan_out_stops_early_for_any() {
+        fn transport(request: ModuleRequest) -> BoxFuture<'static, Result<Vec<Value>, String>> {
+            Box::pin(async move {
+                if request.module == "slow" {
+                    async_std::task::sleep(Duration::from_secs(5)).await;
+                }
+                Ok(vec![])
+            })
+        }
+        let library = Library::default();
+        let zenoh = Zenoh {
+            modules: vec!["slow".to_string(), "fast".to_string()],
+            min_reply: ReplyMode::Any,
+        };
+        let leaf = async_std::task::block_on(fan_out(
+            &zenoh,
+            "home",
+            &[],
+            &BTreeMap::new(),
+            &library,
+            &(Arc::new(transport) as Transport),
+        ))
+        .unwrap();
+        assert_eq!(leaf.replies[0].status, ReplyStatus::NotAwaited);
+        assert_eq!(leaf.replies[1].status, ReplyStatus::Replied(vec![]));
+        assert!(leaf.evaluate().is_ok());
+    }
+}
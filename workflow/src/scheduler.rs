@@ -0,0 +1,520 @@
+use crate::conf::{
+    get_tree_by_name, get_workflow_by_title, Access, BehaviorTreeFile, Library, Node, Sequence,
+    WorkflowFile,
+};
+use crate::events::{run_log_path, EventKind};
+use crate::executor::{Executor, RunReport, StepReport, StepStatus};
+use crate::reply::{service_timeout, Transport};
+use crate::tools::plan_tool_changes;
+use futures::stream::{FuturesUnordered, StreamExt};
+use log::{debug, info, warn};
+use std::cmp::Reverse;
+use std::collections::HashSet;
+use std::error::Error;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+/// Predicts how long a tree takes to run
+pub type Estimate = fn(&BehaviorTreeFile, &Library) -> Duration;
+
+/// A workflow to run on behalf of one sample
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunRequest {
+    pub id: String,
+    pub workflow: String,
+    /// Trees of runs with a higher priority get the modules first
+    pub priority: i32,
+}
+
+/// When a step of a run is expected to hold its modules, relative to the start of the schedule
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlannedStep {
+    pub run: String,
+    pub step_index: usize,
+    pub tree: String,
+    pub modules: Vec<String>,
+    pub start: Duration,
+    pub finish: Duration,
+}
+
+struct Run<'a> {
+    request: RunRequest,
+    workflow: &'a WorkflowFile,
+}
+
+/// A step waiting for its modules
+struct Queued {
+    run: usize,
+    priority: i32,
+    modules: Vec<String>,
+}
+
+/// Runs several workflows at once, handing out modules to their trees by priority
+pub struct Scheduler<'a> {
+    library: &'a Library,
+    runs: Vec<Run<'a>>,
+    pub estimate: Estimate,
+    /// Directory to write the log of every run to
+    pub log_dir: Option<PathBuf>,
+}
+
+impl<'a> Scheduler<'a> {
+    pub fn new(library: &'a Library) -> Self {
+        Self {
+            library,
+            runs: Vec::new(),
+            estimate: estimate_from_timeouts,
+            log_dir: None,
+        }
+    }
+
+    pub fn submit(&mut self, request: RunRequest) -> Result<(), Box<dyn Error>> {
+        if self.runs.iter().any(|r| r.request.id == request.id) {
+            return Err(format!("Run {} is already scheduled", request.id).into());
+        }
+        let workflow = get_workflow_by_title(&request.workflow, self.library)?;
+        // Runs share the modules, so one cannot know which tool another one left mounted
+        let changes = plan_tool_changes(&workflow.workflow, &workflow.parameters, self.library)?;
+        if let Some(change) = changes.first() {
+            return Err(format!(
+                "Run {} cannot be scheduled, step {} of {} needs {} to be mounted",
+                request.id,
+                change.step_index + 1,
+                request.workflow,
+                change.tool
+            )
+            .into());
+        }
+        info!(
+            "Queued run {} of {} with priority {}",
+            request.id, request.workflow, request.priority
+        );
+        self.runs.push(Run { request, workflow });
+        Ok(())
+    }
+
+    fn tree(&self, run: usize, step_index: usize) -> Result<&'a BehaviorTreeFile, Box<dyn Error>> {
+        let step = &self.runs[run].workflow.workflow[step_index];
+        get_tree_by_name(&step.name, self.library)
+    }
+
+    fn exclusive_modules(&self, tree: &BehaviorTreeFile) -> Vec<String> {
+        tree.participants
+            .iter()
+            .filter(|m| {
+                self.library
+                    .modules
+                    .content
+                    .get(*m)
+                    .is_none_or(|module| module.info.access == Access::Exclusive)
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// The runs that are not busy and have steps left, in the order they get to pick modules
+    fn queue(&self, next_step: &[usize], blocked: &[bool]) -> Result<Vec<Queued>, Box<dyn Error>> {
+        let mut queue = Vec::new();
+        for (run, step_index) in next_step.iter().enumerate() {
+            if blocked[run] || *step_index >= self.runs[run].workflow.workflow.len() {
+                continue;
+            }
+            queue.push(Queued {
+                run,
+                priority: self.runs[run].request.priority,
+                modules: self.exclusive_modules(self.tree(run, *step_index)?),
+            });
+        }
+        queue.sort_by_key(|q| (Reverse(q.priority), q.run));
+        Ok(queue)
+    }
+
+    /// Predict when each step starts and finishes, assuming every step succeeds
+    pub fn plan(&self) -> Result<Vec<PlannedStep>, Box<dyn Error>> {
+        let mut next_step = vec![0; self.runs.len()];
+        let mut in_flight = vec![false; self.runs.len()];
+        let mut held = HashSet::new();
+        let mut running: Vec<(Duration, usize, Vec<String>)> = Vec::new();
+        let mut planned = Vec::new();
+        let mut now = Duration::ZERO;
+
+        loop {
+            for queued in select(self.queue(&next_step, &in_flight)?, &held) {
+                let step_index = next_step[queued.run];
+                let tree = self.tree(queued.run, step_index)?;
+                let finish = now + (self.estimate)(tree, self.library);
+                planned.push(PlannedStep {
+                    run: self.runs[queued.run].request.id.clone(),
+                    step_index,
+                    tree: tree.tree.name.clone(),
+                    modules: tree.participants.clone(),
+                    start: now,
+                    finish,
+                });
+                held.extend(queued.modules.iter().cloned());
+                in_flight[queued.run] = true;
+                running.push((finish, queued.run, queued.modules));
+            }
+
+            let earliest = match running.iter().enumerate().min_by_key(|(_, r)| r.0) {
+                Some((index, _)) => index,
+                None => break,
+            };
+            let (finish, run, modules) = running.remove(earliest);
+            now = finish;
+            for module in &modules {
+                held.remove(module);
+            }
+            in_flight[run] = false;
+            next_step[run] += 1;
+        }
+        Ok(planned)
+    }
+
+    /// Run all the workflows, starting each tree as soon as its modules are free
+    ///
+    /// A failed step skips the remaining steps of its run, the other runs carry on.
+    pub async fn execute(&self, transport: Transport) -> Result<Vec<RunReport>, Box<dyn Error>> {
+        let mut executors = Vec::new();
+        for run in &self.runs {
+            let mut executor = Executor::new(self.library, transport.clone());
+            if let Some(dir) = &self.log_dir {
+                executor = executor.with_run_log(&run_log_path(dir, &run.request.id))?;
+            }
+            executor.parameters = run.workflow.parameters.clone();
+            executor.emit(
+                &run.request.id,
+                &run.request.id,
+                Duration::ZERO,
+                EventKind::RunStarted {
+                    steps: run
+                        .workflow
+                        .workflow
+                        .iter()
+                        .map(|s| s.name.clone())
+                        .collect(),
+                },
+            );
+            executors.push(executor);
+        }
+        let mut reports = self
+            .runs
+            .iter()
+            .map(|run| RunReport {
+                title: run.request.id.clone(),
+                steps: Vec::new(),
+                cleanup: None,
+                blackboard: Default::default(),
+                elapsed: Duration::ZERO,
+            })
+            .collect::<Vec<RunReport>>();
+        let mut next_step = vec![0; self.runs.len()];
+        // Runs with a step in flight, or that stopped after a failure
+        let mut blocked = vec![false; self.runs.len()];
+        let mut held = HashSet::new();
+        let mut running = FuturesUnordered::new();
+        let start = Instant::now();
+
+        loop {
+            for queued in select(self.queue(&next_step, &blocked)?, &held) {
+                let run = queued.run;
+                let step_index = next_step[run];
+                let tree = self.tree(run, step_index)?;
+                info!(
+                    "Run {}: starting step {} {} at +{:.1}s",
+                    self.runs[run].request.id,
+                    step_index + 1,
+                    tree.tree.name,
+                    start.elapsed().as_secs_f64()
+                );
+                held.extend(queued.modules.iter().cloned());
+                blocked[run] = true;
+                let executor = &executors[run];
+                running.push(async move {
+                    let step_start = Instant::now();
+                    let result = executor.execute_tree_step(step_index, tree).await;
+                    (
+                        run,
+                        step_index,
+                        queued.modules,
+                        result,
+                        step_start.elapsed(),
+                    )
+                });
+            }
+
+            let (run, step_index, modules, result, elapsed) = match running.next().await {
+                Some(finished) => finished,
+                None => break,
+            };
+            for module in &modules {
+                held.remove(module);
+            }
+            let status = match result {
+                Ok(()) => {
+                    blocked[run] = false;
+                    StepStatus::Success
+                }
+                Err(e) => {
+                    warn!("Run {} failed: {}", self.runs[run].request.id, e);
+                    StepStatus::Failure(e.to_string())
+                }
+            };
+            reports[run].steps.push(StepReport {
+                index: step_index,
+                name: self.runs[run].workflow.workflow[step_index].name.clone(),
+                status,
+                elapsed,
+            });
+            reports[run].elapsed = start.elapsed();
+            reports[run].blackboard = executors[run].blackboard();
+            next_step[run] += 1;
+        }
+
+        for (run, report) in reports.iter_mut().enumerate() {
+            for (index, step) in self.runs[run].workflow.workflow.iter().enumerate() {
+                if index >= next_step[run] {
+                    report.steps.push(StepReport {
+                        index,
+                        name: step.name.clone(),
+                        status: StepStatus::Skipped,
+                        elapsed: Duration::ZERO,
+                    });
+                }
+            }
+            executors[run].emit(
+                &report.title,
+                &report.title,
+                report.elapsed,
+                EventKind::RunFinished {
+                    success: report.succeeded(),
+                },
+            );
+        }
+        Ok(reports)
+    }
+}
+
+/// Pick the queued steps that can start now
+///
+/// Steps are considered by priority. A module wanted by a step that has to wait is
+/// reserved for it, so steps with a lower priority cannot keep it busy.
+fn select(queue: Vec<Queued>, held: &HashSet<String>) -> Vec<Queued> {
+    let mut taken = held.clone();
+    let mut reserved = HashSet::new();
+    let mut selected = Vec::new();
+    for queued in queue {
+        let free = queued
+            .modules
+            .iter()
+            .all(|m| !taken.contains(m) && !reserved.contains(m));
+        if free {
+            taken.extend(queued.modules.iter().cloned());
+            selected.push(queued);
+        } else {
+            debug!("Run #{} waits for {:?}", queued.run, queued.modules);
+            reserved.extend(queued.modules);
+        }
+    }
+    selected
+}
+
+/// Worst case duration of a tree from the timeouts of the services it calls
+///
+/// Fallbacks are assumed to succeed on their first child.
+pub fn estimate_from_timeouts(tree: &BehaviorTreeFile, library: &Library) -> Duration {
+    node_timeout(&tree.tree, library)
+}
+
+fn node_timeout(node: &Node, library: &Library) -> Duration {
+    match &node.sequence {
+        Some(Sequence::Children(children)) => {
+            children.iter().map(|c| node_timeout(c, library)).sum()
+        }
+        Some(Sequence::Fallback(children)) => children
+            .first()
+            .map_or(Duration::ZERO, |c| node_timeout(c, library)),
+        Some(Sequence::Parallel { children, .. }) => children
+            .iter()
+            .map(|c| node_timeout(c, library))
+            .max()
+            .unwrap_or(Duration::ZERO),
+        None => library
+            .nodes
+            .content
+            .get(&node.name)
+            .and_then(|n| n.zenoh.as_ref())
+            .map(|zenoh| {
+                zenoh
+                    .modules
+                    .iter()
+                    .filter_map(|m| service_timeout(library, m, &node.name).ok())
+                    .max()
+                    .unwrap_or(Duration::ZERO)
+            })
+            .unwrap_or(Duration::ZERO),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reply::ModuleRequest;
+    use std::sync::{Arc, Mutex};
+
+    /// Answers every request, recording it as `module/service` in `sent`
+    fn recorded(sent: &Arc<Mutex<Vec<String>>>) -> Transport {
+        let sent = sent.clone();
+        Arc::new(move |request: ModuleRequest| {
+            let request = format!("{}/{}", request.module, request.service);
+            sent.lock().unwrap().push(request);
+            Box::pin(async { Ok(vec![]) })
+        })
+    }
+
+    /// `load` holds the gantry, `spin` the centrifuge, `prep` runs both and `top` only `load`
+    fn library() -> Library {
+        let tree = |name: &str, node: &str, module: &str| -> BehaviorTreeFile {
+            serde_yaml::from_str(&format!(
+                "{{title: t, version: 0.1.0, description: d, participant_modules: [{}], \
+                 tree: {{name: {}, step_number: 1, sequence: !children [{{name: {}, step_number: 1}}]}}}}",
+                module, name, node
+            ))
+            .unwrap()
+        };
+        let workflow = |title: &str, steps: &str| -> WorkflowFile {
+            serde_yaml::from_str(&format!(
+                "{{title: {}, description: d, version: 0.1.0, parameters: [], process_tldr: p, \
+                 workflow: {}}}",
+                title, steps
+            ))
+            .unwrap()
+        };
+        Library {
+            nodes: serde_yaml::from_str(
+                "{title: n, description: d, version: 0.1.0, content: {\
+                 grab: {type: action, description: d, zenoh: {modules: [gantry], min_reply: all}}, \
+                 spin: {type: action, description: d, zenoh: {modules: [centrifuge], min_reply: all}}}}",
+            )
+            .unwrap(),
+            trees: vec![
+                tree("load", "grab", "gantry"),
+                tree("spin", "spin", "centrifuge"),
+            ],
+            workflows: vec![
+                workflow("prep", "[{name: load, why: w}, {name: spin, why: w}]"),
+                workflow("top", "[{name: load, why: w}]"),
+            ],
+            ..Default::default()
+        }
+    }
+
+    fn scheduler(library: &Library) -> Scheduler<'_> {
+        let mut scheduler = Scheduler::new(library);
+        scheduler.estimate = |_, _| Duration::from_secs(10);
+        for (id, workflow, priority) in [("a", "prep", i32::MIN), ("b", "top", 5), ("c", "top", 0)]
+        {
+            let request = RunRequest {
+                id: id.to_string(),
+                workflow: workflow.to_string(),
+                priority,
+            };
+            scheduler.submit(request).unwrap();
+        }
+        scheduler
+    }
+
+    fn queued(run: usize, priority: i32, modules: &[&str]) -> Queued {
+        Queued {
+            run,
+            priority,
+            modules: modules.iter().map(|m| m.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn waiting_steps_reserve_their_modules() {
+        let held = HashSet::from(["gantry".to_string()]);
+        let queue = vec![
+            queued(0, 5, &["gantry", "centrifuge"]),
+            queued(1, 1, &["centrifuge"]),
+            queued(2, 1, &["slider"]),
+        ];
+        let selected = select(queue, &held)
+            .into_iter()
+            .map(|q| q.run)
+            .collect::<Vec<usize>>();
+        assert_eq!(selected, vec![2]);
+    }
+
+    #[test]
+    fn steps_without_common_modules_start_together() {
+        let queue = vec![
+            queued(0, 0, &["gantry"]),
+            queued(1, 0, &["centrifuge"]),
+            queued(2, 0, &["gantry"]),
+        ];
+        let selected = select(queue, &HashSet::new())
+            .into_iter()
+            .map(|q| q.run)
+            .collect::<Vec<usize>>();
+        assert_eq!(selected, vec![0, 1]);
+    }
+
+    #[test]
+    fn runs_that_change_tools_are_rejected() {
+        let mut library = library();
+        library.tools = serde_yaml::from_str(
+            "{name: Tools, version: 0.1.0, content: {gripper: {name: Gripper, description: g}}}",
+        )
+        .unwrap();
+        library.nodes.content.get_mut("grab").unwrap().needs_tool = Some("gripper".into());
+        let mut scheduler = Scheduler::new(&library);
+        let request = RunRequest {
+            id: "a".to_string(),
+            workflow: "top".to_string(),
+            priority: 0,
+        };
+        assert!(scheduler.submit(request).is_err());
+    }
+
+    #[test]
+    fn modules_are_handed_on_by_priority() {
+        let library = library();
+        let scheduler = scheduler(&library);
+        let planned = scheduler
+            .plan()
+            .unwrap()
+            .into_iter()
+            .map(|p| (p.run, p.tree, p.start.as_secs()))
+            .collect::<Vec<_>>();
+        let expected = [
+            ("b", "load", 0),
+            ("c", "load", 10),
+            ("a", "load", 20),
+            ("a", "spin", 30),
+        ];
+        assert_eq!(
+            planned,
+            expected
+                .iter()
+                .map(|(run, tree, start)| (run.to_string(), tree.to_string(), *start))
+                .collect::<Vec<_>>()
+        );
+
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let reports = async_std::task::block_on(scheduler.execute(recorded(&sent))).unwrap();
+        assert!(reports.iter().all(|r| r.succeeded()));
+        assert_eq!(reports[0].steps.len(), 2);
+        let sent = sent.lock().unwrap().clone();
+        assert_eq!(
+            sent,
+            [
+                "gantry/grab",
+                "gantry/grab",
+                "gantry/grab",
+                "centrifuge/spin"
+            ]
+        );
+    }
+}
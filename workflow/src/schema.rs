@@ -0,0 +1,387 @@
+use crate::conf::{
+    BehaviorTreeFile, BlackboardValue, ExpectedDuration, KnownNodesFile, Library, ModuleFile,
+    NodeType, ToolFile, WorkflowFile,
+};
+use log::debug;
+use schemars::{schema_for, JsonSchema};
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Schema of every kind of library file, with the files of the library it applies to, without
+/// their extension
+pub const LIBRARY_FILES: [(&str, &[&str]); 5] = [
+    ("modules", &["/modules", "/modules.*"]),
+    ("tools", &["/tools", "/tools.*"]),
+    ("nodes", &["/nodes", "/nodes.*"]),
+    ("trees", &["/trees/*"]),
+    ("workflows", &["/workflows/*"]),
+];
+
+/// VS Code extension that validates YAML files against the schemas in `yaml.schemas`
+const YAML_EXTENSION: &str = "redhat.vscode-yaml";
+
+pub(crate) fn example_timeout() -> &'static str {
+    "10s"
+}
+
+pub(crate) fn example_rule() -> &'static str {
+    "volume >= 10 && volume < 100"
+}
+
+pub(crate) fn example_expression() -> &'static str {
+    "tip_slot >= 0"
+}
+
+pub(crate) fn example_duration() -> ExpectedDuration {
+    ExpectedDuration {
+        expected: "4s".to_string(),
+        min: Some("3s".to_string()),
+        max: Some("8s".to_string()),
+    }
+}
+
+pub(crate) fn example_consumes() -> BTreeMap<String, f64> {
+    BTreeMap::from([("tip".to_string(), 1.0)])
+}
+
+pub(crate) fn example_preconditions() -> Vec<&'static str> {
+    vec!["tip attached"]
+}
+
+pub(crate) fn example_postconditions() -> Vec<&'static str> {
+    vec!["!tip attached"]
+}
+
+pub(crate) fn example_backoff() -> &'static str {
+    "500ms"
+}
+
+pub(crate) fn example_parameters() -> BTreeMap<String, BlackboardValue> {
+    BTreeMap::from([("volume".to_string(), BlackboardValue::Integer(50))])
+}
+
+/// Path of the schema of the `name` files in `schema_dir`
+pub fn schema_path(schema_dir: &Path, name: &str) -> PathBuf {
+    schema_dir.join(format!("{}_schema.json", name))
+}
+
+/// Restrict the string, or optional string, at `pointer` of `schema` to `names`
+fn set_enum(schema: &mut Value, pointer: &str, names: &[&String]) -> Result<(), Box<dyn Error>> {
+    let field = schema
+        .pointer_mut(pointer)
+        .and_then(Value::as_object_mut)
+        .ok_or(format!("The schema has no {}", pointer))?;
+    let mut values = names.iter().map(|n| json!(n)).collect::<Vec<_>>();
+    if field.get("type") == Some(&json!(["string", "null"])) {
+        values.push(Value::Null);
+    }
+    field.insert("enum".to_string(), Value::Array(values));
+    Ok(())
+}
+
+/// Names `library` defines of each kind, sorted
+struct Names<'a> {
+    modules: Vec<&'a String>,
+    /// Modules and tools, what `zenoh.modules` may call
+    dependencies: Vec<&'a String>,
+    tools: Vec<&'a String>,
+    /// Nodes of the nodes file that can be leaves of a tree
+    leaves: Vec<&'a String>,
+    errors: Vec<&'a String>,
+    trees: Vec<&'a String>,
+}
+
+impl<'a> Names<'a> {
+    fn new(library: &'a Library) -> Self {
+        let sorted = |names: Vec<&'a String>| {
+            let mut names = names;
+            names.sort();
+            names.dedup();
+            names
+        };
+        let nodes = |error: bool| {
+            library
+                .nodes
+                .content
+                .iter()
+                .filter(|(_, node)| (node.node_type == NodeType::Error) == error)
+                .map(|(name, _)| name)
+                .collect()
+        };
+        Names {
+            modules: sorted(library.modules.content.keys().collect()),
+            dependencies: sorted(
+                library
+                    .modules
+                    .content
+                    .keys()
+                    .chain(library.tools.content.keys())
+                    .collect(),
+            ),
+            tools: sorted(library.tools.content.keys().collect()),
+            leaves: sorted(nodes(false)),
+            errors: sorted(nodes(true)),
+            trees: sorted(library.trees.iter().map(|t| &t.tree.name).collect()),
+        }
+    }
+}
+
+/// Turn the fields of the `name` files that refer to other parts of `library` into enums of
+/// the names it defines
+fn restrict_to_library(
+    name: &str,
+    schema: &mut Value,
+    library: &Library,
+) -> Result<(), Box<dyn Error>> {
+    let names = Names::new(library);
+    match name {
+        "tools" => set_enum(
+            schema,
+            "/definitions/Tool/properties/pick_up",
+            &names.leaves,
+        )?,
+        "nodes" => {
+            let zenoh_modules = "/definitions/Zenoh/properties/modules/items";
+            set_enum(schema, zenoh_modules, &names.dependencies)?;
+            let needs_tool = "/definitions/KnownNode/properties/needs_tool";
+            set_enum(schema, needs_tool, &names.tools)?;
+            let recovery = "/definitions/ErrorInfo/properties/recovery";
+            set_enum(schema, recovery, &names.trees)?;
+        }
+        "trees" => {
+            let participants = "/properties/participant_modules/items";
+            set_enum(schema, participants, &names.modules)?;
+            set_enum(schema, "/definitions/Node/properties/error", &names.errors)?;
+            // Only leaves, the nodes without a sequence, have to be known nodes
+            let node = schema
+                .pointer_mut("/definitions/Node")
+                .and_then(Value::as_object_mut)
+                .ok_or("The schema has no /definitions/Node")?;
+            node.insert(
+                "if".to_string(),
+                json!({"properties": {"sequence": {"type": "null"}}}),
+            );
+            node.insert(
+                "then".to_string(),
+                json!({"properties": {"name": {"enum": names.leaves}}}),
+            );
+        }
+        "workflows" => {
+            set_enum(
+                schema,
+                "/definitions/WorkflowStep/properties/name",
+                &names.trees,
+            )?;
+            set_enum(schema, "/properties/cleanup", &names.trees)?;
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Write the schema of the `name` files, restricted to the names `library` defines if given
+pub fn file_type_to_schema<T: JsonSchema>(
+    name: &str,
+    schema_dir: &Path,
+    library: Option<&Library>,
+) -> Result<PathBuf, Box<dyn Error>> {
+    let mut file_schema = serde_json::to_value(schema_for!(T))?;
+    if let Some(library) = library {
+        restrict_to_library(name, &mut file_schema, library)?;
+    }
+    let path = schema_path(schema_dir, name);
+    fs::write(&path, serde_json::to_string_pretty(&file_schema)?)?;
+    debug!("{} schema written to {:?}", name, path);
+    Ok(path)
+}
+
+/// Write the schemas of the library files to `schema_dir`, creating it if needed
+///
+/// With a `library`, the fields naming nodes, trees, modules and tools only accept the ones it
+/// defines. Generate them again when the library changes.
+pub fn generate_json_schemas(
+    schema_dir: &Path,
+    library: Option<&Library>,
+) -> Result<(), Box<dyn Error>> {
+    fs::create_dir_all(schema_dir)
+        .map_err(|e| format!("Cannot create {}: {}", schema_dir.display(), e))?;
+
+    // Create the schema json files for each, modules, nodes, trees, worflows, etc.
+    file_type_to_schema::<ModuleFile>("modules", schema_dir, library)?;
+    file_type_to_schema::<ToolFile>("tools", schema_dir, library)?;
+    file_type_to_schema::<KnownNodesFile>("nodes", schema_dir, library)?;
+    file_type_to_schema::<BehaviorTreeFile>("trees", schema_dir, library)?;
+    file_type_to_schema::<WorkflowFile>("workflows", schema_dir, library)?;
+
+    Ok(())
+}
+
+/// Read a JSON object from `path`, an empty one if the file does not exist
+fn read_object(path: &Path) -> Result<Map<String, Value>, Box<dyn Error>> {
+    if !path.exists() {
+        return Ok(Map::new());
+    }
+    let text = fs::read_to_string(path)?;
+    match serde_json::from_str(&text) {
+        Ok(Value::Object(object)) => Ok(object),
+        _ => Err(format!(
+            "Cannot update {}, it is not a plain JSON object (comments are not supported)",
+            path.display()
+        )
+        .into()),
+    }
+}
+
+/// Map the library files to the schemas in `schema_dir` in `.vscode/settings.json` of the
+/// library, so the YAML extension validates and completes them. Other settings are kept.
+pub fn write_editor_settings(
+    library_path: &Path,
+    schema_dir: &Path,
+) -> Result<PathBuf, Box<dyn Error>> {
+    let vscode_dir = library_path.join(".vscode");
+    fs::create_dir_all(&vscode_dir)?;
+
+    let settings_path = vscode_dir.join("settings.json");
+    let mut settings = read_object(&settings_path)?;
+    // Both paths in full, a relative schema directory would otherwise be written relative to
+    // the current directory
+    let canonical = |path: &Path| {
+        fs::canonicalize(path).map_err(|e| format!("Cannot resolve {}: {}", path.display(), e))
+    };
+    let library_path = canonical(library_path)?;
+    let schema_dir = canonical(schema_dir)?;
+    let mut mapping = Vec::new();
+    for (name, patterns) in LIBRARY_FILES {
+        let path = schema_path(&schema_dir, name);
+        // Relative to the library, the folder opened in the editor
+        let path = match path.strip_prefix(&library_path) {
+            Ok(relative) => format!("./{}", relative.display()),
+            Err(_) => path.display().to_string(),
+        };
+        let globs = |extensions: &[&str]| {
+            patterns
+                .iter()
+                .flat_map(|p| extensions.iter().map(move |e| format!("{}.{}", p, e)))
+                .collect::<Vec<_>>()
+        };
+        mapping.push((path, globs(&["yaml", "yml"]), globs(&["json"])));
+    }
+
+    let yaml_schemas = settings
+        .entry("yaml.schemas")
+        .or_insert_with(|| json!({}))
+        .as_object_mut()
+        .ok_or("yaml.schemas is not an object")?;
+    for (path, yaml_globs, _) in &mapping {
+        yaml_schemas.insert(path.clone(), json!(yaml_globs));
+    }
+    // JSON files are checked by the editor itself
+    let json_schemas = settings
+        .entry("json.schemas")
+        .or_insert_with(|| json!([]))
+        .as_array_mut()
+        .ok_or("json.schemas is not an array")?;
+    for (path, _, json_globs) in &mapping {
+        json_schemas.retain(|s| s["url"] != json!(path));
+        json_schemas.push(json!({"fileMatch": json_globs, "url": path}));
+    }
+    fs::write(&settings_path, serde_json::to_string_pretty(&settings)?)?;
+
+    let extensions_path = vscode_dir.join("extensions.json");
+    let mut extensions = read_object(&extensions_path)?;
+    let recommendations = extensions
+        .entry("recommendations")
+        .or_insert_with(|| json!([]))
+        .as_array_mut()
+        .ok_or("recommendations is not an array")?;
+    if !recommendations.contains(&json!(YAML_EXTENSION)) {
+        recommendations.push(json!(YAML_EXTENSION));
+    }
+    fs::write(&extensions_path, serde_json::to_string_pretty(&extensions)?)?;
+
+    Ok(settings_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn schemas_are_mapped_to_the_library_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let library_path = dir.path().join("library");
+        let schema_dir = library_path.join("schema");
+        generate_json_schemas(&schema_dir, None).unwrap();
+
+        let nodes: Value =
+            serde_json::from_str(&fs::read_to_string(schema_path(&schema_dir, "nodes")).unwrap())
+                .unwrap();
+        assert_eq!(nodes["title"], "Nodes");
+        let known_node = &nodes["definitions"]["KnownNode"]["properties"];
+        assert_eq!(known_node["expression"]["examples"][0], "tip_slot >= 0");
+
+        fs::create_dir_all(library_path.join(".vscode")).unwrap();
+        fs::write(
+            library_path.join(".vscode/settings.json"),
+            "{\"editor.tabSize\": 2}",
+        )
+        .unwrap();
+        write_editor_settings(&library_path, &schema_dir).unwrap();
+        write_editor_settings(&library_path, &schema_dir).unwrap();
+        let settings = read_object(&library_path.join(".vscode/settings.json")).unwrap();
+        assert_eq!(settings["editor.tabSize"], 2);
+        assert_eq!(
+            settings["yaml.schemas"]["./schema/trees_schema.json"],
+            json!(["/trees/*.yaml", "/trees/*.yml"])
+        );
+        assert_eq!(settings["json.schemas"].as_array().unwrap().len(), 5);
+        assert_eq!(
+            settings["json.schemas"][0]["fileMatch"],
+            json!(["/modules.json", "/modules.*.json"])
+        );
+        let extensions = read_object(&library_path.join(".vscode/extensions.json")).unwrap();
+        assert_eq!(extensions["recommendations"], json!([YAML_EXTENSION]));
+
+        // The same library reached through another path
+        #[cfg(unix)]
+        {
+            let link = dir.path().join("link");
+            std::os::unix::fs::symlink(&library_path, &link).unwrap();
+            write_editor_settings(&link, &schema_dir).unwrap();
+            let settings = read_object(&library_path.join(".vscode/settings.json")).unwrap();
+            assert_eq!(settings["yaml.schemas"].as_object().unwrap().len(), 5);
+        }
+    }
+
+    #[test]
+    fn library_schemas_only_accept_known_names() {
+        let library = Library {
+            nodes: serde_yaml::from_str(
+                "{title: n, description: d, version: 0.1.0, content: {\
+                 spin: {type: action, description: d}, \
+                 no_tip: {type: error, description: d, error: {class: consumable, message: m}}}}",
+            )
+            .unwrap(),
+            trees: vec![serde_yaml::from_str(
+                "{title: t, version: 0.1.0, description: d, participant_modules: [], \
+                 tree: {name: spin_down, step_number: 0, sequence: !children [\
+                 {name: spin, step_number: 1}]}}",
+            )
+            .unwrap()],
+            ..Default::default()
+        };
+        let mut trees = serde_json::to_value(schema_for!(BehaviorTreeFile)).unwrap();
+        restrict_to_library("trees", &mut trees, &library).unwrap();
+        let node = &trees["definitions"]["Node"];
+        assert_eq!(node["then"]["properties"]["name"]["enum"], json!(["spin"]));
+        assert_eq!(node["properties"]["error"]["enum"], json!(["no_tip", null]));
+
+        let mut workflows = serde_json::to_value(schema_for!(WorkflowFile)).unwrap();
+        restrict_to_library("workflows", &mut workflows, &library).unwrap();
+        let step = &workflows["definitions"]["WorkflowStep"];
+        assert_eq!(step["properties"]["name"]["enum"], json!(["spin_down"]));
+    }
+}
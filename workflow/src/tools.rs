@@ -0,0 +1,244 @@
+use crate::blackboard::{Blackboard, Expression};
+use crate::conf::{
+    get_tree_by_name, BlackboardKey, BlackboardValue, Library, Node, Tool, Value, Variant,
+    WorkflowStep,
+};
+use log::{debug, trace};
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+
+/// Blackboard key holding the abbreviation of the variant of `tool` in use
+pub fn variant_key(tool: &str) -> String {
+    format!("{}_variant", tool)
+}
+
+/// Tool to mount before a step, with the variant picked for it
+#[derive(Debug, Clone, PartialEq)]
+pub struct ToolChange {
+    pub step_index: usize,
+    /// Key of the tool in the tools file
+    pub tool: String,
+    /// Abbreviation of the variant, `None` for tools without variants
+    pub variant: Option<String>,
+    /// Node run to mount the tool, `None` if it has to be mounted by hand
+    pub pick_up: Option<String>,
+}
+
+/// Values the variant rules of a step are evaluated against: the run parameters, overridden by
+/// the parameters of the step
+pub fn step_values(
+    parameters: &[Value],
+    step: &WorkflowStep,
+) -> Result<Blackboard, Box<dyn Error>> {
+    let mut values = parameters
+        .iter()
+        .map(|p| (p.schema.name.clone(), BlackboardValue::Number(p.value)))
+        .collect::<BTreeMap<_, _>>();
+    values.extend(step.parameters.clone());
+    let keys = values
+        .into_iter()
+        .map(|(name, value)| {
+            let key = BlackboardKey {
+                key_type: value.key_type(),
+                description: String::new(),
+                unit: None,
+                default: Some(value),
+            };
+            (name, key)
+        })
+        .collect();
+    let mut blackboard = Blackboard::default();
+    blackboard.declare(&keys)?;
+    Ok(blackboard)
+}
+
+/// The first variant of `tool` whose `preffered_when` rule holds for `values`
+pub fn select_variant<'a>(
+    tool: &'a Tool,
+    values: &Blackboard,
+) -> Result<Option<&'a Variant>, Box<dyn Error>> {
+    let variants = match &tool.variants {
+        Some(variants) if !variants.is_empty() => variants,
+        _ => return Ok(None),
+    };
+    for variant in variants {
+        let rule = Expression::parse(&variant.preffered_when)?;
+        // A rule about a parameter the step does not set, e.g. the liquid class, does not apply
+        if let Some(key) = rule.keys().into_iter().find(|k| values.get(k).is_none()) {
+            trace!("{} {}: {} is not set", tool.name, variant.abbr, key);
+            continue;
+        }
+        let preferred = rule
+            .evaluate(values)
+            .map_err(|e| format!("Cannot choose a {}: {}", tool.name, e))?;
+        trace!("{} {}: {}", tool.name, variant.abbr, preferred);
+        if preferred {
+            return Ok(Some(variant));
+        }
+    }
+    Err(format!(
+        "No variant of {} is preferred for {:?}",
+        tool.name,
+        values.values()
+    )
+    .into())
+}
+
+/// Tools needed by the leaves under `node`
+fn needed_tools(node: &Node, library: &Library, tools: &mut BTreeSet<String>) {
+    match &node.sequence {
+        Some(sequence) => {
+            for child in sequence.nodes() {
+                needed_tools(child, library, tools);
+            }
+        }
+        None => {
+            if let Some(tool) = library
+                .nodes
+                .content
+                .get(&node.name)
+                .and_then(|n| n.needs_tool.as_ref())
+            {
+                tools.insert(tool.clone());
+            }
+        }
+    }
+}
+
+/// The tool changes needed to run `steps` in order
+///
+/// A tool is mounted before the first step that needs it, and mounted again before a later
+/// step that prefers another variant.
+pub fn plan_tool_changes(
+    steps: &[WorkflowStep],
+    parameters: &[Value],
+    library: &Library,
+) -> Result<Vec<ToolChange>, Box<dyn Error>> {
+    let mut mounted: BTreeMap<String, Option<String>> = BTreeMap::new();
+    let mut changes = Vec::new();
+    for (step_index, step) in steps.iter().enumerate() {
+        let tree = get_tree_by_name(&step.name, library)?;
+        let mut tools = BTreeSet::new();
+        needed_tools(&tree.tree, library, &mut tools);
+        if tools.is_empty() {
+            continue;
+        }
+        let values = step_values(parameters, step)?;
+        for abbr in tools {
+            let tool = library
+                .tools
+                .content
+                .get(&abbr)
+                .ok_or(format!("Tool {} is not a known tool", abbr))?;
+            let variant = select_variant(tool, &values)
+                .map_err(|e| format!("Step {} {}: {}", step_index + 1, step.name, e))?
+                .map(|v| v.abbr.clone());
+            if mounted.get(&abbr) == Some(&variant) {
+                continue;
+            }
+            debug!(
+                "Step {} {} needs {} {:?}",
+                step_index + 1,
+                step.name,
+                abbr,
+                variant
+            );
+            mounted.insert(abbr.clone(), variant.clone());
+            changes.push(ToolChange {
+                step_index,
+                tool: abbr,
+                variant,
+                pick_up: tool.pick_up.clone(),
+            });
+        }
+    }
+    Ok(changes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conf::{BehaviorTreeFile, KnownNode, NodeType, Sequence};
+
+    fn library() -> Library {
+        let mut library = Library::default();
+        library.nodes.content.insert(
+            "aspirate".to_string(),
+            KnownNode {
+                node_type: NodeType::Action,
+                zenoh: None,
+                description: String::new(),
+                reads: Vec::new(),
+                writes: Vec::new(),
+                expression: None,
+                duration: None,
+                consumes: BTreeMap::new(),
+                error: None,
+                preconditions: Vec::new(),
+                postconditions: Vec::new(),
+                needs_tool: Some("pipette".to_string()),
+            },
+        );
+        library.tools = serde_yaml::from_str(
+            "{name: Tools, version: 0.1.0, content: {pipette: {name: Pipette, description: p, \
+             pick_up: pick_pipette, variants: [\
+             {name: P10, abbr: p10, preffered_when: 'volume < 10'}, \
+             {name: P1000 viscous, abbr: p1000v, preffered_when: \"liquid_class == 'viscous'\"}, \
+             {name: P1000, abbr: p1000, preffered_when: 'volume >= 10 && volume <= 1000'}]}}}",
+        )
+        .unwrap();
+        let mut tree = serde_yaml::from_str::<BehaviorTreeFile>(
+            "{title: t, version: 0.1.0, description: d, participant_modules: [], \
+             tree: {name: transfer, step_number: 0, sequence: null, error: null}}",
+        )
+        .unwrap();
+        tree.tree.sequence = Some(Sequence::Children(vec![Node {
+            name: "aspirate".to_string(),
+            step_number: 0,
+            sequence: None,
+            error: None,
+            decorators: Vec::new(),
+        }]));
+        library.trees.push(tree);
+        library
+    }
+
+    fn step(parameters: &[(&str, BlackboardValue)]) -> WorkflowStep {
+        WorkflowStep {
+            name: "transfer".to_string(),
+            why: String::new(),
+            parameters: parameters
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.clone()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn changes_tools_when_another_variant_is_preferred() {
+        let library = library();
+        let steps = vec![
+            step(&[("volume", BlackboardValue::Number(5.0))]),
+            step(&[("volume", BlackboardValue::Number(2.0))]),
+            step(&[("volume", BlackboardValue::Integer(200))]),
+            step(&[
+                ("volume", BlackboardValue::Integer(200)),
+                (
+                    "liquid_class",
+                    BlackboardValue::String("viscous".to_string()),
+                ),
+            ]),
+        ];
+        let changes = plan_tool_changes(&steps, &[], &library).unwrap();
+        let variants = changes
+            .iter()
+            .map(|c| (c.step_index, c.variant.as_deref().unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(variants, vec![(0, "p10"), (2, "p1000"), (3, "p1000v")]);
+        assert_eq!(changes[0].pick_up.as_deref(), Some("pick_pipette"));
+
+        assert!(plan_tool_changes(&[step(&[])], &[], &library).is_err());
+        let too_much = step(&[("volume", BlackboardValue::Number(5000.0))]);
+        assert!(plan_tool_changes(&[too_much], &[], &library).is_err());
+    }
+}